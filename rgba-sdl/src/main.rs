@@ -7,17 +7,25 @@
 //           By: Louise <ludwigette>
 //
 mod sdl;
+mod rewind;
 
 use clap::builder::PossibleValue;
 use clap::{Arg, Command};
+use log::{error, info, warn};
+use std::fs;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 use sdl::SDLPlatform;
+use rewind::{Rewind, ReplayPlatform};
 
-use rgba_builder::ConsoleBuilder;
+use rgba_builder::{Console, ConsoleBuilder, Model};
 use rgba_common::{ConsoleType, Core, Event};
 
+// A snapshot every 4 frames, 10 seconds of rewind
+const REWIND_INTERVAL: u32 = 4;
+const REWIND_CAPACITY: usize = 150;
+
 fn main() {
     let matches = Command::new("rgba")
         .version(env!("CARGO_PKG_VERSION"))
@@ -65,6 +73,59 @@ fn main() {
                 ])
                 .required(false),
         )
+        .arg(
+            Arg::new("gdb")
+                .short('g')
+                .long("gdb")
+                .value_name("PORT")
+                .value_parser(clap::value_parser!(u16))
+                .help("Waits for GDB on the given local port (GBA only)")
+                .required(false),
+        )
+        .arg(
+            Arg::new("link")
+                .long("link")
+                .value_name("PORT")
+                .value_parser(clap::value_parser!(u16))
+                .help("Links to another instance over the given local port (Gameboy only)")
+                .required(false),
+        )
+        .arg(
+            Arg::new("infrared")
+                .long("infrared")
+                .value_name("PORT")
+                .value_parser(clap::value_parser!(u16))
+                .help("Links the infrared port of HuC1/HuC3 cartridges to another instance over the given local port (Gameboy only)")
+                .required(false),
+        )
+        .arg(
+            Arg::new("printer")
+                .long("printer")
+                .value_name("DIR")
+                .help("Plugs a Game Boy Printer saving its prints in the given directory (Gameboy only)")
+                .required(false),
+        )
+        .arg(
+            Arg::new("camera")
+                .long("camera")
+                .value_name("PATH")
+                .help("Image, or directory of images, seen by the Pocket Camera (Gameboy only)")
+                .required(false),
+        )
+        .arg(
+            Arg::new("model")
+                .short('m')
+                .long("model")
+                .num_args(1)
+                .value_parser([
+                    PossibleValue::new("dmg"),
+                    PossibleValue::new("mgb"),
+                    PossibleValue::new("cgb"),
+                    PossibleValue::new("agb"),
+                ])
+                .help("Sets the Gameboy model to emulate without a bootrom")
+                .required(false),
+        )
         .get_matches();
 
     let rom_name = matches.get_one::<String>("ROM").unwrap();
@@ -92,7 +153,41 @@ fn main() {
         .load_bios(bios_name.map(String::as_str))
         .load_rom(rom_name);
 
-    let mut console = match matches.get_one::<String>("console").map(String::as_str) {
+    let console = match matches.get_one::<String>("model").map(String::as_str) {
+        Some("dmg") => console.set_gb_model(Model::DMG),
+        Some("mgb") => console.set_gb_model(Model::MGB),
+        Some("cgb") => console.set_gb_model(Model::CGB),
+        Some("agb") => console.set_gb_model(Model::AGB),
+        None => console,
+        _ => unreachable!(),
+    };
+
+    let console = match matches.get_one::<u16>("gdb") {
+        Some(&port) => console.set_gdb_port(port),
+        None => console,
+    };
+
+    let console = match matches.get_one::<u16>("link") {
+        Some(&port) => console.set_link_port(port),
+        None => console,
+    };
+
+    let console = match matches.get_one::<u16>("infrared") {
+        Some(&port) => console.set_infrared_port(port),
+        None => console,
+    };
+
+    let console = match matches.get_one::<String>("printer") {
+        Some(dir) => console.set_printer_dir(dir),
+        None => console,
+    };
+
+    let console = match matches.get_one::<String>("camera") {
+        Some(path) => console.set_camera_images(path),
+        None => console,
+    };
+
+    let console = match matches.get_one::<String>("console").map(String::as_str) {
         Some("gb") => console.set_console(ConsoleType::Gameboy),
         Some("gba") => console.set_console(ConsoleType::GBA),
         Some("nes") => console.set_console(ConsoleType::NES),
@@ -100,8 +195,15 @@ fn main() {
         None => console,
         _ => unreachable!(),
     }
-    .build()
-    .unwrap();
+    .build();
+
+    let mut console = match console {
+        Ok(console) => console,
+        Err(e) => {
+            error!("Couldn't start the emulator : {}", e);
+            std::process::exit(1);
+        }
+    };
 
     if debug {
         console.process_event(Event::Debug);
@@ -110,18 +212,59 @@ fn main() {
     let parameters = console.get_platform_parameters();
     let mut platform = SDLPlatform::new(parameters.0, parameters.1, 2);
 
+    let mut rewind = Rewind::new(REWIND_CAPACITY);
+    let mut frames_since_snapshot = REWIND_INTERVAL;
+
     'main_loop: loop {
         let frame_start = Instant::now();
 
         while let Some(event) = platform.poll_event() {
             match event {
                 Event::Quit => break 'main_loop,
+                Event::SaveState(slot) => save_state(&console, rom_name, slot),
+                Event::LoadState(slot) => {
+                    if load_state(&mut console, rom_name, slot) {
+                        // Older snapshots belong to another timeline
+                        rewind.clear();
+                        frames_since_snapshot = REWIND_INTERVAL;
+                    }
+                }
                 _ => console.process_event(event),
             }
         }
 
-        let buffer = console.run_frame(&mut platform);
-        platform.set_buffer(buffer);
+        if platform.is_rewinding() {
+            // Go back one frame by replaying from the last snapshot
+            if frames_since_snapshot > 1 {
+                frames_since_snapshot -= 1;
+            } else if rewind.pop() {
+                frames_since_snapshot = REWIND_INTERVAL;
+            }
+
+            if let Some(state) = rewind.current() {
+                if let Err(e) = console.load_state(state) {
+                    warn!("Couldn't rewind : {}", e);
+                }
+
+                for _ in 1..frames_since_snapshot {
+                    console.run_frame(&mut ReplayPlatform);
+                }
+
+                let buffer = console.run_frame(&mut ReplayPlatform);
+                platform.set_buffer(buffer);
+            }
+        } else {
+            if frames_since_snapshot >= REWIND_INTERVAL {
+                rewind.push(console.save_state());
+                frames_since_snapshot = 0;
+            }
+
+            let buffer = console.run_frame(&mut platform);
+            platform.set_buffer(buffer);
+
+            frames_since_snapshot += 1;
+        }
+
         platform.present();
 
         let time_frame_took = Instant::now() - frame_start;
@@ -132,3 +275,37 @@ fn main() {
         }
     }
 }
+
+fn state_filename(rom_name: &str, slot: u8) -> String {
+    format!("{}.ss{}", rom_name, slot)
+}
+
+fn save_state(console: &Console, rom_name: &str, slot: u8) {
+    let filename = state_filename(rom_name, slot);
+
+    match fs::write(&filename, console.save_state()) {
+        Ok(()) => info!("Saved state to slot {}", slot),
+        Err(e) => warn!("Couldn't write savestate {} : {}", filename, e),
+    }
+}
+
+fn load_state(console: &mut Console, rom_name: &str, slot: u8) -> bool {
+    let filename = state_filename(rom_name, slot);
+
+    match fs::read(&filename) {
+        Ok(state) => match console.load_state(&state) {
+            Ok(()) => {
+                info!("Loaded state from slot {}", slot);
+                true
+            }
+            Err(e) => {
+                warn!("Couldn't load savestate {} : {}", filename, e);
+                false
+            }
+        },
+        Err(e) => {
+            warn!("Couldn't read savestate {} : {}", filename, e);
+            false
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// rewind.rs ---
+//
+// Filename: rewind.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 16:40:27 2026 (+0200)
+// Last-Updated: Sun Oct 18 23:41:12 2026 (+0200)
+//           By: Louise <louise>
+//
+use std::collections::VecDeque;
+
+use rgba_common::Platform;
+
+/// Ring buffer of core snapshots. Only the newest snapshot is kept
+/// whole, the older ones are stored as compressed deltas against the
+/// snapshot that follows them.
+pub struct Rewind {
+    capacity: usize,
+
+    current: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity,
+
+            current: None,
+            deltas: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.current.take() {
+            self.deltas.push_back(encode_delta(&previous, &state));
+
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+
+        self.current = Some(state);
+    }
+
+    #[inline]
+    pub fn current(&self) -> Option<&[u8]> {
+        self.current.as_deref()
+    }
+
+    /// Drops the newest snapshot and rebuilds the one before it.
+    /// Returns false when there is nothing older to go back to.
+    pub fn pop(&mut self) -> bool {
+        match (self.deltas.pop_back(), &self.current) {
+            (Some(delta), Some(current)) => {
+                self.current = Some(decode_delta(current, &delta));
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forgets every snapshot, used when the timeline is replaced.
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+    }
+}
+
+/// Platform used when replaying frames, which drops all output.
+pub struct ReplayPlatform;
+
+impl Platform for ReplayPlatform { }
+
+// The delta is the XOR of both snapshots, run-length encoded as a
+// sequence of (zero run, literal count, literals).
+fn encode_delta(previous: &[u8], next: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    write_varint(&mut out, previous.len());
+
+    while i < previous.len() {
+        let start = i;
+        while i < previous.len() && previous[i] == xor_byte(next, i, 0) {
+            i += 1;
+        }
+        write_varint(&mut out, i - start);
+
+        let start = i;
+        while i < previous.len() && previous[i] != xor_byte(next, i, 0) {
+            i += 1;
+        }
+        write_varint(&mut out, i - start);
+
+        for (j, byte) in previous[start..i].iter().enumerate() {
+            out.push(xor_byte(next, start + j, *byte));
+        }
+    }
+
+    out
+}
+
+fn decode_delta(next: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut offset = 0;
+    let len = read_varint(delta, &mut offset);
+    let mut out = Vec::with_capacity(len);
+
+    while out.len() < len {
+        let zeros = read_varint(delta, &mut offset);
+        for _ in 0..zeros {
+            out.push(xor_byte(next, out.len(), 0));
+        }
+
+        let literals = read_varint(delta, &mut offset);
+        for _ in 0..literals {
+            out.push(xor_byte(next, out.len(), delta[offset]));
+            offset += 1;
+        }
+    }
+
+    out
+}
+
+#[inline]
+fn xor_byte(data: &[u8], index: usize, value: u8) -> u8 {
+    data.get(index).copied().unwrap_or(0) ^ value
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], offset: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = data[*offset];
+        *offset += 1;
+
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+}
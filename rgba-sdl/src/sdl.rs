@@ -6,13 +6,16 @@
 // Last-Updated: Mon Jul  1 12:46:25 2019 (+0200)
 //           By: Louise <ludwigette>
 //
-use rgba_common::{Platform, Key};
+use rgba_common::{Platform, Key, Axis, SAMPLE_RATE};
+
+use std::collections::VecDeque;
 
 use sdl2::EventPump;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::event::Event;
 use sdl2::surface::Surface;
-use sdl2::keyboard::Scancode;
+use sdl2::keyboard::{Scancode, Mod};
+use sdl2::mouse::MouseButton;
 use sdl2::video::Window;
 use sdl2::audio::{AudioSpecDesired, AudioQueue};
 
@@ -31,6 +34,14 @@ pub struct SDLPlatform {
     
     event_pump: EventPump,
     rl: Editor::<()>,
+
+    rewinding: bool,
+    // Events produced along with the last one
+    pending_events: VecDeque<rgba_common::Event>,
+
+    // Rumble motor state, and the one shown in the title
+    rumble: bool,
+    title_rumble: bool,
 }
 
 impl SDLPlatform {
@@ -51,7 +62,7 @@ impl SDLPlatform {
 
         let audio_device = audio_sub.open_queue(None,
                                          &AudioSpecDesired {
-                                             freq: Some(48_100),
+                                             freq: Some(SAMPLE_RATE as i32),
                                              channels: Some(1),
                                              samples: Some(4096)
                                          }
@@ -71,6 +82,12 @@ impl SDLPlatform {
             audio_device,
             event_pump,
             rl,
+
+            rewinding: false,
+            pending_events: VecDeque::new(),
+
+            rumble: false,
+            title_rumble: false,
         }
     }
 
@@ -92,6 +109,13 @@ impl SDLPlatform {
     }
 
     pub fn present(&mut self) {
+        // Games toggle the motor many times per frame, only
+        // update the title once per frame
+        if self.rumble != self.title_rumble {
+            self.title_rumble = self.rumble;
+            self.set_title(if self.rumble { "rGBA (rumble)" } else { "rGBA" }.to_string());
+        }
+
         let rect1 = sdl2::rect::Rect::new(0, 0, self.width, self.height);
         let rect2 = sdl2::rect::Rect::new(0, 0,
                                           self.width * self.scale,
@@ -114,10 +138,32 @@ impl SDLPlatform {
         }
     }
 
+    #[inline]
+    pub fn is_rewinding(&self) -> bool { self.rewinding }
+
+    fn tilt_towards(&mut self, x: i32, y: i32) -> rgba_common::Event {
+        let half_width = (self.width * self.scale / 2) as i32;
+        let half_height = (self.height * self.scale / 2) as i32;
+
+        let axis = |pos: i32, half: i32| {
+            ((pos - half) * i16::MAX as i32 / half).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+        };
+
+        self.pending_events.push_back(
+            rgba_common::Event::Analog(Axis::TiltY, axis(y, half_height))
+        );
+
+        rgba_common::Event::Analog(Axis::TiltX, axis(x, half_width))
+    }
+
     pub fn poll_event(&mut self) -> Option<rgba_common::Event> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Some(event);
+        }
+
         match self.event_pump.poll_event() {
             Some(Event::Quit { .. }) => Some(rgba_common::Event::Quit),
-            Some(Event::KeyDown { scancode: Some(scan), .. }) =>
+            Some(Event::KeyDown { scancode: Some(scan), keymod, .. }) =>
                 match scan {
                     Scancode::F10 =>
                         Some(rgba_common::Event::FastMode),
@@ -125,6 +171,10 @@ impl SDLPlatform {
                         Some(rgba_common::Event::Debug),
                     Scancode::F12 =>
                         Some(rgba_common::Event::Reset),
+                    Scancode::Backspace => {
+                        self.rewinding = true;
+                        None
+                    }
                     Scancode::Q =>
                         Some(rgba_common::Event::KeyDown(Key::A)),
                     Scancode::W =>
@@ -141,10 +191,32 @@ impl SDLPlatform {
                         Some(rgba_common::Event::KeyDown(Key::Right)),
                     Scancode::Left =>
                         Some(rgba_common::Event::KeyDown(Key::Left)),
-                    _ => None
+                    Scancode::A =>
+                        Some(rgba_common::Event::KeyDown(Key::L)),
+                    Scancode::S =>
+                        Some(rgba_common::Event::KeyDown(Key::R)),
+                    Scancode::I =>
+                        Some(rgba_common::Event::Analog(Axis::TiltY, i16::MIN)),
+                    Scancode::K =>
+                        Some(rgba_common::Event::Analog(Axis::TiltY, i16::MAX)),
+                    Scancode::J =>
+                        Some(rgba_common::Event::Analog(Axis::TiltX, i16::MIN)),
+                    Scancode::L =>
+                        Some(rgba_common::Event::Analog(Axis::TiltX, i16::MAX)),
+                    _ => state_slot(scan).map(|slot| {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            rgba_common::Event::SaveState(slot)
+                        } else {
+                            rgba_common::Event::LoadState(slot)
+                        }
+                    })
                 },
             Some(Event::KeyUp { scancode: Some(scan), .. }) =>
                 match scan {
+                    Scancode::Backspace => {
+                        self.rewinding = false;
+                        None
+                    }
                     Scancode::Q =>
                         Some(rgba_common::Event::KeyUp(Key::A)),
                     Scancode::W =>
@@ -161,19 +233,55 @@ impl SDLPlatform {
                         Some(rgba_common::Event::KeyUp(Key::Right)),
                     Scancode::Left =>
                         Some(rgba_common::Event::KeyUp(Key::Left)),
+                    Scancode::A =>
+                        Some(rgba_common::Event::KeyUp(Key::L)),
+                    Scancode::S =>
+                        Some(rgba_common::Event::KeyUp(Key::R)),
+                    Scancode::I | Scancode::K =>
+                        Some(rgba_common::Event::Analog(Axis::TiltY, 0)),
+                    Scancode::J | Scancode::L =>
+                        Some(rgba_common::Event::Analog(Axis::TiltX, 0)),
                     _ => None,
                 },
+            // Dragging the mouse tilts the console towards the cursor
+            Some(Event::MouseMotion { mousestate, x, y, .. }) if mousestate.left() =>
+                Some(self.tilt_towards(x, y)),
+            Some(Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. }) =>
+                Some(self.tilt_towards(x, y)),
+            Some(Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. }) => {
+                self.pending_events.push_back(rgba_common::Event::Analog(Axis::TiltY, 0));
+                Some(rgba_common::Event::Analog(Axis::TiltX, 0))
+            }
             _ => None
         }
     }
 }
 
+/// Savestate slots are mapped on F1 to F8
+fn state_slot(scan: Scancode) -> Option<u8> {
+    match scan {
+        Scancode::F1 => Some(1),
+        Scancode::F2 => Some(2),
+        Scancode::F3 => Some(3),
+        Scancode::F4 => Some(4),
+        Scancode::F5 => Some(5),
+        Scancode::F6 => Some(6),
+        Scancode::F7 => Some(7),
+        Scancode::F8 => Some(8),
+        _ => None,
+    }
+}
+
 impl Platform for SDLPlatform {
     fn queue_samples(&mut self, samples: &[i16]) {
         // TODO: Do something else than panic
         self.audio_device.queue_audio(samples).unwrap();
     }
 
+    fn set_rumble(&mut self, on: bool) {
+        self.rumble = on;
+    }
+
     fn read_line(&mut self, prompt: &str) -> Option<String> {
         if let Ok(s) = self.rl.readline(prompt) {
             self.rl.add_history_entry(&s);
@@ -14,7 +14,8 @@ extern crate rgba_dmg_core;
 extern crate rgba_gba_core;
 
 use rgba_common::{ConsoleType, Core};
-use rgba_dmg_core::Gameboy;
+use rgba_dmg_core::{Gameboy, Printer, TcpInfrared, TcpLink};
+pub use rgba_dmg_core::Model;
 use rgba_gba_core::GBA;
 
 #[derive(Debug, Default)]
@@ -24,6 +25,12 @@ pub struct ConsoleBuilder {
     rom: Option<String>,
 
     console: Option<ConsoleType>,
+    gb_model: Option<Model>,
+    gdb_port: Option<u16>,
+    link_port: Option<u16>,
+    infrared_port: Option<u16>,
+    printer_dir: Option<String>,
+    camera_images: Option<String>,
 }
 
 impl ConsoleBuilder {
@@ -45,7 +52,51 @@ impl ConsoleBuilder {
         self
     }
 
-    pub fn build(mut self) -> Option<Console> {
+    /// Sets the Gameboy model to emulate when there is no bootrom
+    pub fn set_gb_model(mut self, model: Model) -> ConsoleBuilder {
+        self.gb_model = Some(model);
+
+        self
+    }
+
+    /// Waits for GDB on the given local port before running (GBA only)
+    pub fn set_gdb_port(mut self, port: u16) -> ConsoleBuilder {
+        self.gdb_port = Some(port);
+
+        self
+    }
+
+    /// Links the Gameboy to another one over the given local port
+    pub fn set_link_port(mut self, port: u16) -> ConsoleBuilder {
+        self.link_port = Some(port);
+
+        self
+    }
+
+    /// Points the infrared port of HuC1/HuC3 cartridges at another
+    /// Gameboy over the given local port
+    pub fn set_infrared_port(mut self, port: u16) -> ConsoleBuilder {
+        self.infrared_port = Some(port);
+
+        self
+    }
+
+    /// Plugs a Game Boy Printer saving its prints in the given directory
+    pub fn set_printer_dir(mut self, dir: &str) -> ConsoleBuilder {
+        self.printer_dir = Some(dir.to_string());
+
+        self
+    }
+
+    /// Uses an image file or a directory of images as what the
+    /// Pocket Camera sees
+    pub fn set_camera_images(mut self, path: &str) -> ConsoleBuilder {
+        self.camera_images = Some(path.to_string());
+
+        self
+    }
+
+    pub fn build(mut self) -> Result<Console, &'static str> {
         if self.console.is_none() {
             if let Some(ref rom_name) = self.rom {
                 if Gameboy::is_file(rom_name) {
@@ -53,7 +104,7 @@ impl ConsoleBuilder {
                 } else if GBA::is_file(rom_name) {
                     self.console = Some(ConsoleType::GBA);
                 } else {
-                    error!("Couldn't guess what console this ROM is for.")
+                    return Err("Couldn't guess what console this ROM is for");
                 }
             }
         }
@@ -61,13 +112,46 @@ impl ConsoleBuilder {
         match self.console {
             Some(ConsoleType::Gameboy) => {
                 let mut gb = Gameboy::new();
+
+                if let Some(model) = self.gb_model {
+                    gb.set_model(model);
+                }
+
                 let _ = gb.load_bios(self.bios);
 
                 if let Some(file_name) = self.rom {
-                    gb.load_rom(&file_name);
+                    gb.load_rom(&file_name)?;
                 };
 
-                Some(Console::Gameboy(gb))
+                if self.gdb_port.is_some() {
+                    warn!("The GDB stub is only available for the GBA");
+                }
+
+                if let Some(port) = self.link_port {
+                    match TcpLink::open(port) {
+                        Ok(link) => gb.connect_serial(Box::new(link)),
+                        Err(e) => error!("{}", e),
+                    }
+
+                    if self.printer_dir.is_some() {
+                        warn!("The link cable is already plugged in, not using the printer");
+                    }
+                } else if let Some(dir) = self.printer_dir {
+                    gb.connect_serial(Box::new(Printer::new(dir)));
+                }
+
+                if let Some(port) = self.infrared_port {
+                    match TcpInfrared::open(port) {
+                        Ok(infrared) => gb.connect_infrared(Box::new(infrared)),
+                        Err(e) => error!("{}", e),
+                    }
+                }
+
+                if let Some(path) = self.camera_images {
+                    gb.set_camera_images(&path)?;
+                }
+
+                Ok(Console::Gameboy(gb))
             },
 
             Some(ConsoleType::GBA) => {
@@ -75,13 +159,32 @@ impl ConsoleBuilder {
                 let _ = gba.load_bios(self.bios);
 
                 if let Some(file_name) = self.rom {
-                    gba.load_rom(&file_name);
+                    gba.load_rom(&file_name)?;
                 };
 
-                Some(Console::GBA(gba))
+                if let Some(port) = self.gdb_port {
+                    if let Err(e) = gba.listen_gdb(port) {
+                        error!("{}", e);
+                    }
+                }
+
+                if self.link_port.is_some() || self.printer_dir.is_some() {
+                    warn!("The link cable and the printer are only available for the Gameboy");
+                }
+
+                if self.infrared_port.is_some() {
+                    warn!("The infrared link is only available for Gameboy cartridges");
+                }
+
+                if self.camera_images.is_some() {
+                    warn!("The Pocket Camera is only available for the Gameboy");
+                }
+
+                Ok(Console::GBA(gba))
             }
 
-            _ => None,
+            Some(_) => Err("This console is not supported"),
+            None => Err("No console or ROM given"),
         }
     }
 
@@ -121,13 +224,27 @@ impl Core for Console {
         }
     }
 
-    fn load_rom(&mut self, filename: &str) -> bool {
+    fn load_rom(&mut self, filename: &str) -> Result<(), &'static str> {
         match self {
             Console::Gameboy(gb) => gb.load_rom(filename),
             Console::GBA(gba) => gba.load_rom(filename),
         }
     }
 
+    fn save_state(&self) -> Vec<u8> {
+        match self {
+            Console::Gameboy(gb) => gb.save_state(),
+            Console::GBA(gba) => gba.save_state(),
+        }
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), &'static str> {
+        match self {
+            Console::Gameboy(gb) => gb.load_state(state),
+            Console::GBA(gba) => gba.load_state(state),
+        }
+    }
+
     fn get_platform_parameters(&self) -> (u32, u32) {
         match self {
             Console::Gameboy(gb) => gb.get_platform_parameters(),
@@ -0,0 +1,16 @@
+// mod.rs ---
+//
+// Filename: mod.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 23:52:18 2026 (+0200)
+// Last-Updated: Sun Oct 18 23:52:18 2026 (+0200)
+//           By: Louise <louise>
+//
+// PSG channels shared by the Gameboy and the GBA, which clocks
+// them at the Gameboy rate.
+//
+mod square;
+mod noise;
+
+pub use self::square::SquareChannel;
+pub use self::noise::NoiseChannel;
@@ -3,9 +3,10 @@
 // Filename: noise.rs
 // Author: Louise <louise>
 // Created: Thu Dec 28 00:06:44 2017 (+0100)
-// Last-Updated: Tue Jul 10 00:55:06 2018 (+0200)
-//           By: Louise <ludwigette>
+// Last-Updated: Sun Oct 18 23:52:18 2026 (+0200)
+//           By: Louise <louise>
 // 
+use crate::savestate;
 
 const DIVISORS: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
 
@@ -42,6 +43,14 @@ pub struct NoiseChannel {
     last_trigger: bool,
 }
 
+savestate!(NoiseChannel,
+           timer, timer_load, divisor, out_volume, enabled, dac_enabled, lfsr,
+           width_mode, clock_shift,
+           length_load, length_counter, length_enable,
+           volume, volume_load, envelope_running, envelope_direction,
+           envelope_period, envelope_period_load,
+           last_trigger);
+
 impl NoiseChannel {
     pub fn new() -> NoiseChannel {
         Default::default()
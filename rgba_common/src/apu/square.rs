@@ -3,9 +3,10 @@
 // Filename: square.rs
 // Author: Louise <louise>
 // Created: Sat Dec 23 01:16:18 2017 (+0100)
-// Last-Updated: Tue Jul 10 00:55:12 2018 (+0200)
-//           By: Louise <ludwigette>
+// Last-Updated: Sun Oct 18 23:52:18 2026 (+0200)
+//           By: Louise <louise>
 // 
+use crate::savestate;
 
 const DUTY_TABLE: [[bool; 8]; 4] = [
     [false, false, false, false, false, false, false, true],
@@ -52,6 +53,15 @@ pub struct SquareChannel {
     last_trigger: bool,
 }
 
+savestate!(SquareChannel,
+           timer, timer_load, frequency, enabled, dac_enabled,
+           duty, duty_state, out_volume,
+           sweep_enable, sweep_period, sweep_period_load, sweep_negate, sweep_shift, sweep_shadow,
+           length_load, length_counter, length_enable,
+           volume, volume_load, envelope_running, envelope_direction,
+           envelope_period, envelope_period_load,
+           last_trigger);
+
 impl SquareChannel {
     pub fn new() -> SquareChannel {
         SquareChannel {
@@ -246,7 +256,7 @@ impl SquareChannel {
     pub fn spend_cycles(&mut self, cycles: u16) {
         self.timer += cycles;
 
-        if self.timer >= self.timer_load {
+        while self.timer >= self.timer_load {
             self.timer -= self.timer_load;
             self.duty_state = (self.duty_state + 1) & 0x7;
             
@@ -6,6 +6,15 @@
 // Last-Updated: Sat Jul  6 22:57:07 2019 (+0200)
 //           By: Louise <ludwigette>
 //
+mod state;
+mod scheduler;
+pub mod apu;
+
+pub use crate::state::{Savestate, StateWriter, StateReader};
+pub use crate::scheduler::{Scheduler, Event as ScheduledEvent};
+
+/// Rate of the mono samples given to `Platform::queue_samples`
+pub const SAMPLE_RATE: u32 = 48_100;
 
 // Enums
 
@@ -28,7 +37,19 @@ pub enum Key {
     Up,
     Down,
     Right,
-    Left
+    Left,
+
+    L,
+    R
+}
+
+/// Analog inputs, like the accelerometer of some cartridges
+#[derive(Debug, Clone, Copy)]
+pub enum Axis {
+    // Positive is tilted to the right
+    TiltX,
+    // Positive is tilted towards the player
+    TiltY,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -37,8 +58,12 @@ pub enum Event {
     Debug,
     Reset,
     FastMode,
+    SaveState(u8),
+    LoadState(u8),
     KeyDown(Key),
-    KeyUp(Key)
+    KeyUp(Key),
+    // Position of an analog input, 0 is at rest
+    Analog(Axis, i16)
 }
 
 // Traits
@@ -48,7 +73,10 @@ pub trait Core {
     fn process_event(&mut self, event: Event);
     fn is_file(filename: &str) -> bool;
     fn load_bios<T: ToString>(&mut self, filename: Option<T>) -> Result<(), &'static str>;
-    fn load_rom(&mut self, filename: &str) -> bool;
+    fn load_rom(&mut self, filename: &str) -> Result<(), &'static str>;
+
+    fn save_state(&self) -> Vec<u8>;
+    fn load_state(&mut self, state: &[u8]) -> Result<(), &'static str>;
 
     fn get_platform_parameters(&self) -> (u32, u32);
     fn get_console_type() -> ConsoleType;
@@ -62,6 +90,9 @@ pub trait Platform {
     
     // Input functions
     fn read_line(&mut self, _prompt: &str) -> Option<String> { None }
+
+    // Called when the cartridge turns its rumble motor on or off
+    fn set_rumble(&mut self, _on: bool) { }
 }
 
 // Functions
@@ -0,0 +1,93 @@
+// scheduler.rs ---
+//
+// Filename: scheduler.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 15:02:41 2026 (+0200)
+// Last-Updated: Sun Oct 18 17:20:12 2026 (+0200)
+//           By: Louise <louise>
+//
+use crate::state::{Savestate, StateWriter, StateReader};
+
+/// Something that has to happen at a given cycle
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Event<K> {
+    pub time: u64,
+    pub kind: K,
+}
+
+impl<K: Savestate> Savestate for Event<K> {
+    fn save(&self, state: &mut StateWriter) {
+        self.time.save(state);
+        self.kind.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        self.time.load(state)?;
+        self.kind.load(state)
+    }
+}
+
+/// Keeps the upcoming events of a core sorted by timestamp. There
+/// are only ever a handful of them, so a sorted Vec does the job.
+#[derive(Default)]
+pub struct Scheduler<K> {
+    timestamp: u64,
+    events: Vec<Event<K>>,
+}
+
+impl<K: Savestate + Default> Savestate for Scheduler<K> {
+    fn save(&self, state: &mut StateWriter) {
+        self.timestamp.save(state);
+        self.events.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        self.timestamp.load(state)?;
+        self.events.load(state)
+    }
+}
+
+impl<K: Copy + PartialEq> Scheduler<K> {
+    pub fn new() -> Scheduler<K> {
+        Scheduler { timestamp: 0, events: Vec::new() }
+    }
+
+    #[inline]
+    pub fn now(&self) -> u64 { self.timestamp }
+
+    #[inline]
+    pub fn advance(&mut self, cycles: u32) {
+        self.timestamp += cycles as u64;
+    }
+
+    /// Timestamp of the next event, if there is one
+    #[inline]
+    pub fn next_event(&self) -> Option<u64> {
+        self.events.first().map(|event| event.time)
+    }
+
+    /// Adds an event. Events happening at the same time are
+    /// handled in the order they were scheduled.
+    pub fn schedule(&mut self, time: u64, kind: K) {
+        let index = self.events.partition_point(|event| event.time <= time);
+
+        self.events.insert(index, Event { time, kind });
+    }
+
+    pub fn cancel(&mut self, kind: K) {
+        self.events.retain(|event| event.kind != kind);
+    }
+
+    pub fn is_scheduled(&self, kind: K) -> bool {
+        self.events.iter().any(|event| event.kind == kind)
+    }
+
+    /// Removes and returns the next event if it is due.
+    #[inline]
+    pub fn pop(&mut self) -> Option<Event<K>> {
+        match self.events.first() {
+            Some(event) if event.time <= self.timestamp => Some(self.events.remove(0)),
+            _ => None,
+        }
+    }
+}
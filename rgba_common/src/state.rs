@@ -0,0 +1,250 @@
+// state.rs ---
+//
+// Filename: state.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 14:02:11 2026 (+0200)
+// Last-Updated: Sun Oct 18 14:02:11 2026 (+0200)
+//           By: Louise <louise>
+//
+use crate::ConsoleType;
+
+const STATE_MAGIC: [u8; 4] = *b"RGBS";
+
+/// Serializes the state of a core into a versioned, little-endian
+/// binary blob.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(console: ConsoleType, version: u16) -> StateWriter {
+        let mut writer = StateWriter { data: Vec::new() };
+
+        writer.write_bytes(&STATE_MAGIC);
+        writer.write_u8(console as u8);
+        writer.write_u16(version);
+
+        writer
+    }
+
+    #[inline]
+    pub fn write_u8(&mut self, value: u8) { self.data.push(value); }
+    #[inline]
+    pub fn write_u16(&mut self, value: u16) { self.data.extend_from_slice(&value.to_le_bytes()); }
+    #[inline]
+    pub fn write_u32(&mut self, value: u32) { self.data.extend_from_slice(&value.to_le_bytes()); }
+    #[inline]
+    pub fn write_u64(&mut self, value: u64) { self.data.extend_from_slice(&value.to_le_bytes()); }
+    #[inline]
+    pub fn write_bytes(&mut self, bytes: &[u8]) { self.data.extend_from_slice(bytes); }
+
+    pub fn into_inner(self) -> Vec<u8> { self.data }
+}
+
+/// Reads back a blob produced by `StateWriter`, checking that it
+/// was made by the same core and state version.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8], console: ConsoleType, version: u16) -> Result<StateReader<'a>, &'static str> {
+        let mut reader = StateReader { data, offset: 0 };
+
+        let mut magic = [0; 4];
+        reader.read_bytes(&mut magic)?;
+
+        if magic != STATE_MAGIC {
+            return Err("Not a savestate");
+        }
+
+        if reader.read_u8()? != console as u8 {
+            return Err("Savestate is for another console");
+        }
+
+        if reader.read_u16()? != version {
+            return Err("Savestate version mismatch");
+        }
+
+        Ok(reader)
+    }
+
+    pub fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), &'static str> {
+        let end = self.offset + bytes.len();
+
+        if end > self.data.len() {
+            return Err("Savestate is truncated");
+        }
+
+        bytes.copy_from_slice(&self.data[self.offset..end]);
+        self.offset = end;
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_u8(&mut self) -> Result<u8, &'static str> {
+        match self.data.get(self.offset) {
+            Some(&v) => { self.offset += 1; Ok(v) }
+            None => Err("Savestate is truncated"),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, &'static str> {
+        let mut bytes = [0; 2];
+        self.read_bytes(&mut bytes)?;
+
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, &'static str> {
+        let mut bytes = [0; 4];
+        self.read_bytes(&mut bytes)?;
+
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, &'static str> {
+        let mut bytes = [0; 8];
+        self.read_bytes(&mut bytes)?;
+
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Bytes left to read
+    #[inline]
+    pub fn remaining(&self) -> usize { self.data.len() - self.offset }
+
+    /// Checks that the whole blob has been consumed.
+    pub fn finish(&self) -> Result<(), &'static str> {
+        if self.offset == self.data.len() {
+            Ok(())
+        } else {
+            Err("Savestate has trailing data")
+        }
+    }
+}
+
+/// Implemented by every component whose state goes into a savestate.
+pub trait Savestate {
+    fn save(&self, state: &mut StateWriter);
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str>;
+}
+
+/// Implements `Savestate` for a struct by (de)serializing the listed
+/// fields in order.
+#[macro_export]
+macro_rules! savestate {
+    ($t:ty, $($field:ident),* $(,)?) => {
+        impl $crate::Savestate for $t {
+            fn save(&self, state: &mut $crate::StateWriter) {
+                $( $crate::Savestate::save(&self.$field, state); )*
+            }
+
+            fn load(&mut self, state: &mut $crate::StateReader) -> Result<(), &'static str> {
+                $( $crate::Savestate::load(&mut self.$field, state)?; )*
+                Ok(())
+            }
+        }
+    };
+}
+
+macro_rules! savestate_int {
+    ($t:ty, $write:ident, $read:ident, $wt:ty) => {
+        impl Savestate for $t {
+            #[inline]
+            fn save(&self, state: &mut StateWriter) { state.$write(*self as $wt); }
+            #[inline]
+            fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+                *self = state.$read()? as $t;
+                Ok(())
+            }
+        }
+    };
+}
+
+savestate_int!(u8, write_u8, read_u8, u8);
+savestate_int!(i8, write_u8, read_u8, u8);
+savestate_int!(u16, write_u16, read_u16, u16);
+savestate_int!(i16, write_u16, read_u16, u16);
+savestate_int!(u32, write_u32, read_u32, u32);
+savestate_int!(i32, write_u32, read_u32, u32);
+savestate_int!(u64, write_u64, read_u64, u64);
+savestate_int!(usize, write_u64, read_u64, u64);
+
+impl Savestate for bool {
+    #[inline]
+    fn save(&self, state: &mut StateWriter) { state.write_u8(*self as u8); }
+    #[inline]
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        *self = state.read_u8()? != 0;
+        Ok(())
+    }
+}
+
+impl<T: Savestate, const N: usize> Savestate for [T; N] {
+    fn save(&self, state: &mut StateWriter) {
+        for v in self.iter() { v.save(state); }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        for v in self.iter_mut() { v.load(state)?; }
+        Ok(())
+    }
+}
+
+impl<T: Savestate + Default> Savestate for Vec<T> {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u32(self.len() as u32);
+
+        for v in self.iter() { v.save(state); }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        let len = state.read_u32()? as usize;
+
+        // Every element takes at least a byte, a bigger length can
+        // only come from a corrupted savestate
+        if len > state.remaining() {
+            return Err("Savestate is truncated");
+        }
+
+        self.clear();
+        self.resize_with(len, Default::default);
+
+        for v in self.iter_mut() { v.load(state)?; }
+        Ok(())
+    }
+}
+
+impl<T: Savestate + ?Sized> Savestate for Box<T> {
+    #[inline]
+    fn save(&self, state: &mut StateWriter) { (**self).save(state); }
+    #[inline]
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        (**self).load(state)
+    }
+}
+
+impl<T: Savestate + Default> Savestate for Option<T> {
+    fn save(&self, state: &mut StateWriter) {
+        match self {
+            Some(v) => { state.write_u8(1); v.save(state); }
+            None => state.write_u8(0),
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        if state.read_u8()? != 0 {
+            let mut v = T::default();
+            v.load(state)?;
+
+            *self = Some(v);
+        } else {
+            *self = None;
+        }
+
+        Ok(())
+    }
+}
@@ -6,15 +6,13 @@
 // Last-Updated: Wed Jul 11 19:44:39 2018 (+0200)
 //           By: Louise <ludwigette>
 // 
-mod square;
 mod wave;
-mod noise;
 
-use crate::apu::square::SquareChannel;
 use crate::apu::wave::WaveChannel;
-use crate::apu::noise::NoiseChannel;
 
 use rgba_common::Platform;
+use rgba_common::apu::{SquareChannel, NoiseChannel};
+use rgba_common::savestate;
 
 pub struct APU {
     enabled: bool,
@@ -26,7 +24,6 @@ pub struct APU {
 
     nr51: u8,
 
-    frame_cycles: u32,
     frame_sequencer: u8,
 
     samples: [i16; 1024],
@@ -34,8 +31,24 @@ pub struct APU {
     buffer_complete: bool,
     
     downsample_count: u32,
+
+    // Square wave played by the cartridge speaker of the HuC3,
+    // half period and time left in T-cycles
+    tone_half_period: u32,
+    tone_phase: u32,
+    tone_remaining: u32,
 }
 
+savestate!(APU,
+           enabled, channel1, channel2, channel3, channel4, nr51,
+           frame_sequencer,
+           samples, samples_index, buffer_complete, downsample_count,
+           tone_half_period, tone_phase, tone_remaining);
+
+// Length and volume of the tones of the cartridge speaker
+const TONE_CYCLES: u32 = 1 << 21;
+const TONE_VOLUME: i16 = 0x1000;
+
 impl APU {
     pub fn new() -> APU {
         APU {
@@ -48,7 +61,6 @@ impl APU {
 
             nr51: 0,
 
-            frame_cycles: 8192,
             frame_sequencer: 0,
 
             samples: [0; 1024],
@@ -56,6 +68,10 @@ impl APU {
             buffer_complete: false,
 
             downsample_count: 0,
+
+            tone_half_period: 0,
+            tone_phase: 0,
+            tone_remaining: 0,
         }
     }
 
@@ -140,6 +156,16 @@ impl APU {
         self.enabled = (nr52 & 0x80) != 0;
     }
 
+    /// Digital outputs of channels 1 and 2 (PCM12)
+    pub fn pcm12(&self) -> u8 {
+        (self.channel1.render() & 0xF) | (self.channel2.render() << 4)
+    }
+
+    /// Digital outputs of channels 3 and 4 (PCM34)
+    pub fn pcm34(&self) -> u8 {
+        (self.channel3.render() & 0xF) | (self.channel4.render() << 4)
+    }
+
     pub fn nr3_wave(&self, address: usize) -> u8 { self.channel3.wave(address) }
     pub fn set_nr3_wave(&mut self, address: usize, value: u8) {
         self.channel3.set_wave(address, value);
@@ -198,39 +224,59 @@ impl APU {
         so2
     }
     
-    pub fn spend_cycles(&mut self, cycles: u32) {
-        let cycles_16 = cycles as u16;
-        
-        self.frame_cycles += cycles;
+    /// Clocks the frame sequencer, which runs at 512Hz
+    pub fn frame_sequencer_click(&mut self) {
+        match self.frame_sequencer {
+            0 | 4 => {
+                self.channel1.length_click();
+                self.channel2.length_click();
+                self.channel3.length_click();
+                self.channel4.length_click();
+            },
+            2 | 6 => {
+                self.channel1.sweep_click();
+                self.channel1.length_click();
+                self.channel2.length_click();
+                self.channel3.length_click();
+                self.channel4.length_click();
+            },
+            7 => {
+                self.channel1.envelope_click();
+                self.channel2.envelope_click();
+                self.channel4.envelope_click();
+            },
+            _ => { }
+        }
 
-        if self.frame_cycles >= 8192 {
-            self.frame_cycles -= 8192;
-            
-            match self.frame_sequencer {
-                0 | 4 => {
-                    self.channel1.length_click();
-                    self.channel2.length_click();
-                    self.channel3.length_click();
-                    self.channel4.length_click();
-                },
-                2 | 6 => {
-                    self.channel1.sweep_click();
-                    self.channel1.length_click();
-                    self.channel2.length_click();
-                    self.channel3.length_click();
-                    self.channel4.length_click();
-                },
-                7 => {
-                    self.channel1.envelope_click();
-                    self.channel2.envelope_click();
-                    self.channel4.envelope_click();
-                },
-                _ => { }
-            }
+        self.frame_sequencer = (self.frame_sequencer + 1) & 0x7;
+    }
+
+    /// Cycles until the next sample is mixed
+    pub fn cycles_to_next_sample(&self) -> u32 {
+        88 - self.downsample_count
+    }
+
+    /// Plays a beep on the cartridge speaker, over the channels
+    pub fn play_tone(&mut self, frequency: u32) {
+        self.tone_half_period = (1 << 21) / frequency;
+        self.tone_phase = 0;
+        self.tone_remaining = TONE_CYCLES;
+    }
 
-            self.frame_sequencer = (self.frame_sequencer + 1) & 0x7;
+    fn tone(&mut self) -> i16 {
+        if self.tone_remaining == 0 {
+            return 0;
         }
 
+        self.tone_remaining = self.tone_remaining.saturating_sub(88);
+        self.tone_phase = (self.tone_phase + 88) % (self.tone_half_period << 1);
+
+        if self.tone_phase < self.tone_half_period { TONE_VOLUME } else { -TONE_VOLUME }
+    }
+
+    pub fn spend_cycles(&mut self, cycles: u32) {
+        let cycles_16 = cycles as u16;
+
         if self.channel1.enabled {
             self.channel1.spend_cycles(cycles_16);
         }
@@ -256,8 +302,9 @@ impl APU {
             let so2 = self.get_so2() as u16;
             
             let mix = (so1 + so2) << 7;
+            let tone = self.tone();
                 
-            self.samples[self.samples_index] = mix as i16;
+            self.samples[self.samples_index] = (mix as i16).saturating_add(tone);
             self.samples_index = (self.samples_index + 1) & 0x3ff;
                 
             if self.samples_index == 0 {
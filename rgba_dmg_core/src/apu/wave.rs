@@ -6,6 +6,7 @@
 // Last-Updated: Tue Jul 10 00:55:19 2018 (+0200)
 //           By: Louise <ludwigette>
 // 
+use rgba_common::savestate;
 
 #[derive(Debug, Default)]
 pub struct WaveChannel {
@@ -29,6 +30,11 @@ pub struct WaveChannel {
     wave_state: usize,
 }
 
+savestate!(WaveChannel,
+           enabled, timer, timer_load, frequency, out_volume, volume,
+           length_counter, length_load, length_enable,
+           last_trigger, wave_data, wave_state);
+
 impl WaveChannel {
     pub fn new() -> WaveChannel {
         Default::default()
@@ -95,7 +101,7 @@ impl WaveChannel {
     pub fn spend_cycles(&mut self, cycles: u16) {
         self.timer = self.timer.wrapping_add(cycles);
 
-        if self.timer >= self.timer_load {
+        while self.timer >= self.timer_load {
             self.timer -= self.timer_load;
             self.wave_state = (self.wave_state + 1) & 0x1f;
             
@@ -0,0 +1,366 @@
+// camera.rs ---
+//
+// Filename: camera.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 22:04:51 2026 (+0200)
+// Last-Updated: Sun Oct 18 23:59:51 2026 (+0200)
+//           By: Louise <louise>
+//
+use std::fs;
+use std::path::Path;
+
+use rgba_common::{savestate, Savestate, StateWriter, StateReader};
+
+use super::SaveRam;
+
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+
+const REGISTERS: usize = 0x36;
+
+const A000_CAPTURE: u8 = 0x01;
+const A001_N: u8 = 0x80;
+const A004_INVERT: u8 = 0x08;
+
+// Exposure giving the sensor image unchanged
+const EXPOSURE_UNITY: i32 = 0x1000;
+
+// Edge enhancement ratios, in quarters
+const EDGE_RATIOS: [i32; 8] = [2, 3, 4, 5, 8, 12, 16, 20];
+
+// The picture is written as 16x14 tiles in the first RAM bank
+const PICTURE_ADDRESS: usize = 0x100;
+
+// Larger input images are refused before anything is allocated
+const MAX_IMAGE_SIDE: usize = 8192;
+
+/// Registers of the Pocket Camera sensor, mapped on A000-A07F
+#[derive(Clone)]
+pub struct Camera {
+    regs: [u8; REGISTERS],
+    capture_requested: bool,
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera {
+            regs: [0; REGISTERS],
+            capture_requested: false,
+        }
+    }
+
+    #[inline]
+    pub fn busy(&self) -> bool {
+        (self.regs[0] & A000_CAPTURE) != 0
+    }
+
+    /// Only A000 can be read back
+    pub fn read(&self, address: usize) -> u8 {
+        if address & 0x7F == 0 {
+            self.regs[0] & 0x07
+        } else {
+            0x00
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        let reg = address & 0x7F;
+
+        if reg == 0 {
+            if !self.busy() && (value & A000_CAPTURE) != 0 {
+                self.capture_requested = true;
+            }
+
+            // A capture can't be stopped
+            self.regs[0] = (value & 0x07) | (self.regs[0] & A000_CAPTURE);
+        } else if reg < REGISTERS {
+            self.regs[reg] = value;
+        }
+    }
+
+    /// If a capture has just been started, the T-cycles it takes
+    pub fn take_capture(&mut self) -> Option<u64> {
+        if !self.capture_requested {
+            return None;
+        }
+
+        self.capture_requested = false;
+
+        let exposure = (u64::from(self.regs[2]) << 8) | u64::from(self.regs[3]);
+        let n = if self.regs[1] & A001_N != 0 { 0 } else { 2048 };
+
+        Some(129784 + n + exposure * 64)
+    }
+
+    /// Processes the sensor image, and writes the picture in RAM
+    pub fn finish_capture(&mut self, image: &[u8], ram: &mut SaveRam) {
+        let exposure = (i32::from(self.regs[2]) << 8) | i32::from(self.regs[3]);
+        let exposed: Vec<i32> = image.iter()
+            .map(|&p| (i32::from(p) * exposure / EXPOSURE_UNITY).min(255))
+            .collect();
+
+        let ratio = EDGE_RATIOS[((self.regs[4] >> 4) & 0x7) as usize];
+        let invert = (self.regs[4] & A004_INVERT) != 0;
+
+        let pixel = |x: isize, y: isize| {
+            let x = x.clamp(0, SENSOR_WIDTH as isize - 1) as usize;
+            let y = y.clamp(0, SENSOR_HEIGHT as isize - 1) as usize;
+
+            exposed[y * SENSOR_WIDTH + x]
+        };
+
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let (xi, yi) = (x as isize, y as isize);
+                let p = pixel(xi, yi);
+
+                // Edge enhancement, horizontal, vertical or both
+                let mut value = match (self.regs[1] >> 5) & 0x3 {
+                    1 => p + ratio * (2 * p - pixel(xi - 1, yi) - pixel(xi + 1, yi)) / 8,
+                    2 => p + ratio * (2 * p - pixel(xi, yi - 1) - pixel(xi, yi + 1)) / 8,
+                    3 => p + ratio * (4 * p - pixel(xi - 1, yi) - pixel(xi + 1, yi)
+                                      - pixel(xi, yi - 1) - pixel(xi, yi + 1)) / 16,
+                    _ => p,
+                }.clamp(0, 255);
+
+                if invert {
+                    value = 255 - value;
+                }
+
+                // Each position of the 4x4 matrix has 3 thresholds
+                let matrix = 6 + (((y & 3) << 2) | (x & 3)) * 3;
+                let color = if value < i32::from(self.regs[matrix]) {
+                    3
+                } else if value < i32::from(self.regs[matrix + 1]) {
+                    2
+                } else if value < i32::from(self.regs[matrix + 2]) {
+                    1
+                } else {
+                    0
+                };
+
+                let tile = (y >> 3) * (SENSOR_WIDTH >> 3) + (x >> 3);
+                let address = PICTURE_ADDRESS + (tile << 4) + ((y & 7) << 1);
+                let bit = 7 - (x & 7);
+
+                let low = (ram.read(address) & !(1 << bit)) | ((color & 1) << bit);
+                let high = (ram.read(address + 1) & !(1 << bit)) | ((color >> 1) << bit);
+
+                ram.write(address, low);
+                ram.write(address + 1, high);
+            }
+        }
+
+        self.regs[0] &= !A000_CAPTURE;
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Camera { Camera::new() }
+}
+
+savestate!(Camera, regs, capture_requested);
+
+/// Pictures seen by the camera sensor, read from image files so
+/// captures are the same on every run. They are used in turn,
+/// one per capture.
+pub struct ImageSource {
+    frames: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl ImageSource {
+    /// Without images, the sensor sees a gradient
+    pub fn new() -> ImageSource {
+        let gradient = (0..SENSOR_WIDTH * SENSOR_HEIGHT)
+            .map(|i| {
+                let (x, y) = (i % SENSOR_WIDTH, i / SENSOR_WIDTH);
+                ((x + y) * 255 / (SENSOR_WIDTH + SENSOR_HEIGHT)) as u8
+            })
+            .collect();
+
+        ImageSource {
+            frames: vec![gradient],
+            next: 0,
+        }
+    }
+
+    /// Reads an image file, or every image of a directory in the
+    /// order of their names. PGM, PPM and BMP files are supported.
+    pub fn open(path: &str) -> Result<ImageSource, &'static str> {
+        let path = Path::new(path);
+
+        let mut files = if path.is_dir() {
+            let entries = fs::read_dir(path).map_err(|e| {
+                warn!("Couldn't read camera directory : {}", e);
+                "Error reading camera directory"
+            })?;
+
+            entries.filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.is_file())
+                .collect()
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        files.sort();
+
+        let mut frames = Vec::new();
+        for file in files.iter() {
+            let data = fs::read(file).map_err(|e| {
+                warn!("Couldn't read camera image {} : {}", file.display(), e);
+                "Error reading camera image"
+            })?;
+
+            match decode_image(&data) {
+                Some((width, height, gray)) => frames.push(scale(width, height, &gray)),
+                None => warn!("{} is not a supported image, skipping it", file.display()),
+            }
+        }
+
+        if frames.is_empty() {
+            return Err("No camera image found");
+        }
+
+        info!("{} camera image(s) loaded", frames.len());
+
+        Ok(ImageSource { frames, next: 0 })
+    }
+
+    pub fn next_frame(&mut self) -> &[u8] {
+        let frame = self.next % self.frames.len();
+        self.next = (frame + 1) % self.frames.len();
+
+        &self.frames[frame]
+    }
+}
+
+// Only the position in the sequence is saved, the images come
+// from the files
+impl Savestate for ImageSource {
+    fn save(&self, state: &mut StateWriter) {
+        self.next.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        self.next.load(state)?;
+        self.next %= self.frames.len();
+
+        Ok(())
+    }
+}
+
+/// Scales a grayscale image to the sensor size
+fn scale(width: usize, height: usize, gray: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(SENSOR_WIDTH * SENSOR_HEIGHT);
+
+    for y in 0..SENSOR_HEIGHT {
+        for x in 0..SENSOR_WIDTH {
+            let sx = x * width / SENSOR_WIDTH;
+            let sy = y * height / SENSOR_HEIGHT;
+
+            frame.push(gray[sy * width + sx]);
+        }
+    }
+
+    frame
+}
+
+#[inline]
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114) / 1000) as u8
+}
+
+/// Decodes binary PGM/PPM and uncompressed 24/32-bit BMP files
+/// into a grayscale image
+fn decode_image(data: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    match data.get(0..2)? {
+        b"P5" | b"P6" => decode_pnm(data),
+        b"BM" => decode_bmp(data),
+        _ => None,
+    }
+}
+
+fn decode_pnm(data: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    let channels = if data[1] == b'6' { 3 } else { 1 };
+
+    // Width, height and maximum value, with comments in between
+    let mut offset = 2;
+    let mut fields = [0usize; 3];
+    for field in fields.iter_mut() {
+        loop {
+            match *data.get(offset)? {
+                b'#' => while *data.get(offset)? != b'\n' { offset += 1; },
+                c if c.is_ascii_whitespace() => offset += 1,
+                _ => break,
+            }
+        }
+
+        let start = offset;
+        while data.get(offset)?.is_ascii_digit() { offset += 1; }
+        *field = std::str::from_utf8(&data[start..offset]).ok()?.parse().ok()?;
+    }
+
+    let [width, height, max] = fields;
+    if width == 0 || height == 0 || width > MAX_IMAGE_SIDE || height > MAX_IMAGE_SIDE ||
+        max == 0 || max > 255 {
+        return None;
+    }
+
+    // A single whitespace before the pixels
+    let start = offset.checked_add(1)?;
+    let end = width.checked_mul(height)?.checked_mul(channels)?.checked_add(start)?;
+    let pixels = data.get(start..end)?;
+    let gray = pixels.chunks(channels)
+        .map(|p| {
+            let value = if channels == 3 { luma(p[0], p[1], p[2]) } else { p[0] };
+            (usize::from(value) * 255 / max).min(255) as u8
+        })
+        .collect();
+
+    Some((width, height, gray))
+}
+
+fn decode_bmp(data: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    let u16_at = |o: usize| data.get(o..o + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |o: usize| data.get(o..o + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+    let pixels = u32_at(10)? as usize;
+    let width = u32_at(18)? as i32;
+    let height = u32_at(22)? as i32;
+    let bpp = u16_at(28)? as usize;
+    let compression = u32_at(30)?;
+
+    // Bitfields are only accepted as the usual 32-bit layout
+    if width <= 0 || height == 0 || !(bpp == 24 || bpp == 32) ||
+        !(compression == 0 || (compression == 3 && bpp == 32)) {
+        return None;
+    }
+
+    let (width, top_down) = (width as usize, height < 0);
+    let height = height.unsigned_abs() as usize;
+    if width > MAX_IMAGE_SIDE || height > MAX_IMAGE_SIDE {
+        return None;
+    }
+
+    let bytes = bpp / 8;
+    let stride = (width * bytes + 3) & !3;
+
+    // The whole pixel array has to be there
+    let end = stride.checked_mul(height)?.checked_add(pixels)?;
+    if end > data.len() {
+        return None;
+    }
+
+    let mut gray = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let start = pixels + row * stride;
+
+        for p in data.get(start..start + width * bytes)?.chunks(bytes) {
+            gray.push(luma(p[2], p[1], p[0]));
+        }
+    }
+
+    Some((width, height, gray))
+}
@@ -0,0 +1,113 @@
+// flash.rs ---
+//
+// Filename: flash.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 20:05:17 2026 (+0200)
+// Last-Updated: Sun Oct 18 20:05:17 2026 (+0200)
+//           By: Louise <louise>
+//
+use rgba_common::savestate;
+
+use super::SaveRam;
+
+// Macronix MX29F008, 1 MiB
+pub const FLASH_SIZE: usize = 0x100000;
+const SECTOR_SIZE: usize = 0x20000;
+
+const MANUFACTURER_ID: u8 = 0xC2;
+const DEVICE_ID: u8 = 0x81;
+
+// Command sequence, every command starts with AA to 5555 and
+// 55 to 2AAA
+const STATE_IDLE: u8 = 0;
+const STATE_UNLOCK1: u8 = 1;
+const STATE_UNLOCK2: u8 = 2;
+const STATE_ERASE: u8 = 3;
+const STATE_ERASE_UNLOCK1: u8 = 4;
+const STATE_ERASE_UNLOCK2: u8 = 5;
+const STATE_PROGRAM: u8 = 6;
+
+/// Flash chip of the MBC6, saved next to the ROM in a .flash file
+#[derive(Clone)]
+pub struct Flash {
+    data: SaveRam,
+
+    state: u8,
+    id_mode: bool,
+}
+
+impl Flash {
+    pub fn new(filename: &str) -> Flash {
+        Flash {
+            data: SaveRam::erased(&format!("{}.flash", filename), FLASH_SIZE),
+
+            state: STATE_IDLE,
+            id_mode: false,
+        }
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        if self.id_mode {
+            if address & 1 == 0 { MANUFACTURER_ID } else { DEVICE_ID }
+        } else {
+            self.data.read(address)
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        let command = address & 0x7FFF;
+
+        self.state = match (self.state, command, value) {
+            (STATE_PROGRAM, _, _) => {
+                // Programming can only clear bits
+                let old = self.data.read(address);
+                self.data.write(address, old & value);
+
+                STATE_IDLE
+            }
+
+            (_, _, 0xF0) => {
+                self.id_mode = false;
+                STATE_IDLE
+            }
+
+            (STATE_IDLE, 0x5555, 0xAA) => STATE_UNLOCK1,
+            (STATE_UNLOCK1, 0x2AAA, 0x55) => STATE_UNLOCK2,
+            (STATE_UNLOCK2, 0x5555, 0x80) => STATE_ERASE,
+            (STATE_UNLOCK2, 0x5555, 0x90) => {
+                self.id_mode = true;
+                STATE_IDLE
+            }
+            (STATE_UNLOCK2, 0x5555, 0xA0) => STATE_PROGRAM,
+
+            (STATE_ERASE, 0x5555, 0xAA) => STATE_ERASE_UNLOCK1,
+            (STATE_ERASE_UNLOCK1, 0x2AAA, 0x55) => STATE_ERASE_UNLOCK2,
+            (STATE_ERASE_UNLOCK2, 0x5555, 0x10) => {
+                self.erase(0, FLASH_SIZE);
+                STATE_IDLE
+            }
+            (STATE_ERASE_UNLOCK2, _, 0x30) => {
+                self.erase(address & !(SECTOR_SIZE - 1), SECTOR_SIZE);
+                STATE_IDLE
+            }
+
+            _ => {
+                warn!("Unknown flash command {:02x} at {:05x}", value, address);
+                STATE_IDLE
+            }
+        };
+    }
+
+    fn erase(&mut self, start: usize, len: usize) {
+        for address in start..start + len {
+            self.data.write(address, 0xFF);
+        }
+    }
+
+    #[inline]
+    pub fn save_ram(&mut self) -> &mut SaveRam {
+        &mut self.data
+    }
+}
+
+savestate!(Flash, data, state, id_mode);
@@ -0,0 +1,222 @@
+// huc3.rs ---
+//
+// Filename: huc3.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 22:52:36 2026 (+0200)
+// Last-Updated: Sun Oct 18 22:52:36 2026 (+0200)
+//           By: Louise <louise>
+//
+use rgba_common::savestate;
+
+use super::rtc::unix_time;
+
+const MINUTES_PER_DAY: u64 = 1440;
+
+// Locations in the nibble memory of the chip
+const CLOCK_MINUTES: usize = 0x00;
+const CLOCK_DAYS: usize = 0x03;
+const TONE: usize = 0x26;
+const TONE_ENABLE: usize = 0x27;
+const ALARM_MINUTES: usize = 0x58;
+const ALARM_DAYS: usize = 0x5B;
+const ALARM_ENABLE: usize = 0x5F;
+
+// The melodies of the real chip aren't documented, each tone is
+// played as a beep of its own pitch
+const TONE_FREQUENCIES: [u32; 4] = [1046, 1318, 1568, 2093];
+
+/// Size of the RTC footer appended to the savefile, in the format
+/// used by SameBoy : a 64-bit UNIX timestamp, then the minutes, days,
+/// alarm minutes and alarm days as 16-bit words, and the alarm flag.
+pub const FOOTER_SIZE: usize = 17;
+
+/// The HuC3 chip, with its real-time clock, tone generator and
+/// infrared port. The game talks to it through A000 in the modes
+/// selected by writing 0x0B-0x0E to 0000-1FFF.
+#[derive(Clone)]
+pub struct Huc3 {
+    // Minutes of the day and day counter, and the UNIX time at
+    // which they were up to date
+    minutes: u16,
+    days: u16,
+    timestamp: u64,
+
+    // 256 nibbles, accessed one at a time by commands
+    memory: [u8; 0x100],
+    index: u8,
+    command: u8,
+    response: u8,
+
+    tone_requested: bool,
+
+    ir_led: bool,
+    ir_light: bool,
+}
+
+impl Huc3 {
+    /// Builds the chip from a savefile footer, the clock starts
+    /// from zero if there is none.
+    pub fn from_footer(footer: &[u8]) -> Huc3 {
+        let mut huc3 = Huc3 {
+            minutes: 0,
+            days: 0,
+            timestamp: unix_time(),
+
+            memory: [0; 0x100],
+            index: 0,
+            command: 0,
+            response: 0,
+
+            tone_requested: false,
+
+            ir_led: false,
+            ir_light: false,
+        };
+
+        if footer.len() != FOOTER_SIZE {
+            if !footer.is_empty() {
+                warn!("Unknown RTC footer size ({} bytes), ignoring it", footer.len());
+            }
+
+            return huc3;
+        }
+
+        let word = |i: usize| u16::from_le_bytes([footer[i], footer[i + 1]]);
+
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&footer[0..8]);
+
+        huc3.timestamp = u64::from_le_bytes(timestamp);
+        huc3.minutes = word(8);
+        huc3.days = word(10);
+        huc3.set_nibbles(ALARM_MINUTES, 3, word(12));
+        huc3.set_nibbles(ALARM_DAYS, 4, word(14));
+        huc3.memory[ALARM_ENABLE] = footer[16] & 1;
+
+        info!("RTC loaded from savefile");
+
+        huc3
+    }
+
+    pub fn footer(&mut self) -> Vec<u8> {
+        self.update();
+
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+
+        footer.extend_from_slice(&self.timestamp.to_le_bytes());
+        footer.extend_from_slice(&self.minutes.to_le_bytes());
+        footer.extend_from_slice(&self.days.to_le_bytes());
+        footer.extend_from_slice(&self.nibbles(ALARM_MINUTES, 3).to_le_bytes());
+        footer.extend_from_slice(&self.nibbles(ALARM_DAYS, 4).to_le_bytes());
+        footer.push(self.memory[ALARM_ENABLE] & 1);
+
+        footer
+    }
+
+    pub fn read(&self, mode: u8) -> u8 {
+        match mode {
+            // Last command and its result
+            0x0C => (self.command << 4) | self.response,
+            // Commands are run right away, the chip is always ready
+            0x0D => 0xFF,
+            0x0E => 0xC0 | (self.ir_light as u8),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, mode: u8, value: u8) {
+        match mode {
+            0x0B => self.run_command(value),
+            0x0D => { },
+            0x0E => self.ir_led = (value & 1) != 0,
+            _ => warn!("Unmapped write to HuC3 in mode {:02x} (value={:02x})", mode, value),
+        }
+    }
+
+    fn run_command(&mut self, value: u8) {
+        let argument = value & 0xF;
+        let index = self.index as usize;
+
+        self.command = (value >> 4) & 0x7;
+
+        match self.command {
+            0x1 => {
+                self.response = self.memory[index];
+                self.index = self.index.wrapping_add(1);
+            }
+            0x3 => {
+                self.memory[index] = argument;
+                self.index = self.index.wrapping_add(1);
+            }
+            0x4 => self.index = (self.index & 0xF0) | argument,
+            0x5 => self.index = (self.index & 0x0F) | (argument << 4),
+            0x6 => match argument {
+                0x0 => {
+                    self.update();
+                    self.set_nibbles(CLOCK_MINUTES, 3, self.minutes);
+                    self.set_nibbles(CLOCK_DAYS, 4, self.days);
+                }
+                0x1 => {
+                    self.update();
+                    self.minutes = self.nibbles(CLOCK_MINUTES, 3);
+                    self.days = self.nibbles(CLOCK_DAYS, 4);
+                }
+                0x2 => self.response = 1,
+                0xE => self.tone_requested = self.memory[TONE_ENABLE] == 1,
+                _ => warn!("Unknown HuC3 extended command {:x}", argument),
+            },
+            _ => warn!("Unknown HuC3 command {:02x}", value),
+        }
+    }
+
+    /// Frequency of the tone the game has just started, if any
+    pub fn take_tone(&mut self) -> Option<u32> {
+        if !self.tone_requested {
+            return None;
+        }
+
+        self.tone_requested = false;
+
+        Some(TONE_FREQUENCIES[(self.memory[TONE] & 0x3) as usize])
+    }
+
+    #[inline]
+    pub fn ir_led(&self) -> bool { self.ir_led }
+
+    #[inline]
+    pub fn set_ir_light(&mut self, light: bool) { self.ir_light = light; }
+
+    /// Little-endian value stored in `count` nibbles
+    fn nibbles(&self, start: usize, count: usize) -> u16 {
+        self.memory[start..start + count].iter().rev()
+            .fold(0, |value, &n| (value << 4) | u16::from(n & 0xF))
+    }
+
+    fn set_nibbles(&mut self, start: usize, count: usize, value: u16) {
+        for (i, n) in self.memory[start..start + count].iter_mut().enumerate() {
+            *n = ((value >> (i * 4)) & 0xF) as u8;
+        }
+    }
+
+    /// Only whole minutes are counted, the seconds stay in the
+    /// timestamp
+    fn update(&mut self) {
+        let now = unix_time();
+
+        if now < self.timestamp {
+            self.timestamp = now;
+            return;
+        }
+
+        let elapsed = (now - self.timestamp) / 60;
+        let total = u64::from(self.minutes) + elapsed;
+
+        self.minutes = (total % MINUTES_PER_DAY) as u16;
+        self.days = self.days.wrapping_add((total / MINUTES_PER_DAY) as u16);
+        self.timestamp += elapsed * 60;
+    }
+}
+
+savestate!(Huc3,
+           minutes, days, timestamp, memory, index, command, response,
+           tone_requested, ir_led, ir_light);
@@ -0,0 +1,253 @@
+// mbc7.rs ---
+//
+// Filename: mbc7.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 21:10:33 2026 (+0200)
+// Last-Updated: Sun Oct 18 21:10:33 2026 (+0200)
+//           By: Louise <louise>
+//
+use rgba_common::{savestate, Axis};
+
+use super::SaveRam;
+
+// Accelerometer value at rest, and the offset for 1g
+const ACCEL_CENTER: i32 = 0x81D0;
+const ACCEL_G: i32 = 0x70;
+
+// 93LC56, 128 16-bit words
+const EEPROM_SIZE: usize = 0x100;
+
+const STATE_IDLE: u8 = 0;
+const STATE_COMMAND: u8 = 1;
+const STATE_READ: u8 = 2;
+const STATE_WRITE: u8 = 3;
+const STATE_WRITE_ALL: u8 = 4;
+const STATE_DONE: u8 = 5;
+
+/// Accelerometer and serial EEPROM of the MBC7, mapped on A000-AFFF
+#[derive(Clone)]
+pub struct Mbc7 {
+    eeprom: SaveRam,
+
+    // Tilt given by the platform, positive is right and down
+    tilt: [i16; 2],
+    latched: [u16; 2],
+    erased: bool,
+
+    // EEPROM pins
+    cs: bool,
+    clk: bool,
+    di: bool,
+    dout: bool,
+
+    state: u8,
+    buffer: u16,
+    bits: u8,
+    address: u8,
+    write_enable: bool,
+}
+
+impl Mbc7 {
+    pub fn new(filename: &str) -> Mbc7 {
+        Mbc7 {
+            eeprom: SaveRam::erased(&format!("{}.sav", filename), EEPROM_SIZE),
+
+            tilt: [0; 2],
+            latched: [0x8000; 2],
+            erased: false,
+
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+
+            state: STATE_IDLE,
+            buffer: 0,
+            bits: 0,
+            address: 0,
+            write_enable: false,
+        }
+    }
+
+    pub fn set_tilt(&mut self, axis: Axis, value: i16) {
+        match axis {
+            Axis::TiltX => self.tilt[0] = value,
+            Axis::TiltY => self.tilt[1] = value,
+        }
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match (address >> 4) & 0xF {
+            0x2 => self.latched[0] as u8,
+            0x3 => (self.latched[0] >> 8) as u8,
+            0x4 => self.latched[1] as u8,
+            0x5 => (self.latched[1] >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => {
+                ((self.cs as u8) << 7) | ((self.clk as u8) << 6) |
+                ((self.di as u8) << 1) | (self.dout as u8)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        match (address >> 4) & 0xF {
+            0x0 if value == 0x55 => {
+                self.erased = true;
+                self.latched = [0x8000; 2];
+            }
+            0x1 if value == 0xAA && self.erased => {
+                self.erased = false;
+                self.latched = [
+                    (ACCEL_CENTER + i32::from(self.tilt[0]) * ACCEL_G / 0x8000) as u16,
+                    (ACCEL_CENTER + i32::from(self.tilt[1]) * ACCEL_G / 0x8000) as u16,
+                ];
+            }
+            0x8 => self.write_eeprom_pins(value),
+            _ => { },
+        }
+    }
+
+    fn write_eeprom_pins(&mut self, value: u8) {
+        let cs = (value & 0x80) != 0;
+        let clk = (value & 0x40) != 0;
+        let di = (value & 0x02) != 0;
+
+        if !cs {
+            self.state = STATE_IDLE;
+            self.dout = true;
+        } else if clk && !self.clk {
+            self.clock(di);
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+        self.di = di;
+    }
+
+    // Data is shifted on the rising edge of the clock
+    fn clock(&mut self, di: bool) {
+        match self.state {
+            STATE_IDLE if di => {
+                self.state = STATE_COMMAND;
+                self.buffer = 0;
+                self.bits = 0;
+            }
+            STATE_COMMAND => {
+                self.shift_in(di);
+
+                // 2 bits of opcode, 8 bits of address
+                if self.bits == 10 {
+                    self.command();
+                }
+            }
+            STATE_READ => {
+                self.dout = (self.buffer & 0x8000) != 0;
+                self.buffer <<= 1;
+                self.bits += 1;
+
+                // Keeps reading the next words
+                if self.bits == 16 {
+                    self.address = (self.address + 1) & 0x7F;
+                    self.buffer = self.word(self.address);
+                    self.bits = 0;
+                }
+            }
+            STATE_WRITE | STATE_WRITE_ALL => {
+                self.shift_in(di);
+
+                if self.bits == 16 {
+                    if self.write_enable {
+                        if self.state == STATE_WRITE_ALL {
+                            for address in 0..0x80 {
+                                self.set_word(address, self.buffer);
+                            }
+                        } else {
+                            self.set_word(self.address, self.buffer);
+                        }
+                    }
+
+                    self.state = STATE_DONE;
+                    self.dout = true;
+                }
+            }
+            _ => { },
+        }
+    }
+
+    fn shift_in(&mut self, di: bool) {
+        self.buffer = (self.buffer << 1) | di as u16;
+        self.bits += 1;
+    }
+
+    fn command(&mut self) {
+        let address = (self.buffer & 0x7F) as u8;
+
+        self.bits = 0;
+        self.state = STATE_DONE;
+
+        match (self.buffer >> 8) & 0x3 {
+            // READ, a dummy 0 comes first
+            0b10 => {
+                self.address = address;
+                self.buffer = self.word(address);
+                self.dout = false;
+                self.state = STATE_READ;
+            }
+            // WRITE
+            0b01 => {
+                self.address = address;
+                self.buffer = 0;
+                self.state = STATE_WRITE;
+            }
+            // ERASE
+            0b11 => {
+                if self.write_enable {
+                    self.set_word(address, 0xFFFF);
+                }
+                self.dout = true;
+            }
+            _ => match (self.buffer >> 6) & 0x3 {
+                0b11 => self.write_enable = true,
+                0b00 => self.write_enable = false,
+                // ERAL
+                0b10 => {
+                    if self.write_enable {
+                        for address in 0..0x80 {
+                            self.set_word(address, 0xFFFF);
+                        }
+                    }
+                    self.dout = true;
+                }
+                // WRAL
+                _ => {
+                    self.buffer = 0;
+                    self.state = STATE_WRITE_ALL;
+                }
+            },
+        }
+    }
+
+    fn word(&self, address: u8) -> u16 {
+        let address = (address as usize) << 1;
+
+        u16::from_le_bytes([self.eeprom.read(address), self.eeprom.read(address + 1)])
+    }
+
+    fn set_word(&mut self, address: u8, value: u16) {
+        let address = (address as usize) << 1;
+
+        self.eeprom.write(address, value as u8);
+        self.eeprom.write(address + 1, (value >> 8) as u8);
+    }
+
+    #[inline]
+    pub fn save_ram(&mut self) -> &mut SaveRam {
+        &mut self.eeprom
+    }
+}
+
+savestate!(Mbc7,
+           eeprom, latched, erased, cs, clk, di, dout,
+           state, buffer, bits, address, write_enable);
@@ -0,0 +1,1380 @@
+// mod.rs --- 
+// 
+// Filename: mod.rs
+// Author: Louise <louise>
+// Created: Wed Dec  6 23:43:31 2017 (+0100)
+// Last-Updated: Sun Jan 21 14:40:54 2018 (+0100)
+//           By: Louise <louise>
+//
+use std::fs;
+use std::io::Write;
+
+use rgba_common::{Savestate, StateWriter, StateReader};
+
+mod rtc;
+mod flash;
+mod mbc7;
+mod camera;
+mod huc3;
+
+use self::rtc::Rtc;
+use self::flash::Flash;
+use self::mbc7::Mbc7;
+use self::camera::Camera;
+use self::huc3::Huc3;
+
+pub use self::camera::ImageSource;
+
+use rgba_common::Axis;
+
+// Frames to wait after the last write to the RAM before
+// writing the savefile, and maximum frames between two writes
+// if the game keeps writing
+const SAVE_IDLE_FRAMES: u32 = 60;
+const SAVE_MAX_FRAMES: u32 = 600;
+
+#[derive(Clone)]
+pub enum Cartridge {
+    NoCartridge,
+    RomOnly(
+        Vec<u8>
+    ),
+    MBC1 {
+        rom: Vec<u8>,
+        ram: SaveRam,
+        
+        mode: bool,
+        ram_enable: bool,
+
+        rom_bank: u8,
+        ram_bank: u8,
+
+        rom_banks: usize,
+        // MBC1M, the second bank register starts at bit 4
+        multicart: bool,
+    },
+    MBC2 {
+        rom: Vec<u8>,
+        ram: SaveRam,
+
+        ram_enable: bool,
+        rom_bank: u8,
+
+        rom_banks: usize,
+    },
+    MBC3 {
+        rom: Vec<u8>,
+        ram: SaveRam,
+        rtc: Option<Rtc>,
+
+        ram_enable: bool,
+        rom_bank: u8,
+        ram_bank: u8,
+
+        rom_banks: usize,
+    },
+    MBC5 {
+        rom: Vec<u8>,
+        ram: SaveRam,
+
+        ram_enable: bool,
+        rom_bank: usize,
+        ram_bank: u8,
+
+        rom_banks: usize,
+        // Carts with a motor use bit 3 of the RAM bank register
+        rumble: bool,
+        motor: bool,
+    },
+    MMM01 {
+        rom: Vec<u8>,
+        ram: SaveRam,
+
+        // Until mapped, the last 32KB of the ROM (the menu) are
+        // visible and every register is writable
+        mapped: bool,
+        mode: bool,
+        mode_locked: bool,
+        ram_enable: bool,
+
+        rom_bank: u16,
+        ram_bank: u8,
+
+        // Bank bits the menu locks before starting a game
+        rom_mask: u8,
+        ram_mask: u8,
+    },
+    MBC6 {
+        rom: Vec<u8>,
+        ram: SaveRam,
+        flash: Flash,
+
+        ram_enable: bool,
+        flash_enable: bool,
+        flash_write: bool,
+
+        // Two 8KB ROM/flash windows and two 4KB RAM windows
+        banks: [u8; 2],
+        flash_mapped: [bool; 2],
+        ram_banks: [u8; 2],
+    },
+    MBC7 {
+        rom: Vec<u8>,
+        mbc7: Mbc7,
+
+        // Both have to be set to access the registers
+        ram_enable: bool,
+        ram_enable2: bool,
+        rom_bank: usize,
+
+        rom_banks: usize,
+    },
+    PocketCamera {
+        rom: Vec<u8>,
+        ram: SaveRam,
+        camera: Camera,
+
+        ram_enable: bool,
+        // The sensor registers replace the RAM
+        camera_mapped: bool,
+        rom_bank: usize,
+        ram_bank: u8,
+
+        rom_banks: usize,
+    },
+    HuC1 {
+        rom: Vec<u8>,
+        ram: SaveRam,
+
+        // The infrared port replaces the RAM
+        ir_mapped: bool,
+        ir_led: bool,
+        ir_light: bool,
+
+        rom_bank: usize,
+        ram_bank: u8,
+
+        rom_banks: usize,
+    },
+    HuC3 {
+        rom: Vec<u8>,
+        ram: SaveRam,
+        huc3: Huc3,
+
+        // What A000-BFFF maps, the RAM or one of the chip registers
+        mode: u8,
+        rom_bank: usize,
+        ram_bank: u8,
+
+        rom_banks: usize,
+    },
+}
+
+impl Cartridge {
+    pub fn new(filename: &str, rom: Vec<u8>) -> Result<Cartridge, &'static str> {
+        if rom.len() < 0x8000 {
+            return Err("ROM is too small");
+        }
+
+        // The MMM01 header is in the menu, at the end of the ROM
+        let menu = rom.len() - 0x8000;
+        if let mbc @ 0x0B..=0x0D = rom[menu + 0x147] {
+            let ram = SaveRam::new(filename, ram_size(rom[menu + 0x149]), mbc == 0x0D);
+
+            return Ok(Cartridge::MMM01 {
+                rom,
+                ram,
+                mapped: false,
+                mode: false,
+                mode_locked: false,
+                ram_enable: false,
+                rom_bank: 0,
+                ram_bank: 0,
+                rom_mask: 0,
+                ram_mask: 0,
+            });
+        }
+
+        let rom_banks = rom_banks(&rom);
+        let ram_size = ram_size(rom[0x149]);
+        
+        let cart = match rom[0x147] {
+            0x00 => {
+                Cartridge::RomOnly(rom)
+            },
+
+            mbc @ 0x01..=0x03 => {
+                let ram = SaveRam::new(filename, ram_size, mbc == 0x03);
+                let multicart = is_mbc1_multicart(&rom);
+
+                if multicart {
+                    info!("MBC1 multicart detected");
+                }
+                
+                Cartridge::MBC1 {
+                    rom,
+                    ram,
+                    ram_enable: false,
+                    mode: false,
+                    rom_bank: 1,
+                    ram_bank: 0,
+                    rom_banks,
+                    multicart,
+                }
+            },
+
+            mbc @ 0x05..=0x06 => {
+                // 512 half-bytes built in the MBC
+                let ram = SaveRam::new(filename, 0x200, mbc == 0x06);
+
+                Cartridge::MBC2 {
+                    rom,
+                    ram,
+                    ram_enable: false,
+                    rom_bank: 1,
+                    rom_banks,
+                }
+            },
+
+            mbc @ 0x0F..=0x13 => {
+                let ram = SaveRam::new(filename, ram_size, mbc != 0x11 && mbc != 0x12);
+                let rtc = if mbc == 0x0F || mbc == 0x10 {
+                    Some(Rtc::from_footer(ram.footer()))
+                } else {
+                    None
+                };
+                
+                Cartridge::MBC3 {
+                    rom,
+                    ram,
+                    rtc,
+                    ram_enable: false,
+                    
+                    rom_bank: 1,
+                    ram_bank: 0,
+                    rom_banks,
+                }
+            },
+
+            mbc @ 0x19..=0x1E => {
+                let ram = SaveRam::new(filename, ram_size, mbc == 0x1B || mbc == 0x1E);
+                
+                Cartridge::MBC5 {
+                    rom,
+                    ram,
+                    ram_enable: false,
+                    
+                    rom_bank: 1,
+                    ram_bank: 0,
+                    rom_banks,
+                    rumble: (0x1C..=0x1E).contains(&mbc),
+                    motor: false,
+                }
+            },
+
+            0x20 => {
+                let ram = SaveRam::new(filename, ram_size, true);
+                let flash = Flash::new(filename);
+
+                Cartridge::MBC6 {
+                    rom,
+                    ram,
+                    flash,
+                    ram_enable: false,
+                    flash_enable: false,
+                    flash_write: false,
+                    banks: [0, 1],
+                    flash_mapped: [false, false],
+                    ram_banks: [0, 1],
+                }
+            },
+            
+            0x22 => {
+                let mbc7 = Mbc7::new(filename);
+
+                Cartridge::MBC7 {
+                    rom,
+                    mbc7,
+                    ram_enable: false,
+                    ram_enable2: false,
+                    rom_bank: 1,
+                    rom_banks,
+                }
+            },
+            
+            0xFC => {
+                let ram = SaveRam::new(filename, ram_size, true);
+
+                Cartridge::PocketCamera {
+                    rom,
+                    ram,
+                    camera: Camera::new(),
+                    ram_enable: false,
+                    camera_mapped: false,
+                    rom_bank: 1,
+                    ram_bank: 0,
+                    rom_banks,
+                }
+            },
+            
+            0xFE => {
+                let ram = SaveRam::new(filename, ram_size, true);
+                let huc3 = Huc3::from_footer(ram.footer());
+
+                Cartridge::HuC3 {
+                    rom,
+                    ram,
+                    huc3,
+                    mode: 0,
+                    rom_bank: 1,
+                    ram_bank: 0,
+                    rom_banks,
+                }
+            },
+
+            0xFF => {
+                let ram = SaveRam::new(filename, ram_size, true);
+
+                Cartridge::HuC1 {
+                    rom,
+                    ram,
+                    ir_mapped: false,
+                    ir_led: false,
+                    ir_light: false,
+                    rom_bank: 1,
+                    ram_bank: 0,
+                    rom_banks,
+                }
+            },
+
+            mbc => {
+                error!("MBC type {:02x} is not supported", mbc);
+
+                return Err("Unsupported cartridge type");
+            }
+        };
+
+        Ok(cart)
+    }
+
+    pub fn read_rom(&self, address: usize) -> u8 {
+        match *self {
+            Cartridge::NoCartridge => {
+                warn!("Unmapped read from {:04x} (Cart ROM)", address);
+                0xFF
+            },
+            Cartridge::RomOnly(ref v) => v[address],
+            Cartridge::MBC1 {
+                rom: ref v, rom_bank, ram_bank, mode, multicart, rom_banks, ..
+            } => {
+                let shift = if multicart { 4 } else { 5 };
+                let high = (ram_bank as usize) << shift;
+
+                let bank = match address {
+                    0x0000..=0x3FFF if mode => high,
+                    0x0000..=0x3FFF => 0,
+                    0x4000..=0x7FFF => high | (rom_bank as usize & ((1 << shift) - 1)),
+                    _ => unreachable!(),
+                };
+
+                v[((bank % rom_banks) << 14) + (address & 0x3FFF)]
+            }
+            Cartridge::MBC2 { rom: ref v, rom_bank: b, .. } |
+            Cartridge::MBC3 { rom: ref v, rom_bank: b, .. } => {
+                match address {
+                    0x0000..=0x3FFF => v[address],
+                    0x4000..=0x7FFF => 
+                        v[((b as usize) << 14) + (address & 0x3FFF)],
+                    _ => unreachable!(),
+                }
+            }
+            Cartridge::MBC5 { rom: ref v, rom_bank: b, rom_banks, .. } |
+            Cartridge::MBC7 { rom: ref v, rom_bank: b, rom_banks, .. } |
+            Cartridge::PocketCamera { rom: ref v, rom_bank: b, rom_banks, .. } |
+            Cartridge::HuC1 { rom: ref v, rom_bank: b, rom_banks, .. } |
+            Cartridge::HuC3 { rom: ref v, rom_bank: b, rom_banks, .. } => {
+                match address {
+                    0x0000..=0x3FFF => v[address],
+                    0x4000..=0x7FFF => 
+                        v[((b % rom_banks) << 14) + (address & 0x3FFF)],
+                    _ => unreachable!(),
+                }
+            }
+            Cartridge::MMM01 { rom: ref v, mapped: false, .. } => {
+                v[v.len() - 0x8000 + address]
+            }
+            Cartridge::MMM01 { rom: ref v, rom_bank, rom_mask, .. } => {
+                let writable = 0x1F & !((rom_mask as u16) << 1);
+
+                let bank = match address {
+                    0x0000..=0x3FFF => rom_bank & !writable,
+                    0x4000..=0x7FFF if rom_bank & writable == 0 => rom_bank | 1,
+                    0x4000..=0x7FFF => rom_bank,
+                    _ => unreachable!(),
+                } as usize;
+
+                v[((bank % (v.len() >> 14)) << 14) + (address & 0x3FFF)]
+            }
+            Cartridge::MBC6 { rom: ref v, ref flash, banks, flash_mapped, .. } => {
+                match address {
+                    0x0000..=0x3FFF => v[address],
+                    0x4000..=0x7FFF => {
+                        let window = (address >> 13) & 1;
+                        let bank = (banks[window] & 0x7F) as usize;
+
+                        if flash_mapped[window] {
+                            flash.read((bank << 13) + (address & 0x1FFF))
+                        } else {
+                            v[((bank % (v.len() >> 13)) << 13) + (address & 0x1FFF)]
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    pub fn write_rom(&mut self, address: usize, value: u8) {
+        match *self {
+            Cartridge::NoCartridge |
+            Cartridge::RomOnly(_) =>
+                warn!("Unmapped write to {:04x} (Cart ROM, value={:02x})", address, value),
+            Cartridge::MBC1 {
+                ref mut rom_bank,
+                ref mut ram_bank,
+                ref mut mode,
+                ref mut ram_enable, ..
+            } => {
+                match address {
+                    0x0000..=0x1FFF => {
+                        *ram_enable = (value & 0xF) == 0xA;
+                    },
+                    0x2000..=0x3FFF => {
+                        *rom_bank = if value & 0x1f == 0 { 1 } else { value & 0x1f };
+                    }
+                    0x4000..=0x5FFF => *ram_bank = value & 0x3,
+                    0x6000..=0x7FFF => *mode = (value & 1) != 0,
+                    _ => unreachable!(),
+                }
+            }
+            Cartridge::MBC2 {
+                ref mut rom_bank,
+                ref mut ram_enable,
+                ref rom_banks, ..
+            } => {
+                match address {
+                    // Bit 8 of the address selects the register
+                    0x0000..=0x3FFF if address & 0x100 == 0 => {
+                        *ram_enable = (value & 0xF) == 0xA;
+                    }
+                    0x0000..=0x3FFF => {
+                        *rom_bank = if value & 0xF == 0 { 1 } else { value & 0xF };
+                        *rom_bank = (*rom_bank as usize % rom_banks) as u8;
+                    }
+                    _ => warn!("Unmapped write to {:04x} (Cart ROM, value={:02x})", address, value),
+                }
+            }
+            Cartridge::MBC3 {
+                ref mut rom_bank,
+                ref mut ram_bank,
+                ref mut ram_enable,
+                ref mut rtc,
+                ref rom_banks, ..
+            } => {
+                match address {
+                    0x0000..=0x1FFF => {
+                        *ram_enable = (value & 0xF) == 0xA;
+                    },
+                    0x2000..=0x3FFF => {
+                        *rom_bank = if value == 0 {
+                            1
+                        } else {
+                            value & 0x7f
+                        };
+
+                        *rom_bank = (*rom_bank as usize % rom_banks) as u8;
+                    }
+                    0x4000..=0x5FFF => {
+                        match value {
+                            0x00..=0x03 => *ram_bank = value,
+                            0x08..=0x0C if rtc.is_some() => *ram_bank = value,
+                            _ => warn!("Invalid MBC3 RAM bank {:02x}", value),
+                        }
+                    }
+                    0x6000..=0x7FFF => {
+                        if let Some(ref mut rtc) = *rtc {
+                            rtc.write_latch(value);
+                        }
+                    },
+                    _ => unreachable!(),
+                }
+            }
+            Cartridge::MBC5 {
+                ref mut rom_bank,
+                ref mut ram_bank,
+                ref mut ram_enable,
+                ref mut motor,
+                rumble, ..
+            } => {
+                match address {
+                    0x0000..=0x1FFF => {
+                        *ram_enable = (value & 0xF) == 0xA;
+                    },
+                    // The 9-bit bank number is wrapped to the ROM size on reads
+                    0x2000..=0x2FFF => {
+                        *rom_bank = (*rom_bank & 0x100) | value as usize;
+                    }
+                    0x3000..=0x3FFF => {
+                        *rom_bank = (*rom_bank & 0xFF) | (((value as usize) & 0x01) << 8);
+                    }
+                    0x4000..=0x5FFF if rumble => {
+                        *ram_bank = value & 0x7;
+                        *motor = (value & 0x8) != 0;
+                    }
+                    0x4000..=0x5FFF => {
+                            *ram_bank = value & 0xF;
+                    }
+                    _ => warn!("Unmapped write to {:04x} (Cart ROM, value={:02x})", address, value),
+                }
+            }
+            Cartridge::MMM01 {
+                ref mut mapped,
+                ref mut mode,
+                ref mut mode_locked,
+                ref mut ram_enable,
+                ref mut rom_bank,
+                ref mut ram_bank,
+                ref mut rom_mask,
+                ref mut ram_mask, ..
+            } => {
+                match address {
+                    0x0000..=0x1FFF => {
+                        *ram_enable = (value & 0xF) == 0xA;
+
+                        if !*mapped {
+                            *ram_mask = (value >> 4) & 0x3;
+                            *mapped = (value & 0x40) != 0;
+                        }
+                    }
+                    0x2000..=0x3FFF => {
+                        let writable = if *mapped {
+                            0x1F & !((*rom_mask as u16) << 1)
+                        } else {
+                            0x7F
+                        };
+
+                        *rom_bank = (*rom_bank & !writable) | (value as u16 & writable);
+                    }
+                    0x4000..=0x5FFF => {
+                        let writable = if *mapped { 0x3 & !*ram_mask } else { 0xF };
+
+                        *ram_bank = (*ram_bank & !writable) | (value & writable);
+
+                        if !*mapped {
+                            *rom_bank = (*rom_bank & 0x7F) | (((value as u16 >> 4) & 0x3) << 7);
+                            *mode_locked = (value & 0x40) != 0;
+                        }
+                    }
+                    0x6000..=0x7FFF => {
+                        if !*mode_locked {
+                            *mode = (value & 1) != 0;
+                        }
+
+                        if !*mapped {
+                            *rom_mask = (value >> 2) & 0xF;
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            Cartridge::MBC6 {
+                ref mut flash,
+                ref mut ram_enable,
+                ref mut flash_enable,
+                ref mut flash_write,
+                ref mut banks,
+                ref mut flash_mapped,
+                ref mut ram_banks, ..
+            } => {
+                match address {
+                    0x0000..=0x03FF => *ram_enable = (value & 0xF) == 0xA,
+                    0x0400..=0x07FF => ram_banks[0] = value & 0x7,
+                    0x0800..=0x0BFF => ram_banks[1] = value & 0x7,
+                    0x0C00..=0x0FFF if *flash_write => *flash_enable = (value & 1) != 0,
+                    0x1000..=0x1FFF => *flash_write = (value & 1) != 0,
+                    0x2000..=0x27FF => banks[0] = value,
+                    0x2800..=0x2FFF => flash_mapped[0] = value == 0x08,
+                    0x3000..=0x37FF => banks[1] = value,
+                    0x3800..=0x3FFF => flash_mapped[1] = value == 0x08,
+                    0x4000..=0x7FFF => {
+                        let window = (address >> 13) & 1;
+
+                        if flash_mapped[window] && *flash_enable && *flash_write {
+                            let bank = (banks[window] & 0x7F) as usize;
+
+                            flash.write((bank << 13) + (address & 0x1FFF), value);
+                        }
+                    }
+                    _ => warn!("Unmapped write to {:04x} (Cart ROM, value={:02x})", address, value),
+                }
+            }
+            Cartridge::MBC7 {
+                ref mut ram_enable,
+                ref mut ram_enable2,
+                ref mut rom_bank, ..
+            } => {
+                match address {
+                    0x0000..=0x1FFF => *ram_enable = value == 0x0A,
+                    0x2000..=0x3FFF => *rom_bank = value as usize,
+                    0x4000..=0x5FFF => *ram_enable2 = value == 0x40,
+                    _ => warn!("Unmapped write to {:04x} (Cart ROM, value={:02x})", address, value),
+                }
+            }
+            Cartridge::PocketCamera {
+                ref mut ram_enable,
+                ref mut camera_mapped,
+                ref mut rom_bank,
+                ref mut ram_bank, ..
+            } => {
+                match address {
+                    0x0000..=0x1FFF => *ram_enable = (value & 0xF) == 0xA,
+                    0x2000..=0x3FFF => *rom_bank = (value & 0x3F) as usize,
+                    0x4000..=0x5FFF => {
+                        *camera_mapped = (value & 0x10) != 0;
+                        *ram_bank = value & 0xF;
+                    }
+                    _ => warn!("Unmapped write to {:04x} (Cart ROM, value={:02x})", address, value),
+                }
+            }
+            Cartridge::HuC1 {
+                ref mut ir_mapped,
+                ref mut rom_bank,
+                ref mut ram_bank, ..
+            } => {
+                match address {
+                    // There is no RAM enable, anything but 0x0E maps the RAM
+                    0x0000..=0x1FFF => *ir_mapped = (value & 0xF) == 0xE,
+                    0x2000..=0x3FFF => {
+                        *rom_bank = if value & 0x3F == 0 { 1 } else { (value & 0x3F) as usize };
+                    }
+                    0x4000..=0x5FFF => *ram_bank = value & 0x3,
+                    _ => warn!("Unmapped write to {:04x} (Cart ROM, value={:02x})", address, value),
+                }
+            }
+            Cartridge::HuC3 {
+                ref mut mode,
+                ref mut rom_bank,
+                ref mut ram_bank, ..
+            } => {
+                match address {
+                    0x0000..=0x1FFF => *mode = value & 0xF,
+                    0x2000..=0x3FFF => {
+                        *rom_bank = if value & 0x7F == 0 { 1 } else { (value & 0x7F) as usize };
+                    }
+                    0x4000..=0x5FFF => *ram_bank = value & 0x3,
+                    _ => warn!("Unmapped write to {:04x} (Cart ROM, value={:02x})", address, value),
+                }
+            }
+        }
+    }
+
+    pub fn read_ram(&self, address: usize) -> u8 {
+        match *self {
+            Cartridge::NoCartridge |
+            Cartridge::RomOnly(_) => {
+                warn!("Unmapped read from {:04x} (Cart RAM)", address);
+                0xFF
+            },
+            Cartridge::MBC1 { ref ram, ram_enable, ram_bank, mode, .. } => {
+                let bank = if mode { ram_bank } else { 0 };
+
+                if ram_enable {
+                    ram.read(((bank as usize) << 13) + (address & 0x1FFF))
+                } else {
+                    0xFF
+                }
+            }
+            Cartridge::MBC2 { ref ram, ram_enable, .. } => {
+                // Only the low nibble exists
+                if ram_enable {
+                    ram.read(address & 0x1FF) | 0xF0
+                } else {
+                    0xFF
+                }
+            }
+            Cartridge::MBC3 { ref rtc, ram_enable, ram_bank, .. }
+            if ram_bank >= 0x08 => {
+                match *rtc {
+                    Some(ref rtc) if ram_enable => rtc.read(ram_bank),
+                    _ => 0xFF,
+                }
+            }
+            Cartridge::MBC3 { ref ram, ram_enable, ram_bank, .. } |
+            Cartridge::MBC5 { ref ram, ram_enable, ram_bank, .. } => {
+                if ram_enable {
+                    ram.read(((ram_bank as usize) << 13) + (address & 0x1FFF))
+                } else {
+                    0xFF
+                }
+            }
+            Cartridge::MMM01 { ref ram, ram_enable, ram_bank, mode, .. } => {
+                let bank = if mode { ram_bank } else { ram_bank & 0xC };
+
+                if ram_enable {
+                    ram.read(((bank as usize) << 13) + (address & 0x1FFF))
+                } else {
+                    0xFF
+                }
+            }
+            Cartridge::MBC6 { ref ram, ram_enable, ram_banks, .. } => {
+                let bank = ram_banks[(address >> 12) & 1] as usize;
+
+                if ram_enable {
+                    ram.read((bank << 12) + (address & 0xFFF))
+                } else {
+                    0xFF
+                }
+            }
+            Cartridge::MBC7 { ref mbc7, ram_enable, ram_enable2, .. } => {
+                if ram_enable && ram_enable2 && address < 0xB000 {
+                    mbc7.read(address)
+                } else {
+                    0xFF
+                }
+            }
+            // The RAM can be read without being enabled, but not
+            // while the camera writes to it
+            Cartridge::PocketCamera { ref ram, ref camera, camera_mapped, ram_bank, .. } => {
+                if camera_mapped {
+                    camera.read(address)
+                } else if camera.busy() {
+                    0x00
+                } else {
+                    ram.read(((ram_bank as usize) << 13) + (address & 0x1FFF))
+                }
+            }
+            Cartridge::HuC1 { ref ram, ir_mapped, ir_light, ram_bank, .. } => {
+                if ir_mapped {
+                    0xC0 | (ir_light as u8)
+                } else {
+                    ram.read(((ram_bank as usize) << 13) + (address & 0x1FFF))
+                }
+            }
+            // The RAM is read-only in mode 0, and writable in mode 0x0A
+            Cartridge::HuC3 { ref ram, ref huc3, mode, ram_bank, .. } => {
+                match mode {
+                    0x00 | 0x0A => ram.read(((ram_bank as usize) << 13) + (address & 0x1FFF)),
+                    _ => huc3.read(mode),
+                }
+            }
+        }
+    }
+
+    pub fn write_ram(&mut self, address: usize, value: u8) {
+        match *self {
+            Cartridge::NoCartridge |
+            Cartridge::RomOnly(_) =>
+                warn!("Unmapped write to {:04x} (Cart RAM, value={:02x})", address, value),
+            Cartridge::MBC1 { ref mut ram, ram_enable, ram_bank, mode, .. } => {
+                let bank = if mode { ram_bank } else { 0 };
+
+                if ram_enable {
+                    ram.write(((bank as usize) << 13) + (address & 0x1FFF), value);
+                }
+            }
+            Cartridge::MBC2 { ref mut ram, ram_enable, .. } => {
+                if ram_enable {
+                    ram.write(address & 0x1FF, value & 0xF);
+                }
+            }
+            Cartridge::MBC3 { ref mut ram, ref mut rtc, ram_enable, ram_bank, .. }
+            if ram_bank >= 0x08 => {
+                if let Some(ref mut rtc) = *rtc {
+                    if ram_enable {
+                        rtc.write(ram_bank, value);
+                        ram.set_footer(rtc.footer());
+                    }
+                }
+            }
+            Cartridge::MBC3 { ref mut ram, ram_enable, ram_bank, .. } |
+            Cartridge::MBC5 { ref mut ram, ram_enable, ram_bank, .. } => {
+                if ram_enable {
+                    ram.write(((ram_bank as usize) << 13) + (address & 0x1FFF), value);
+                }
+            }
+            Cartridge::MMM01 { ref mut ram, ram_enable, ram_bank, mode, .. } => {
+                let bank = if mode { ram_bank } else { ram_bank & 0xC };
+
+                if ram_enable {
+                    ram.write(((bank as usize) << 13) + (address & 0x1FFF), value);
+                }
+            }
+            Cartridge::MBC6 { ref mut ram, ram_enable, ram_banks, .. } => {
+                let bank = ram_banks[(address >> 12) & 1] as usize;
+
+                if ram_enable {
+                    ram.write((bank << 12) + (address & 0xFFF), value);
+                }
+            }
+            Cartridge::MBC7 { ref mut mbc7, ram_enable, ram_enable2, .. } => {
+                if ram_enable && ram_enable2 && address < 0xB000 {
+                    mbc7.write(address, value);
+                }
+            }
+            Cartridge::PocketCamera {
+                ref mut ram, ref mut camera, ram_enable, camera_mapped, ram_bank, ..
+            } => {
+                if camera_mapped {
+                    camera.write(address, value);
+                } else if ram_enable && !camera.busy() {
+                    ram.write(((ram_bank as usize) << 13) + (address & 0x1FFF), value);
+                }
+            }
+            Cartridge::HuC1 { ref mut ram, ref mut ir_led, ir_mapped, ram_bank, .. } => {
+                if ir_mapped {
+                    *ir_led = (value & 1) != 0;
+                } else {
+                    ram.write(((ram_bank as usize) << 13) + (address & 0x1FFF), value);
+                }
+            }
+            Cartridge::HuC3 { ref mut ram, ref mut huc3, mode, ram_bank, .. } => {
+                match mode {
+                    0x00 => { },
+                    0x0A => ram.write(((ram_bank as usize) << 13) + (address & 0x1FFF), value),
+                    _ => {
+                        huc3.write(mode, value);
+                        ram.set_footer(huc3.footer());
+                    }
+                }
+            }
+        }
+    }
+
+    fn save_ram(&mut self) -> Option<&mut SaveRam> {
+        match *self {
+            Cartridge::NoCartridge | Cartridge::RomOnly(_) => None,
+            Cartridge::MBC1 { ref mut ram, .. } |
+            Cartridge::MBC2 { ref mut ram, .. } |
+            Cartridge::MBC3 { ref mut ram, .. } |
+            Cartridge::MBC5 { ref mut ram, .. } |
+            Cartridge::MMM01 { ref mut ram, .. } |
+            Cartridge::MBC6 { ref mut ram, .. } |
+            Cartridge::PocketCamera { ref mut ram, .. } |
+            Cartridge::HuC1 { ref mut ram, .. } |
+            Cartridge::HuC3 { ref mut ram, .. } => Some(ram),
+            Cartridge::MBC7 { ref mut mbc7, .. } => Some(mbc7.save_ram()),
+        }
+    }
+
+    /// T-cycles taken by the camera capture the game has just
+    /// started, if any
+    pub fn take_camera_capture(&mut self) -> Option<u64> {
+        match *self {
+            Cartridge::PocketCamera { ref mut camera, .. } => camera.take_capture(),
+            _ => None,
+        }
+    }
+
+    pub fn finish_camera_capture(&mut self, image: &[u8]) {
+        if let Cartridge::PocketCamera { ref mut camera, ref mut ram, .. } = *self {
+            camera.finish_capture(image, ram);
+        }
+    }
+
+    /// Whether the game can currently read the infrared sensor
+    pub fn infrared_mapped(&self) -> bool {
+        matches!(*self, Cartridge::HuC1 { ir_mapped: true, .. } |
+                        Cartridge::HuC3 { mode: 0x0E, .. })
+    }
+
+    /// State of the infrared LED, if the cartridge has one
+    pub fn infrared_led(&self) -> bool {
+        match *self {
+            Cartridge::HuC1 { ir_led, .. } => ir_led,
+            Cartridge::HuC3 { ref huc3, .. } => huc3.ir_led(),
+            _ => false,
+        }
+    }
+
+    pub fn set_infrared_light(&mut self, light: bool) {
+        match *self {
+            Cartridge::HuC1 { ref mut ir_light, .. } => *ir_light = light,
+            Cartridge::HuC3 { ref mut huc3, .. } => huc3.set_ir_light(light),
+            _ => { },
+        }
+    }
+
+    /// Frequency of the tone the HuC3 has just started, if any
+    pub fn take_tone(&mut self) -> Option<u32> {
+        match *self {
+            Cartridge::HuC3 { ref mut huc3, .. } => huc3.take_tone(),
+            _ => None,
+        }
+    }
+
+    /// Analog input, used by the MBC7 accelerometer
+    pub fn set_axis(&mut self, axis: Axis, value: i16) {
+        if let Cartridge::MBC7 { ref mut mbc7, .. } = *self {
+            mbc7.set_tilt(axis, value);
+        }
+    }
+
+    /// Called once per frame, writes the savefile once the game
+    /// has stopped writing to the RAM for a while.
+    pub fn tick_savefile(&mut self) {
+        if let Some(ram) = self.save_ram() {
+            ram.tick();
+        }
+
+        if let Cartridge::MBC6 { ref mut flash, .. } = *self {
+            flash.save_ram().tick();
+        }
+    }
+
+    /// State of the rumble motor, if the cartridge has one
+    pub fn motor(&self) -> bool {
+        matches!(*self, Cartridge::MBC5 { motor: true, .. })
+    }
+
+    pub fn flush_savefile(&mut self) {
+        if let Some(ram) = self.save_ram() {
+            ram.flush();
+        }
+
+        if let Cartridge::MBC6 { ref mut flash, .. } = *self {
+            flash.save_ram().flush();
+        }
+    }
+}
+
+/// MBC1M multicarts are 1MB, with a second game header in bank 0x10
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    rom.len() == 0x100000 && rom[0x104..0x134] == rom[0x40104..0x40134]
+}
+
+/// Cartridge RAM, backed by a savefile if the cartridge has a battery.
+#[derive(Clone)]
+pub struct SaveRam {
+    data: Vec<u8>,
+    // Extra data stored after the RAM in the savefile, like the RTC
+    footer: Vec<u8>,
+    save_filename: Option<String>,
+
+    dirty: bool,
+    idle_frames: u32,
+    dirty_frames: u32,
+}
+
+impl SaveRam {
+    pub fn new(filename: &str, size: usize, battery: bool) -> SaveRam {
+        SaveRam::open(format!("{}.sav", filename), size, 0x00, battery)
+    }
+
+    /// Battery-backed flash memory, blank chips read as 0xFF
+    pub fn erased(save_filename: &str, size: usize) -> SaveRam {
+        SaveRam::open(save_filename.to_string(), size, 0xFF, true)
+    }
+
+    fn open(save_filename: String, size: usize, fill: u8, battery: bool) -> SaveRam {
+        let mut data = vec![fill; size];
+        let mut footer = Vec::new();
+
+        if battery {
+            match fs::read(&save_filename) {
+                Ok(save) => {
+                    let len = save.len().min(size);
+                    data[..len].copy_from_slice(&save[..len]);
+                    footer.extend_from_slice(&save[len..]);
+
+                    if save.len() < size {
+                        warn!("Savefile is smaller than the cartridge RAM");
+                    }
+
+                    info!("Savefile loaded!");
+                }
+                Err(e) => info!("No savefile loaded : {}", e),
+            }
+        }
+
+        SaveRam {
+            data,
+            footer,
+            save_filename: if battery { Some(save_filename) } else { None },
+
+            dirty: false,
+            idle_frames: 0,
+            dirty_frames: 0,
+        }
+    }
+
+    #[inline]
+    pub fn read(&self, address: usize) -> u8 {
+        if self.data.is_empty() {
+            0xFF
+        } else {
+            self.data[address & (self.data.len() - 1)]
+        }
+    }
+
+    #[inline]
+    pub fn write(&mut self, address: usize, value: u8) {
+        if !self.data.is_empty() {
+            let len = self.data.len();
+            self.data[address & (len - 1)] = value;
+
+            self.dirty = true;
+            self.idle_frames = 0;
+        }
+    }
+
+    pub fn footer(&self) -> &[u8] {
+        &self.footer
+    }
+
+    /// Replaces the data stored after the RAM, and schedules a write
+    /// of the savefile.
+    pub fn set_footer(&mut self, footer: Vec<u8>) {
+        if footer != self.footer {
+            self.footer = footer;
+
+            self.dirty = true;
+            self.idle_frames = 0;
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if self.dirty {
+            self.idle_frames += 1;
+            self.dirty_frames += 1;
+
+            if self.idle_frames >= SAVE_IDLE_FRAMES || self.dirty_frames >= SAVE_MAX_FRAMES {
+                self.flush();
+            }
+        }
+    }
+
+    /// Writes the savefile if the RAM changed since the last write.
+    /// The data is written to a temporary file which is then renamed,
+    /// so the savefile is never left half-written.
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+
+        self.dirty = false;
+        self.idle_frames = 0;
+        self.dirty_frames = 0;
+
+        if let Some(ref save_filename) = self.save_filename {
+            info!("Writing savefile!");
+
+            let tmp_filename = format!("{}.tmp", save_filename);
+            let result = fs::File::create(&tmp_filename)
+                .and_then(|mut file| {
+                    file.write_all(&self.data)?;
+                    file.write_all(&self.footer)?;
+                    file.sync_all()
+                })
+                .and_then(|_| fs::rename(&tmp_filename, save_filename));
+
+            if let Err(e) = result {
+                warn!("Couldn't save to savefile : {}", e);
+            }
+        }
+    }
+}
+
+impl Savestate for SaveRam {
+    fn save(&self, state: &mut StateWriter) {
+        self.data.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        let mut data = Vec::new();
+        data.load(state)?;
+
+        if data.len() != self.data.len() {
+            return Err("Savestate has a different cartridge RAM size");
+        }
+
+        if data != self.data {
+            self.data = data;
+            self.dirty = true;
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of 16KB ROM banks, from the cartridge header
+fn rom_banks(rom: &[u8]) -> usize {
+    let banks = match rom[0x148] {
+        code @ 0x00..=0x08 => 2 << code,
+        0x52 => 72,
+        0x53 => 80,
+        0x54 => 96,
+        code => {
+            warn!("Unknown ROM size {:02x} in the header", code);
+            rom.len() >> 14
+        }
+    };
+
+    if banks << 14 > rom.len() {
+        warn!("The ROM is smaller than its header says");
+        rom.len() >> 14
+    } else {
+        banks
+    }
+}
+
+/// RAM size, from the cartridge header
+fn ram_size(code: u8) -> usize {
+    match code {
+        0x01 => 0x800,
+        0x02 => 0x2000,
+        0x03 => 0x8000,
+        0x04 => 0x20000,
+        0x05 => 0x10000,
+        _ => 0,
+    }
+}
+
+impl Cartridge {
+    fn state_tag(&self) -> u8 {
+        match *self {
+            Cartridge::NoCartridge => 0,
+            Cartridge::RomOnly(_) => 1,
+            Cartridge::MBC1 { .. } => 2,
+            Cartridge::MBC3 { .. } => 3,
+            Cartridge::MBC5 { .. } => 4,
+            Cartridge::MBC2 { .. } => 5,
+            Cartridge::MMM01 { .. } => 6,
+            Cartridge::MBC6 { .. } => 7,
+            Cartridge::MBC7 { .. } => 8,
+            Cartridge::PocketCamera { .. } => 9,
+            Cartridge::HuC1 { .. } => 10,
+            Cartridge::HuC3 { .. } => 11,
+        }
+    }
+}
+
+impl Savestate for Cartridge {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.state_tag());
+
+        match *self {
+            Cartridge::NoCartridge | Cartridge::RomOnly(_) => { },
+            Cartridge::MBC1 { ref ram, mode, ram_enable, rom_bank, ram_bank, .. } => {
+                ram.save(state);
+                mode.save(state);
+                ram_enable.save(state);
+                rom_bank.save(state);
+                ram_bank.save(state);
+            }
+            Cartridge::MBC3 { ref ram, ref rtc, ram_enable, rom_bank, ram_bank, .. } => {
+                ram.save(state);
+                rtc.save(state);
+                ram_enable.save(state);
+                rom_bank.save(state);
+                ram_bank.save(state);
+            }
+            Cartridge::MBC5 { ref ram, ram_enable, rom_bank, ram_bank, motor, .. } => {
+                ram.save(state);
+                ram_enable.save(state);
+                rom_bank.save(state);
+                ram_bank.save(state);
+                motor.save(state);
+            }
+            Cartridge::MBC2 { ref ram, ram_enable, rom_bank, .. } => {
+                ram.save(state);
+                ram_enable.save(state);
+                rom_bank.save(state);
+            }
+            Cartridge::MMM01 {
+                ref ram, mapped, mode, mode_locked, ram_enable,
+                rom_bank, ram_bank, rom_mask, ram_mask, ..
+            } => {
+                ram.save(state);
+                mapped.save(state);
+                mode.save(state);
+                mode_locked.save(state);
+                ram_enable.save(state);
+                rom_bank.save(state);
+                ram_bank.save(state);
+                rom_mask.save(state);
+                ram_mask.save(state);
+            }
+            Cartridge::MBC6 {
+                ref ram, ref flash, ram_enable, flash_enable, flash_write,
+                banks, flash_mapped, ram_banks, ..
+            } => {
+                ram.save(state);
+                flash.save(state);
+                ram_enable.save(state);
+                flash_enable.save(state);
+                flash_write.save(state);
+                banks.save(state);
+                flash_mapped.save(state);
+                ram_banks.save(state);
+            }
+            Cartridge::MBC7 { ref mbc7, ram_enable, ram_enable2, rom_bank, .. } => {
+                mbc7.save(state);
+                ram_enable.save(state);
+                ram_enable2.save(state);
+                rom_bank.save(state);
+            }
+            Cartridge::PocketCamera {
+                ref ram, ref camera, ram_enable, camera_mapped, rom_bank, ram_bank, ..
+            } => {
+                ram.save(state);
+                camera.save(state);
+                ram_enable.save(state);
+                camera_mapped.save(state);
+                rom_bank.save(state);
+                ram_bank.save(state);
+            }
+            Cartridge::HuC1 {
+                ref ram, ir_mapped, ir_led, ir_light, rom_bank, ram_bank, ..
+            } => {
+                ram.save(state);
+                ir_mapped.save(state);
+                ir_led.save(state);
+                ir_light.save(state);
+                rom_bank.save(state);
+                ram_bank.save(state);
+            }
+            Cartridge::HuC3 { ref ram, ref huc3, mode, rom_bank, ram_bank, .. } => {
+                ram.save(state);
+                huc3.save(state);
+                mode.save(state);
+                rom_bank.save(state);
+                ram_bank.save(state);
+            }
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        if state.read_u8()? != self.state_tag() {
+            return Err("Savestate is for another cartridge type");
+        }
+
+        match *self {
+            Cartridge::NoCartridge | Cartridge::RomOnly(_) => { },
+            Cartridge::MBC1 {
+                ref mut ram, ref mut mode, ref mut ram_enable,
+                ref mut rom_bank, ref mut ram_bank, ..
+            } => {
+                ram.load(state)?;
+                mode.load(state)?;
+                ram_enable.load(state)?;
+                rom_bank.load(state)?;
+                ram_bank.load(state)?;
+            }
+            Cartridge::MBC3 {
+                ref mut ram, ref mut rtc, ref mut ram_enable,
+                ref mut rom_bank, ref mut ram_bank, ..
+            } => {
+                ram.load(state)?;
+                rtc.load(state)?;
+                ram_enable.load(state)?;
+                rom_bank.load(state)?;
+                ram_bank.load(state)?;
+
+                if let Some(ref mut rtc) = *rtc {
+                    ram.set_footer(rtc.footer());
+                }
+            }
+            Cartridge::MBC5 {
+                ref mut ram, ref mut ram_enable,
+                ref mut rom_bank, ref mut ram_bank, ref mut motor, ..
+            } => {
+                ram.load(state)?;
+                ram_enable.load(state)?;
+                rom_bank.load(state)?;
+                ram_bank.load(state)?;
+                motor.load(state)?;
+            }
+            Cartridge::MBC2 {
+                ref mut ram, ref mut ram_enable, ref mut rom_bank, ..
+            } => {
+                ram.load(state)?;
+                ram_enable.load(state)?;
+                rom_bank.load(state)?;
+            }
+            Cartridge::MMM01 {
+                ref mut ram, ref mut mapped, ref mut mode, ref mut mode_locked,
+                ref mut ram_enable, ref mut rom_bank, ref mut ram_bank,
+                ref mut rom_mask, ref mut ram_mask, ..
+            } => {
+                ram.load(state)?;
+                mapped.load(state)?;
+                mode.load(state)?;
+                mode_locked.load(state)?;
+                ram_enable.load(state)?;
+                rom_bank.load(state)?;
+                ram_bank.load(state)?;
+                rom_mask.load(state)?;
+                ram_mask.load(state)?;
+            }
+            Cartridge::MBC6 {
+                ref mut ram, ref mut flash, ref mut ram_enable, ref mut flash_enable,
+                ref mut flash_write, ref mut banks, ref mut flash_mapped,
+                ref mut ram_banks, ..
+            } => {
+                ram.load(state)?;
+                flash.load(state)?;
+                ram_enable.load(state)?;
+                flash_enable.load(state)?;
+                flash_write.load(state)?;
+                banks.load(state)?;
+                flash_mapped.load(state)?;
+                ram_banks.load(state)?;
+            }
+            Cartridge::MBC7 {
+                ref mut mbc7, ref mut ram_enable, ref mut ram_enable2,
+                ref mut rom_bank, ..
+            } => {
+                mbc7.load(state)?;
+                ram_enable.load(state)?;
+                ram_enable2.load(state)?;
+                rom_bank.load(state)?;
+            }
+            Cartridge::PocketCamera {
+                ref mut ram, ref mut camera, ref mut ram_enable,
+                ref mut camera_mapped, ref mut rom_bank, ref mut ram_bank, ..
+            } => {
+                ram.load(state)?;
+                camera.load(state)?;
+                ram_enable.load(state)?;
+                camera_mapped.load(state)?;
+                rom_bank.load(state)?;
+                ram_bank.load(state)?;
+            }
+            Cartridge::HuC1 {
+                ref mut ram, ref mut ir_mapped, ref mut ir_led, ref mut ir_light,
+                ref mut rom_bank, ref mut ram_bank, ..
+            } => {
+                ram.load(state)?;
+                ir_mapped.load(state)?;
+                ir_led.load(state)?;
+                ir_light.load(state)?;
+                rom_bank.load(state)?;
+                ram_bank.load(state)?;
+            }
+            Cartridge::HuC3 {
+                ref mut ram, ref mut huc3, ref mut mode,
+                ref mut rom_bank, ref mut ram_bank, ..
+            } => {
+                ram.load(state)?;
+                huc3.load(state)?;
+                mode.load(state)?;
+                rom_bank.load(state)?;
+                ram_bank.load(state)?;
+
+                ram.set_footer(huc3.footer());
+            }
+        }
+
+        Ok(())
+    }
+}
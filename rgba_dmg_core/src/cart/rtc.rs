@@ -0,0 +1,208 @@
+// rtc.rs ---
+//
+// Filename: rtc.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 19:12:40 2026 (+0200)
+// Last-Updated: Sun Oct 18 23:03:41 2026 (+0200)
+//           By: Louise <louise>
+//
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rgba_common::savestate;
+
+const SECONDS: usize = 0;
+const MINUTES: usize = 1;
+const HOURS: usize = 2;
+const DAY_LOW: usize = 3;
+const DAY_HIGH: usize = 4;
+
+const DH_DAY: u8 = 0x01;
+const DH_HALT: u8 = 0x40;
+const DH_CARRY: u8 = 0x80;
+
+// Bits actually present in each register
+const MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+
+/// Size of the RTC footer appended to the savefile, in the format
+/// used by BGB, VBA-M and most other emulators : the live and
+/// latched registers as 32-bit words, then a 64-bit UNIX timestamp.
+pub const FOOTER_SIZE: usize = 48;
+
+/// MBC3 real-time clock. It follows the wall clock, so the time
+/// keeps going while the emulator is closed.
+#[derive(Clone, Default)]
+pub struct Rtc {
+    regs: [u8; 5],
+    latched: [u8; 5],
+
+    // UNIX time at which regs were up to date
+    timestamp: u64,
+    latch_armed: bool,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            timestamp: unix_time(),
+            ..Default::default()
+        }
+    }
+
+    /// Builds the RTC from a savefile footer, falls back to a
+    /// fresh clock if there is none.
+    pub fn from_footer(footer: &[u8]) -> Rtc {
+        let mut rtc = Rtc::new();
+
+        // Some emulators write a 32-bit timestamp instead
+        if footer.len() != FOOTER_SIZE && footer.len() != FOOTER_SIZE - 4 {
+            if !footer.is_empty() {
+                warn!("Unknown RTC footer size ({} bytes), ignoring it", footer.len());
+            }
+
+            return rtc;
+        }
+
+        let word = |i: usize| u32::from_le_bytes([
+            footer[i * 4], footer[i * 4 + 1], footer[i * 4 + 2], footer[i * 4 + 3]
+        ]);
+
+        for (i, mask) in MASKS.iter().enumerate() {
+            rtc.regs[i] = word(i) as u8 & mask;
+            rtc.latched[i] = word(i + 5) as u8 & mask;
+        }
+
+        rtc.timestamp = if footer.len() == FOOTER_SIZE {
+            u64::from(word(10)) | (u64::from(word(11)) << 32)
+        } else {
+            u64::from(word(10))
+        };
+
+        info!("RTC loaded from savefile");
+
+        rtc
+    }
+
+    pub fn footer(&mut self) -> Vec<u8> {
+        self.update();
+
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+
+        for &r in self.regs.iter().chain(self.latched.iter()) {
+            footer.extend_from_slice(&u32::from(r).to_le_bytes());
+        }
+
+        footer.extend_from_slice(&self.timestamp.to_le_bytes());
+        footer
+    }
+
+    /// Register selected by a RAM bank value between 0x08 and 0x0C.
+    pub fn read(&self, reg: u8) -> u8 {
+        let reg = (reg - 0x08) as usize;
+
+        self.latched[reg] | !MASKS[reg]
+    }
+
+    pub fn write(&mut self, reg: u8, value: u8) {
+        let reg = (reg - 0x08) as usize;
+
+        self.update();
+        self.regs[reg] = value & MASKS[reg];
+        self.latched[reg] = value & MASKS[reg];
+    }
+
+    /// Writing 0 then 1 copies the running clock to the registers
+    /// the game reads.
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 1 {
+            self.update();
+            self.latched = self.regs;
+        }
+
+        self.latch_armed = value == 0;
+    }
+
+    fn update(&mut self) {
+        let now = unix_time();
+
+        if self.regs[DAY_HIGH] & DH_HALT == 0 && now > self.timestamp {
+            self.advance(now - self.timestamp);
+        }
+
+        self.timestamp = now;
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        // Out of range values wrap on their own width without
+        // carrying, so tick them one by one until they are back
+        while seconds > 0 && !self.in_range() {
+            self.tick();
+            seconds -= 1;
+        }
+
+        if seconds == 0 {
+            return;
+        }
+
+        let day = self.day();
+        let total = u64::from(self.regs[SECONDS])
+            + u64::from(self.regs[MINUTES]) * 60
+            + u64::from(self.regs[HOURS]) * 3600
+            + u64::from(day) * 86400
+            + seconds;
+
+        self.regs[SECONDS] = (total % 60) as u8;
+        self.regs[MINUTES] = (total / 60 % 60) as u8;
+        self.regs[HOURS] = (total / 3600 % 24) as u8;
+
+        let days = total / 86400;
+        if days >= 512 {
+            self.regs[DAY_HIGH] |= DH_CARRY;
+        }
+
+        self.set_day((days % 512) as u16);
+    }
+
+    fn tick(&mut self) {
+        self.regs[SECONDS] = (self.regs[SECONDS] + 1) & MASKS[SECONDS];
+        if self.regs[SECONDS] != 60 { return; }
+
+        self.regs[SECONDS] = 0;
+        self.regs[MINUTES] = (self.regs[MINUTES] + 1) & MASKS[MINUTES];
+        if self.regs[MINUTES] != 60 { return; }
+
+        self.regs[MINUTES] = 0;
+        self.regs[HOURS] = (self.regs[HOURS] + 1) & MASKS[HOURS];
+        if self.regs[HOURS] != 24 { return; }
+
+        self.regs[HOURS] = 0;
+
+        let day = self.day() + 1;
+        if day == 512 {
+            self.regs[DAY_HIGH] |= DH_CARRY;
+        }
+
+        self.set_day(day % 512);
+    }
+
+    fn in_range(&self) -> bool {
+        self.regs[SECONDS] < 60 && self.regs[MINUTES] < 60 && self.regs[HOURS] < 24
+    }
+
+    fn day(&self) -> u16 {
+        u16::from(self.regs[DAY_LOW]) | (u16::from(self.regs[DAY_HIGH] & DH_DAY) << 8)
+    }
+
+    fn set_day(&mut self, day: u16) {
+        self.regs[DAY_LOW] = day as u8;
+        self.regs[DAY_HIGH] = (self.regs[DAY_HIGH] & !DH_DAY) | ((day >> 8) as u8 & DH_DAY);
+    }
+}
+
+savestate!(Rtc, regs, latched, timestamp, latch_armed);
+
+pub(super) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -7,6 +7,8 @@
 //           By: Louise <ludwigette>
 // 
 use crate::Interconnect;
+use crate::Model;
+use rgba_common::savestate;
 use std::fmt;
 
 #[derive(Debug)]
@@ -32,6 +34,8 @@ pub struct LR35902 {
     halt: bool,
 }
 
+savestate!(LR35902, a, b, c, d, e, h, l, zero, sub, half, carry, sp, pc, ime, halt);
+
 impl LR35902 {
     pub fn new() -> LR35902 {
         LR35902 {
@@ -63,6 +67,57 @@ impl LR35902 {
         self.halt = false;
     }
 
+    /// Sets the registers as the bootrom of the given model leaves
+    /// them, and starts at the cartridge entry point.
+    pub fn skip_bootrom(&mut self, model: Model, io: &Interconnect) {
+        if model.is_cgb() {
+            self.set_af(0x1180);
+
+            if io.cgb() {
+                self.set_bc(0x0000);
+                self.set_de(0xFF56);
+                self.set_hl(0x000D);
+            } else {
+                // The CGB bootrom leaves the title checksum in B
+                // for the games it knows a palette for
+                let old_licensee = io.read_u8(0x14B);
+                let nintendo = (old_licensee == 0x01) ||
+                    ((old_licensee == 0x33) &&
+                     (io.read_u8(0x144) == b'0') && (io.read_u8(0x145) == b'1'));
+                let b = if nintendo {
+                    (0x134..=0x143).fold(0u8, |acc, a| acc.wrapping_add(io.read_u8(a)))
+                } else {
+                    0
+                };
+
+                self.set_bc((b as u16) << 8);
+                self.set_de(0x0008);
+                self.set_hl(if b == 0x43 || b == 0x58 { 0x991A } else { 0x007C });
+            }
+
+            // The AGB bootrom ends with an INC B
+            if model == Model::AGB {
+                let b = self.inc_u8(self.b);
+                self.b = b;
+            }
+        } else {
+            self.a = if model == Model::MGB { 0xFF } else { 0x01 };
+
+            // H and C are only cleared if the header checksum is 0
+            let checksum = io.read_u8(0x14D) != 0;
+            self.set_f(0x80 | if checksum { 0x30 } else { 0x00 });
+
+            self.set_bc(0x0013);
+            self.set_de(0x00D8);
+            self.set_hl(0x014D);
+        }
+
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+        self.ime = false;
+        self.halt = false;
+    }
+
     #[inline]
     /// Returns the Program Counter
     pub fn pc(&self) -> usize { self.pc as usize }
@@ -536,10 +591,16 @@ impl LR35902 {
     }
 
     pub fn step(&mut self, io: &mut Interconnect) {
+        // Interrupts wait for the clock to settle
+        if io.is_switching_speed() {
+            io.step_speed_switch();
+            return;
+        }
+
         if !self.halt {
             self.next_instruction(io);
         } else {
-            io.delay(1);
+            io.skip_to_next_event();
         }
 
         if let Some(it) = io.next_interrupt() {
@@ -595,7 +656,13 @@ impl LR35902 {
                 self.carry = (self.a & 0x80) != 0;
             }
 
-            0x10 => { }
+            0x10 => {
+                // Only the CGB speed switch is emulated, STOP is a
+                // NOP otherwise
+                if io.speed_switch_armed() {
+                    io.switch_speed();
+                }
+            }
             0x11 => { let de = self.next_u16(io); self.set_de(de); },
             0x12 => {
                 let de = self.de();
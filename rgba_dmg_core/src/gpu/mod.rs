@@ -7,6 +7,7 @@
 //           By: Louise <ludwigette>
 //
 use std::cmp::Ordering;
+use rgba_common::{savestate, Savestate, StateWriter, StateReader};
 
 mod render_dmg;
 mod render_cgb;
@@ -63,6 +64,9 @@ pub struct GPU {
 
     // CGB stuff
     vram_bank: u8,
+    // OPRI, sprites are ordered by X like on the DMG instead of
+    // by OAM index
+    coordinate_priority: bool,
 
     // Interrupts
     it_vblank: bool,
@@ -71,6 +75,16 @@ pub struct GPU {
     has_hblank: bool,
 }
 
+savestate!(GPU,
+           framebuffer, vram, oam, line_cache, render_line, frame_done,
+           mode, clock, ly, lyc, scy, scx, wy, wx,
+           display_enable, window_map, window_enable, tile_data,
+           bg_map, obj_size, obj_enable, bg_enable,
+           coincidence_irq, mode2_irq, mode1_irq, mode0_irq,
+           bgp, obp0, obp1,
+           bcpi, bcp_inc, bcpd, ocpi, ocp_inc, ocpd,
+           vram_bank, coordinate_priority, it_vblank, it_lcd, has_hblank);
+
 impl GPU {
     pub fn new() -> GPU {
         GPU {
@@ -122,6 +136,8 @@ impl GPU {
             
             // CGB stuff
             vram_bank: 0,
+            // Sprites are ordered by X unless a CGB says otherwise
+            coordinate_priority: true,
 
             // Interrupts
             it_vblank: false,
@@ -161,69 +177,75 @@ impl GPU {
         }
     }
     
-    pub fn spend_cycles(&mut self, cycles: u32) {
-        if !self.display_enable {
-            return;
-        }
-        
-        self.clock += cycles;
+    /// Cycles left before the next mode change
+    pub fn cycles_to_next_mode(&self) -> u32 {
+        let end = match self.mode {
+            GpuMode::ReadingOAM => 80,
+            GpuMode::ReadingVRAM => 252,
+            GpuMode::HBlank | GpuMode::VBlank => 456,
+        };
+
+        end - self.clock.min(end)
+    }
+
+    #[inline]
+    pub fn display_enabled(&self) -> bool { self.display_enable }
 
+    /// Goes to the next mode (or the next line in VBlank), and
+    /// returns the number of cycles until the next change.
+    pub fn next_mode(&mut self) -> u32 {
         match self.mode {
             GpuMode::HBlank => {
-                if self.clock >= 456 {
-                    self.increment_line();
-                    self.clock -= 456;
-                    
-                    if self.ly == 144 {
-                        self.it_vblank = true;
-
-                        if self.mode1_irq {
-                            self.it_lcd = true;
-                        }
-                        
-                        self.mode = GpuMode::VBlank;
-                    } else {
-                        if self.mode2_irq {
-                            self.it_lcd = true;
-                        }
-                        
-                        self.mode = GpuMode::ReadingOAM;
+                self.increment_line();
+                self.clock = 0;
+
+                if self.ly == 144 {
+                    self.it_vblank = true;
+
+                    if self.mode1_irq {
+                        self.it_lcd = true;
+                    }
+
+                    self.mode = GpuMode::VBlank;
+                } else {
+                    if self.mode2_irq {
+                        self.it_lcd = true;
                     }
+
+                    self.mode = GpuMode::ReadingOAM;
                 }
             },
 
             GpuMode::VBlank => {
-                if self.clock >= 456 {
-                    self.increment_line();
-                    self.clock -= 456;
-
-                    if self.ly == 0 {
-                        if self.mode2_irq {
-                            self.it_lcd = true;
-                        }
-                        
-                        self.mode = GpuMode::ReadingOAM;
+                self.increment_line();
+                self.clock = 0;
+
+                if self.ly == 0 {
+                    if self.mode2_irq {
+                        self.it_lcd = true;
                     }
+
+                    self.mode = GpuMode::ReadingOAM;
                 }
             },
 
             GpuMode::ReadingOAM => {
-                if self.clock >= 80 {
-                    self.mode = GpuMode::ReadingVRAM;
-                }
+                self.clock = 80;
+                self.mode = GpuMode::ReadingVRAM;
             },
 
             GpuMode::ReadingVRAM => {
-                if self.clock >= 252 {
-                    if self.mode0_irq {
-                        self.it_lcd = true;
-                    }
-
-                    self.has_hblank = true;
-                    self.mode = GpuMode::HBlank;
+                if self.mode0_irq {
+                    self.it_lcd = true;
                 }
+
+                self.clock = 252;
+                self.has_hblank = true;
+                self.mode = GpuMode::HBlank;
             }
         }
+
+        self.cycles_to_next_mode()
     }
 
     #[inline]
@@ -281,11 +303,19 @@ impl GPU {
     pub fn set_vbk(&mut self, vbk: u8) { self.vram_bank = vbk & 1; }
 
     #[inline]
-    pub fn bcpi(&self) -> u8 { self.bcpi as u8 }
+    pub fn opri(&self) -> u8 { 0xFE | (self.coordinate_priority as u8) }
+    #[inline]
+    pub fn set_opri(&mut self, opri: u8) {
+        self.coordinate_priority = (opri & 1) != 0;
+        self.rebuild_cache();
+    }
+
+    #[inline]
+    pub fn bcpi(&self) -> u8 { ((self.bcp_inc as u8) << 7) | 0x40 | self.bcpi as u8 }
     #[inline]
     pub fn set_bcpi(&mut self, bcpi: u8) {
         self.bcp_inc = (bcpi & 0x80) != 0;
-        self.bcpi = (bcpi & 0x7f) as usize;
+        self.bcpi = (bcpi & 0x3f) as usize;
     }
 
     #[inline]
@@ -297,16 +327,16 @@ impl GPU {
         self.bcpd[self.bcpi >> 3][(self.bcpi & 0x7) >> 1].write(self.bcpi, value);
 
         if self.bcp_inc {
-            self.bcpi += 1;
+            self.bcpi = (self.bcpi + 1) & 0x3f;
         }
     }
 
     #[inline]
-    pub fn ocpi(&self) -> u8 { self.bcpi as u8 }
+    pub fn ocpi(&self) -> u8 { ((self.ocp_inc as u8) << 7) | 0x40 | self.ocpi as u8 }
     #[inline]
     pub fn set_ocpi(&mut self, ocpi: u8) {
         self.ocp_inc = (ocpi & 0x80) != 0;
-        self.ocpi = (ocpi & 0x7f) as usize;
+        self.ocpi = (ocpi & 0x3f) as usize;
     }
 
     #[inline]
@@ -318,7 +348,7 @@ impl GPU {
         self.ocpd[self.ocpi >> 3][(self.ocpi & 0x7) >> 1].write(self.ocpi, value);
 
         if self.ocp_inc {
-            self.ocpi += 1;
+            self.ocpi = (self.ocpi + 1) & 0x3f;
         }
     }
     
@@ -409,6 +439,8 @@ struct Sprite {
     cgb_palette: u8,
 }
 
+savestate!(Sprite, x, y, tile, priority, y_flip, x_flip, dmg_palette, cgb_bank, cgb_palette);
+
 impl Sprite {
     pub fn read(&self, address: usize) -> u8 {
         match address % 4 {
@@ -473,6 +505,24 @@ impl Default for GpuMode {
     fn default() -> GpuMode { GpuMode::ReadingOAM }
 }
 
+impl Savestate for GpuMode {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(*self as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        *self = match state.read_u8()? {
+            0 => GpuMode::HBlank,
+            1 => GpuMode::VBlank,
+            2 => GpuMode::ReadingOAM,
+            3 => GpuMode::ReadingVRAM,
+            _ => return Err("Invalid GPU mode in savestate"),
+        };
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 enum DmgColor {
     White = 0,
@@ -485,6 +535,18 @@ impl Default for DmgColor {
     fn default() -> DmgColor { DmgColor::White }
 }
 
+impl Savestate for DmgColor {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(*self as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        *self = DmgColor::from(state.read_u8()? & 3);
+
+        Ok(())
+    }
+}
+
 impl DmgColor {
     #[inline]
     fn as_real(self) -> u32 {
@@ -516,6 +578,8 @@ struct CgbColor {
     pub b: u8
 }
 
+savestate!(CgbColor, r, g, b);
+
 impl CgbColor {
     pub fn read(self, address: usize) -> u8 {
         match address & 1 {
@@ -533,7 +597,7 @@ impl CgbColor {
             }
             1 => {
                 self.g = (self.g & 0x7) | ((value & 0x3) << 3);
-                self.b = value >> 2;
+                self.b = (value >> 2) & 0x1f;
             }
             _ => unreachable!()
         }
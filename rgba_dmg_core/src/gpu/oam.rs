@@ -41,5 +41,21 @@ impl GPU {
                 }
             }
         }
+
+        // The 10 sprites of a line are still picked in OAM order, but
+        // the leftmost one is drawn on top. The sort is stable, so
+        // the OAM index breaks ties.
+        if self.coordinate_priority {
+            let oam = &self.oam;
+
+            for line in self.line_cache.iter_mut() {
+                let count = line.iter().take_while(|s| s.is_some()).count();
+
+                line[..count].sort_by(|a, b| {
+                    let (a, b) = (a.unwrap() as usize, b.unwrap() as usize);
+                    oam[a].cmp(&oam[b])
+                });
+            }
+        }
     }
 }
@@ -0,0 +1,52 @@
+// mod.rs ---
+//
+// Filename: mod.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 22:39:14 2026 (+0200)
+// Last-Updated: Sun Oct 18 22:39:14 2026 (+0200)
+//           By: Louise <louise>
+//
+mod tcp;
+
+pub use self::tcp::TcpInfrared;
+
+use std::sync::{Arc, Mutex};
+
+/// What the infrared port of the cartridge points at.
+///
+/// The LED state is given when the game changes it, and the sensor
+/// is polled regularly while the game has the port mapped.
+pub trait InfraredDevice {
+    /// Turns the LED of this side on or off.
+    fn set_led(&mut self, on: bool);
+
+    /// Whether the sensor of this side sees light.
+    fn light(&mut self) -> bool;
+}
+
+/// One side of an infrared link between two Gameboys of the same
+/// process. Each side sees the LED of the other one.
+pub struct InfraredEndpoint {
+    leds: Arc<Mutex<[bool; 2]>>,
+    side: usize,
+}
+
+/// Returns both sides of an infrared link.
+pub fn infrared_link() -> (InfraredEndpoint, InfraredEndpoint) {
+    let leds: Arc<Mutex<[bool; 2]>> = Default::default();
+
+    (
+        InfraredEndpoint { leds: leds.clone(), side: 0 },
+        InfraredEndpoint { leds, side: 1 },
+    )
+}
+
+impl InfraredDevice for InfraredEndpoint {
+    fn set_led(&mut self, on: bool) {
+        self.leds.lock().unwrap()[self.side] = on;
+    }
+
+    fn light(&mut self) -> bool {
+        self.leds.lock().unwrap()[self.side ^ 1]
+    }
+}
@@ -0,0 +1,90 @@
+// tcp.rs ---
+//
+// Filename: tcp.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 22:44:02 2026 (+0200)
+// Last-Updated: Sun Oct 18 23:58:35 2026 (+0200)
+//           By: Louise <louise>
+//
+// Infrared link over a local TCP socket. Each side sends a byte
+// every time its LED is turned on (1) or off (0).
+use crate::infrared::InfraredDevice;
+use crate::serial::{connect_local, write_pending};
+
+use std::io::{ErrorKind, Read};
+use std::net::TcpStream;
+
+pub struct TcpInfrared {
+    stream: TcpStream,
+    connected: bool,
+
+    remote_led: bool,
+    outgoing: Vec<u8>,
+}
+
+impl TcpInfrared {
+    /// Connects to the other Gameboy if it is already listening on
+    /// the given local port, or waits for it to connect otherwise.
+    pub fn open(port: u16) -> Result<TcpInfrared, &'static str> {
+        let stream = connect_local(port, "Infrared link")?;
+
+        Ok(TcpInfrared {
+            stream,
+            connected: true,
+
+            remote_led: false,
+            outgoing: Vec::new(),
+        })
+    }
+
+    fn disconnect(&mut self) {
+        self.connected = false;
+        self.remote_led = false;
+    }
+
+    fn flush(&mut self) {
+        if self.connected {
+            if let Err(e) = write_pending(&mut self.stream, &mut self.outgoing) {
+                warn!("Infrared link disconnected : {}", e);
+                self.disconnect();
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        let mut data = [0; 64];
+
+        self.flush();
+
+        while self.connected {
+            match self.stream.read(&mut data) {
+                Ok(0) => {
+                    warn!("Infrared link disconnected");
+                    self.disconnect();
+                }
+                // Only the latest state matters
+                Ok(n) => self.remote_led = data[n - 1] != 0,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => { }
+                Err(e) => {
+                    warn!("Infrared link disconnected : {}", e);
+                    self.disconnect();
+                }
+            }
+        }
+    }
+}
+
+impl InfraredDevice for TcpInfrared {
+    fn set_led(&mut self, on: bool) {
+        if self.connected {
+            self.outgoing.push(on as u8);
+            self.flush();
+        }
+    }
+
+    fn light(&mut self) -> bool {
+        self.receive();
+        self.remote_led
+    }
+}
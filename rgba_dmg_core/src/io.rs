@@ -8,16 +8,21 @@
 // 
 use rgba_common::Platform;
 use rgba_common::Event;
+use rgba_common::savestate;
 
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::Read;
 
-use crate::cart::Cartridge;
+use crate::cart::{Cartridge, ImageSource};
 use crate::gpu::GPU;
 use crate::apu::APU;
 use crate::timer::Timer;
 use crate::joypad::Joypad;
+use crate::serial::SerialDevice;
+use crate::infrared::InfraredDevice;
+use crate::scheduler::{Scheduler, Event as ScheduledEvent, EventKind};
+use crate::Model;
 
 const JOYP: usize = 0xFF00;
 
@@ -83,10 +88,45 @@ const BIOS: usize = 0xFF50;
 
 const VBK: usize = 0xFF4F;
 const SVBK: usize = 0xFF70;
+const KEY1: usize = 0xFF4D;
+const OPRI: usize = 0xFF6C;
+
+// Undocumented CGB registers, FF72-FF75 are plain storage
+const UNDOC_START: usize = 0xFF72;
+const UNDOC_END: usize = 0xFF75;
+const PCM12: usize = 0xFF76;
+const PCM34: usize = 0xFF77;
 
 const IF: usize = 0xFF0F;
 const IE: usize = 0xFFFF;
 
+// IO registers after the bootrom, as (address, value)
+const POST_BOOT_IO: [(usize, u8); 30] = [
+    (NR52, 0xF1),
+    (NR10, 0x80), (NR11, 0xBF), (NR12, 0xF3), (NR13, 0xFF), (NR14, 0x3F),
+    (NR21, 0x3F), (NR22, 0x00), (NR23, 0xFF), (NR24, 0x3F),
+    (NR30, 0x7F), (NR31, 0xFF), (NR32, 0x9F), (NR33, 0xFF), (NR34, 0x3F),
+    (NR41, 0xFF), (NR42, 0x00), (NR43, 0x00), (NR44, 0x3F),
+    (NR50, 0x77), (NR51, 0xF3),
+    (JOYP, 0xCF), (TAC, 0xF8),
+    (LCDC, 0x91), (BGP, 0xFC), (OBP0, 0xFF), (OBP1, 0xFF),
+    (WY, 0x00), (WX, 0x00),
+    (IF, 0xE1),
+];
+
+// How often a transfer on the external clock is checked for,
+// about the time one bit takes on the internal clock
+const SERIAL_POLL_CYCLES: u64 = 512;
+
+// How often the infrared sensor is checked while it is mapped
+const INFRARED_POLL_CYCLES: u64 = 128;
+
+// M-cycles the CPU is stopped for during a speed switch
+const SPEED_SWITCH_CYCLES: u32 = 2050;
+
+// (R) symbol drawn by the bootrom after the logo
+const LOGO_R: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
 pub struct Interconnect {
     bios: Vec<u8>,
     cart: Cartridge,
@@ -99,6 +139,20 @@ pub struct Interconnect {
     apu: APU,
     joypad: Joypad,
     sound_enabled: bool,
+    // Last motor state sent to the platform
+    rumble: bool,
+
+    // Serial port
+    sb: u8,
+    sc: u8,
+    serial: Option<Box<dyn SerialDevice>>,
+
+    // What the Pocket Camera sees
+    camera_images: ImageSource,
+
+    // Infrared port of the cartridge, and last LED state sent to it
+    infrared: Option<Box<dyn InfraredDevice>>,
+    infrared_led: bool,
 
     // Interrupts
     it_vblank_enable: bool,
@@ -106,9 +160,21 @@ pub struct Interconnect {
     it_timer_enable: bool,
     it_serial_enable: bool,
     it_joypad_enable: bool,
+    it_serial: bool,
 
     // CGB stuff
     wram_bank: usize,
+    undocumented: [u8; 4],
+
+    // Double speed, the CPU, timer, serial and DMA run twice as fast
+    // while the PPU and APU keep their pace. The timer is fed CPU
+    // clocks, counted from the time of the last switch.
+    double_speed: bool,
+    speed_switch_armed: bool,
+    speed_switch_time: u64,
+    speed_switch_clock: u64,
+    // M-cycles left before the CPU runs again after a switch
+    speed_switch_stall: u32,
     
     // DMA
     dma_src: usize,
@@ -130,11 +196,25 @@ pub struct Interconnect {
     bios_inplace: bool,
     cgb: bool,
     cycles_to_spend: u32,
+    scheduler: Scheduler,
+    apu_timestamp: u64,
 }
 
+savestate!(Interconnect,
+           cart, wram, hram, timer, gpu, apu, joypad, sb, sc,
+           it_vblank_enable, it_lcd_enable, it_timer_enable,
+           it_serial_enable, it_joypad_enable, it_serial,
+           wram_bank, undocumented,
+           double_speed, speed_switch_armed, speed_switch_time, speed_switch_clock,
+           speed_switch_stall,
+           dma_src, dma_dest, dma_ongoing,
+           hdma_src, hdma_dst, hdma_length, hdma_ongoing,
+           bios_inplace, cgb, cycles_to_spend, scheduler, apu_timestamp,
+           camera_images);
+
 impl Interconnect {
     pub fn new() -> Interconnect {
-        Interconnect {
+        let mut io = Interconnect {
             bios: Vec::new(),
             cart: Cartridge::NoCartridge,
             wram: [0; 0x8000],
@@ -143,16 +223,34 @@ impl Interconnect {
             gpu: GPU::new(),
             apu: APU::new(),
             sound_enabled: true,
+            rumble: false,
             timer: Timer::new(),
             joypad: Default::default(),
+
+            sb: 0,
+            sc: 0,
+            serial: None,
+
+            camera_images: ImageSource::new(),
+
+            infrared: None,
+            infrared_led: false,
             
             it_vblank_enable: false,
             it_lcd_enable: false,
             it_timer_enable: false,
             it_serial_enable: false,
             it_joypad_enable: false,
+            it_serial: false,
 
             wram_bank: 1,
+            undocumented: [0; 4],
+
+            double_speed: false,
+            speed_switch_armed: false,
+            speed_switch_time: 0,
+            speed_switch_clock: 0,
+            speed_switch_stall: 0,
             
             dma_src: 0,
             dma_dest: 0xFEA0,
@@ -170,15 +268,43 @@ impl Interconnect {
             bios_inplace: false,
             cgb: false,
             cycles_to_spend: 0,
-        }
+            scheduler: Scheduler::new(),
+            apu_timestamp: 0,
+        };
+
+        io.start_scheduler();
+        io
     }
 
     pub fn reset(&mut self) {
-        self.bios_inplace = true;
+        self.bios_inplace = self.has_bootrom();
         self.dma_ongoing = false;
+        self.sb = 0;
+        self.set_sc(0);
+        self.it_serial = false;
+
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.speed_switch_time = 0;
+        self.speed_switch_clock = 0;
+        self.speed_switch_stall = 0;
 
         self.timer.reset();
         self.gpu.reset();
+
+        self.cycles_to_spend = 0;
+        self.start_scheduler();
+    }
+
+    /// Schedules the events that are always there
+    fn start_scheduler(&mut self) {
+        self.scheduler = Scheduler::new();
+        self.apu_timestamp = 0;
+
+        self.scheduler.schedule(0, EventKind::FrameSequencer);
+        self.scheduler.schedule(self.apu.cycles_to_next_sample() as u64, EventKind::ApuSample);
+        self.schedule_gpu();
+        self.schedule_timer();
     }
     
     pub fn load_bios(&mut self, filename: &str) -> Result<(), &'static str> {
@@ -201,6 +327,10 @@ impl Interconnect {
                             0x900 => {
                                 info!("BIOS type: CGB");
                                 self.cgb = true;
+
+                                // OPRI is cleared on a CGB, the bootrom
+                                // sets it back for DMG games
+                                self.gpu.set_opri(0);
                                 
                                 Ok(())
                             }
@@ -226,7 +356,92 @@ impl Interconnect {
         }
     }
 
-    pub fn load_rom(&mut self, filename: &str) -> bool {
+    #[inline]
+    pub fn has_bootrom(&self) -> bool { !self.bios.is_empty() }
+
+    #[inline]
+    pub fn cgb(&self) -> bool { self.cgb }
+
+    /// Model matching the CGB flag of the cartridge header
+    pub fn header_model(&self) -> Model {
+        if (self.cart.read_rom(0x143) & 0x80) != 0 {
+            Model::CGB
+        } else {
+            Model::DMG
+        }
+    }
+
+    /// Puts the hardware in the state the bootrom of the given
+    /// model leaves it in.
+    pub fn skip_bootrom(&mut self, model: Model) {
+        self.bios_inplace = false;
+        self.cgb = model.is_cgb() && ((self.cart.read_rom(0x143) & 0x80) != 0);
+
+        info!("Skipping bootrom, model: {:?}, CGB mode: {}", model, self.cgb);
+
+        // Sound has to be powered on before anything else, and
+        // the channels are not retriggered.
+        for &(address, value) in POST_BOOT_IO.iter() {
+            self.write_u8(address, value);
+        }
+
+        // Sprites are ordered by X everywhere but in CGB mode
+        self.gpu.set_opri((!self.cgb) as u8);
+
+        let clock = self.clock_now();
+
+        self.timer.set_internal(clock, match model {
+            Model::DMG | Model::MGB => 0xABCC,
+            Model::CGB | Model::AGB if self.cgb => 0x1EA0,
+            Model::CGB | Model::AGB => 0x267C,
+        });
+
+        if self.cgb {
+            self.write_u8(BCPI, 0x80);
+            self.write_u8(OCPI, 0x80);
+
+            for _ in 0..32 {
+                self.write_u8(BCPD, 0xFF);
+                self.write_u8(BCPD, 0x7F);
+                self.write_u8(OCPD, 0xFF);
+                self.write_u8(OCPD, 0x7F);
+            }
+        }
+
+        self.draw_logo();
+    }
+
+    // The bootrom leaves the header logo in VRAM, scaled 2x, and
+    // the (R) symbol after it.
+    fn draw_logo(&mut self) {
+        let mut address = 0x8010;
+
+        for i in 0x104..0x134 {
+            let byte = self.cart.read_rom(i);
+
+            for nibble in [byte >> 4, byte & 0xF].iter() {
+                let row = (0..4).fold(0u8, |acc, bit| {
+                    acc | (((nibble >> bit) & 1) * (0x3 << (bit * 2)))
+                });
+
+                self.gpu.write_vram_u8(address, row);
+                self.gpu.write_vram_u8(address + 2, row);
+                address += 4;
+            }
+        }
+
+        for (i, &row) in LOGO_R.iter().enumerate() {
+            self.gpu.write_vram_u8(0x8190 + (i << 1), row);
+        }
+
+        for i in 0..12 {
+            self.gpu.write_vram_u8(0x9904 + i, (i + 1) as u8);
+            self.gpu.write_vram_u8(0x9924 + i, (i + 13) as u8);
+        }
+        self.gpu.write_vram_u8(0x9910, 0x19);
+    }
+
+    pub fn load_rom(&mut self, filename: &str) -> Result<(), &'static str> {
         match File::open(filename) {
             Ok(mut file) => {
                 info!("ROM opened!");
@@ -236,24 +451,33 @@ impl Interconnect {
                 if let Err(e) = file.read_to_end(&mut rom) {
                     warn!("Couldn't read ROM file : {}", e);
 
-                    false
+                    Err("Error while reading file")
                 } else {
-                    self.cart = Cartridge::new(filename, rom);
+                    let cart = Cartridge::new(filename, rom)?;
+
+                    self.cart.flush_savefile();
+                    self.cart = cart;
                     info!("ROM loaded!");
                     
-                    true
+                    Ok(())
                 }
             },
 
             Err(e) => {
                 warn!("Couldn't open ROM file : {}", e);
-                false
+                Err("Error opening file")
             }
         }
     }
 
-    pub fn write_savefile(&self) {
-        self.cart.write_savefile();
+    #[inline]
+    pub fn tick_savefile(&mut self) {
+        self.cart.tick_savefile();
+    }
+
+    #[inline]
+    pub fn flush_savefile(&mut self) {
+        self.cart.flush_savefile();
     }
     
     pub fn read_u8(&self, address: usize) -> u8 {
@@ -275,11 +499,12 @@ impl Interconnect {
 
             // IO
             JOYP => self.joypad.read(),
-            SB => 0xFF,
-            SC => 0x00,
+            SB => self.sb,
+            SC if self.cgb => self.sc | 0x7C,
+            SC => self.sc | 0x7E,
 
-            DIV => self.timer.div(),
-            TIMA => self.timer.tima(),
+            DIV => self.timer.div(self.clock_now()),
+            TIMA => self.timer.tima(self.clock_now()),
             TMA => self.timer.tma(),
             TAC => self.timer.tac(),
 
@@ -299,9 +524,18 @@ impl Interconnect {
             BCPD => self.gpu.bcpd(),
             OCPI => self.gpu.ocpi(),
             OCPD => self.gpu.ocpd(),
+            OPRI if self.cgb => self.gpu.opri(),
 
             VBK  if self.cgb => self.gpu.vbk(),
             SVBK if self.cgb => self.wram_bank as u8,
+            KEY1 if self.cgb => {
+                ((self.double_speed as u8) << 7) | 0x7E | (self.speed_switch_armed as u8)
+            }
+
+            UNDOC_END if self.cgb => self.undocumented[3] | 0x8F,
+            UNDOC_START..=UNDOC_END if self.cgb => self.undocumented[address - UNDOC_START],
+            PCM12 if self.cgb => self.apu.pcm12(),
+            PCM34 if self.cgb => self.apu.pcm34(),
 
             HDMA5 => 0x00,
             
@@ -346,10 +580,34 @@ impl Interconnect {
             self.watchpoint_hit = Some((address, value));
         }
         
+        // Sound registers change what the channels output from now on
+        if let NR10..=NR3_WAVE_END = address {
+            let now = self.now();
+            self.sync_apu(now);
+        }
+
         match address {
-            0x0000..=0x7FFF => { self.cart.write_rom(address, value) }
+            0x0000..=0x7FFF => {
+                self.cart.write_rom(address, value);
+                self.update_infrared();
+            }
             0x8000..=0x9FFF => { self.gpu.write_vram_u8(address, value) }
-            0xA000..=0xBFFF => { self.cart.write_ram(address, value) }
+            0xA000..=0xBFFF => {
+                self.cart.write_ram(address, value);
+
+                if let Some(cycles) = self.cart.take_camera_capture() {
+                    let now = self.now();
+                    self.scheduler.schedule(now + cycles, EventKind::CameraCapture);
+                }
+
+                if let Some(frequency) = self.cart.take_tone() {
+                    let now = self.now();
+                    self.sync_apu(now);
+                    self.apu.play_tone(frequency);
+                }
+
+                self.update_infrared();
+            }
             0xC000..=0xCFFF => { self.wram[address & 0xFFF] = value }
             0xD000..=0xDFFF => { self.wram[(self.wram_bank << 12) + (address & 0xFFF)] = value }
             0xE000..=0xEFFF => { self.wram[address & 0xFFF] = value }
@@ -360,15 +618,15 @@ impl Interconnect {
 
             // IO
             JOYP => self.joypad.write(value),
-            SB => {},
-            SC => {},
+            SB => self.set_sb(value),
+            SC => self.set_sc(value),
 
-            DIV  => self.timer.set_div(),
-            TIMA => self.timer.set_tima(value),
+            DIV  => { let clock = self.clock_now(); self.timer.set_div(clock); self.schedule_timer(); }
+            TIMA => { let clock = self.clock_now(); self.timer.set_tima(clock, value); self.schedule_timer(); }
             TMA  => self.timer.set_tma(value),
-            TAC  => self.timer.set_tac(value),
+            TAC  => { let clock = self.clock_now(); self.timer.set_tac(clock, value); self.schedule_timer(); }
 
-            LCDC => self.gpu.set_lcdc(value),
+            LCDC => { self.gpu.set_lcdc(value); self.schedule_gpu(); }
             STAT => self.gpu.set_stat(value),
             SCY  => self.gpu.set_scy(value),
             SCX  => self.gpu.set_scx(value),
@@ -384,9 +642,15 @@ impl Interconnect {
             BCPD if self.cgb => self.gpu.set_bcpd(value),
             OCPI if self.cgb => self.gpu.set_ocpi(value),
             OCPD if self.cgb => self.gpu.set_ocpd(value),
+            OPRI if self.cgb => self.gpu.set_opri(value),
             
             VBK  if self.cgb => self.gpu.set_vbk(value),
             SVBK if self.cgb => self.set_svbk(value),
+            KEY1 if self.cgb => self.speed_switch_armed = (value & 1) != 0,
+
+            UNDOC_END if self.cgb => self.undocumented[3] = value & 0x70,
+            UNDOC_START..=UNDOC_END if self.cgb => self.undocumented[address - UNDOC_START] = value,
+            PCM12 | PCM34 => { },
             
             BIOS => self.bios_inplace = false,
 
@@ -467,19 +731,128 @@ impl Interconnect {
     fn it_f(&self) -> u8 {
         (self.gpu.it_vblank() as u8) |
         ((self.gpu.it_lcd() as u8) << 1) |
-        ((self.timer.it_timer() as u8) << 2)
+        ((self.timer.it_timer() as u8) << 2) |
+        ((self.it_serial as u8) << 3)
     }
     
     fn set_if(&mut self, value: u8) {
         self.gpu.set_it_vblank((value & 0x01) != 0);
         self.gpu.set_it_lcd((value & 0x02) != 0);
         self.timer.set_it_timer((value & 0x04) != 0);
+        self.it_serial = (value & 0x08) != 0;
+    }
+
+    /// Plugs something in the serial port
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial = Some(device);
+
+        let sc = self.sc;
+        self.set_sc(sc);
+    }
+
+    /// Sets the pictures the Pocket Camera sensor sees
+    pub fn set_camera_images(&mut self, images: ImageSource) {
+        self.camera_images = images;
+    }
+
+    /// Points the infrared port of the cartridge at something
+    pub fn connect_infrared(&mut self, device: Box<dyn InfraredDevice>) {
+        self.infrared = Some(device);
+        self.infrared_led = false;
+
+        self.update_infrared();
+    }
+
+    /// Sends the LED state to the other side, and starts polling the
+    /// sensor when the game maps it.
+    fn update_infrared(&mut self) {
+        let led = self.cart.infrared_led();
+
+        if let Some(ref mut device) = self.infrared {
+            if led != self.infrared_led {
+                device.set_led(led);
+            }
+        }
+
+        self.infrared_led = led;
+
+        if self.cart.infrared_mapped() && !self.scheduler.is_scheduled(EventKind::InfraredPoll) {
+            let now = self.now();
+
+            self.poll_infrared();
+            self.scheduler.schedule(now + INFRARED_POLL_CYCLES, EventKind::InfraredPoll);
+        }
+    }
+
+    fn poll_infrared(&mut self) {
+        let light = match self.infrared {
+            Some(ref mut device) => device.light(),
+            None => false,
+        };
+
+        self.cart.set_infrared_light(light);
+    }
+
+    #[inline]
+    fn serial_waiting(&self) -> bool {
+        (self.sc & 0x81) == 0x80
+    }
+
+    fn set_sb(&mut self, value: u8) {
+        self.sb = value;
+
+        // The other side gets the new byte if it starts a transfer
+        if self.serial_waiting() {
+            if let Some(ref mut device) = self.serial {
+                device.set_ready(Some(value));
+            }
+        }
+    }
+
+    /// Starts a transfer, which ends after 8 bits when the internal
+    /// clock is used, or when the other side clocks it otherwise.
+    fn set_sc(&mut self, value: u8) {
+        let now = self.now();
+
+        self.sc = value & if self.cgb { 0x83 } else { 0x81 };
+        self.scheduler.cancel(EventKind::Serial);
+        self.scheduler.cancel(EventKind::SerialPoll);
+
+        if (value & 0x81) == 0x81 {
+            // 8192Hz, or 262144Hz with the CGB fast clock, both
+            // doubled in double speed
+            let cycles = if self.cgb && (value & 0x02) != 0 { 16 * 8 } else { 512 * 8 };
+            let cycles = cycles >> (self.double_speed as u32);
+
+            self.scheduler.schedule(now + cycles, EventKind::Serial);
+        }
+
+        if let Some(ref mut device) = self.serial {
+            if (self.sc & 0x81) == 0x80 {
+                device.set_ready(Some(self.sb));
+                self.scheduler.schedule(now + SERIAL_POLL_CYCLES, EventKind::SerialPoll);
+            } else {
+                device.set_ready(None);
+            }
+        }
+    }
+
+    fn end_serial_transfer(&mut self, received: u8) {
+        self.sb = received;
+        self.sc &= 0x7F;
+        self.it_serial = true;
     }
 
     fn begin_dma(&mut self, value: u8) {
         self.dma_ongoing = true;
         self.dma_dest = 0xFE00;
         self.dma_src = (value as usize) << 8;
+
+        // One byte per M-cycle, starting on the next one
+        let now = self.now();
+
+        self.scheduler.cancel(EventKind::OamDma);
+        self.scheduler.schedule(now + self.m_cycle(), EventKind::OamDma);
     }
 
     fn handle_dma(&mut self) {
@@ -545,6 +918,8 @@ impl Interconnect {
             Some(0x48)
         } else if self.it_timer_enable && self.timer.it_timer() {
             Some(0x50)
+        } else if self.it_serial_enable && self.it_serial {
+            Some(0x58)
         } else {
             None
         }
@@ -557,6 +932,8 @@ impl Interconnect {
             self.gpu.ack_it_lcd();
         } else if self.it_timer_enable && self.timer.it_timer() {
             self.timer.ack_it_timer();
+        } else if self.it_serial_enable && self.it_serial {
+            self.it_serial = false;
         }
     }
     
@@ -567,7 +944,7 @@ impl Interconnect {
     #[inline(always)]
     pub fn get_framebuffer(&mut self) -> &[u32] { &self.gpu.framebuffer }
 
-    pub fn get_internal(&self) -> u16 { self.timer.get_internal() }
+    pub fn get_internal(&self) -> u16 { self.timer.get_internal(self.clock_now()) }
     
     // Watchpoints
     #[inline]
@@ -592,7 +969,10 @@ impl Interconnect {
     }
 
     pub fn handle_event(&mut self, event: Event) {
-        self.joypad.handle_event(event);
+        match event {
+            Event::Analog(axis, value) => self.cart.set_axis(axis, value),
+            _ => self.joypad.handle_event(event),
+        }
     }
 
     #[inline]
@@ -600,27 +980,207 @@ impl Interconnect {
         self.sound_enabled = sound_enabled;
     }
 
+    /// Current timestamp in T-cycles, counting the cycles not
+    /// spent yet
+    #[inline]
+    fn now(&self) -> u64 {
+        self.scheduler.now() + ((self.cycles_to_spend as u64) << self.m_cycle_shift())
+    }
+
+    /// T-cycles in an M-cycle are 4, or 2 in double speed
+    #[inline]
+    fn m_cycle_shift(&self) -> u32 {
+        if self.double_speed { 1 } else { 2 }
+    }
+
+    #[inline]
+    fn m_cycle(&self) -> u64 { 1 << self.m_cycle_shift() }
+
+    /// CPU clocks elapsed at the given time, which the timer counts
+    #[inline]
+    fn clock(&self, time: u64) -> u64 {
+        self.speed_switch_clock + ((time - self.speed_switch_time) << (self.double_speed as u32))
+    }
+
+    #[inline]
+    fn clock_now(&self) -> u64 { self.clock(self.now()) }
+
+    /// Time at which the given number of CPU clocks have elapsed
+    fn time_at_clock(&self, clock: u64) -> u64 {
+        let elapsed = clock - self.speed_switch_clock;
+
+        self.speed_switch_time + elapsed.div_ceil(1 << (self.double_speed as u32))
+    }
+
+    /// Whether KEY1 asks for a speed switch on the next STOP
+    #[inline]
+    pub fn speed_switch_armed(&self) -> bool { self.speed_switch_armed }
+
+    /// Switches between normal and double speed. DIV is reset, and
+    /// the CPU is stopped while the clock settles.
+    pub fn switch_speed(&mut self) {
+        // The cycles of this instruction were at the old speed
+        self.spend_cycles();
+
+        let now = self.now();
+        let clock = self.clock(now);
+
+        self.timer.update(clock);
+        self.speed_switch_time = now;
+        self.speed_switch_clock = clock;
+
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+
+        info!("Switched to {} speed", if self.double_speed { "double" } else { "normal" });
+
+        self.timer.set_div(clock);
+        self.schedule_timer();
+
+        self.speed_switch_stall = SPEED_SWITCH_CYCLES;
+    }
+
+    /// Whether the CPU is still stopped by a speed switch
+    #[inline]
+    pub fn is_switching_speed(&self) -> bool { self.speed_switch_stall != 0 }
+
+    /// Spends the speed switch stall up to the next event at most,
+    /// so that lines are rendered along the way.
+    pub fn step_speed_switch(&mut self) {
+        let now = self.now();
+
+        let cycles = match self.scheduler.next_event() {
+            Some(time) if time > now => (time - now).div_ceil(self.m_cycle()) as u32,
+            _ => 1,
+        }.min(self.speed_switch_stall);
+
+        self.speed_switch_stall -= cycles;
+        self.cycles_to_spend += cycles;
+    }
+
+    /// Used while halted, nothing can happen before the next event.
+    pub fn skip_to_next_event(&mut self) {
+        let now = self.now();
+
+        match self.scheduler.next_event() {
+            Some(time) if time > now =>
+                self.cycles_to_spend += (time - now).div_ceil(self.m_cycle()) as u32,
+            _ => self.cycles_to_spend += 1,
+        }
+    }
+
     pub fn spend_cycles(&mut self) {
-        let cycles = self.cycles_to_spend << 2;
+        let cycles = self.cycles_to_spend << self.m_cycle_shift();
         self.cycles_to_spend = 0;
 
-        if self.gpu.has_hblank() {
-            self.gpu.ack_hblank();
+        self.scheduler.advance(cycles);
 
-            self.handle_hdma();
-        }
-        
-        self.gpu.spend_cycles(cycles);
-        if self.sound_enabled {
-            self.apu.spend_cycles(cycles);
+        while let Some(event) = self.scheduler.pop() {
+            self.handle_scheduled(event);
         }
+    }
+
+    fn handle_scheduled(&mut self, event: ScheduledEvent) {
+        match event.kind {
+            EventKind::GpuMode => {
+                let next = self.gpu.next_mode();
+
+                if self.gpu.has_hblank() {
+                    self.gpu.ack_hblank();
+
+                    self.handle_hdma();
+                }
+
+                self.scheduler.schedule(event.time + next as u64, EventKind::GpuMode);
+            }
+            EventKind::TimerOverflow => {
+                let clock = self.clock(event.time);
+
+                self.timer.update(clock);
+                self.schedule_timer();
+            }
+            EventKind::FrameSequencer => {
+                self.sync_apu(event.time);
+
+                if self.sound_enabled {
+                    self.apu.frame_sequencer_click();
+                }
+
+                self.scheduler.schedule(event.time + 8192, EventKind::FrameSequencer);
+            }
+            EventKind::ApuSample => {
+                self.sync_apu(event.time);
+
+                let next = event.time + self.apu.cycles_to_next_sample() as u64;
+                self.scheduler.schedule(next, EventKind::ApuSample);
+            }
+            EventKind::Serial => {
+                // 0xFF is shifted in when nothing is plugged in
+                let sb = self.sb;
+                let received = match self.serial {
+                    Some(ref mut device) => device.transfer(sb),
+                    None => 0xFF,
+                };
+
+                self.end_serial_transfer(received);
+            }
+            EventKind::SerialPoll => {
+                let received = self.serial.as_mut().and_then(|device| device.poll());
 
-        for _ in 0..cycles {
-            if self.dma_ongoing {
+                match received {
+                    Some(byte) => self.end_serial_transfer(byte),
+                    None => self.scheduler.schedule(event.time + SERIAL_POLL_CYCLES, EventKind::SerialPoll),
+                }
+            }
+            EventKind::CameraCapture => {
+                let image = self.camera_images.next_frame();
+                self.cart.finish_camera_capture(image);
+            }
+            EventKind::InfraredPoll => {
+                if self.cart.infrared_mapped() {
+                    self.poll_infrared();
+                    self.scheduler.schedule(event.time + INFRARED_POLL_CYCLES, EventKind::InfraredPoll);
+                }
+            }
+            EventKind::OamDma => {
                 self.handle_dma();
+
+                if self.dma_ongoing {
+                    self.scheduler.schedule(event.time + self.m_cycle(), EventKind::OamDma);
+                }
             }
-            
-            self.timer.handle();
+        }
+    }
+
+    /// Catches the APU up to the given timestamp
+    fn sync_apu(&mut self, now: u64) {
+        if self.sound_enabled {
+            self.apu.spend_cycles((now - self.apu_timestamp) as u32);
+        }
+
+        self.apu_timestamp = now;
+    }
+
+    fn schedule_timer(&mut self) {
+        self.scheduler.cancel(EventKind::TimerOverflow);
+
+        if let Some(clock) = self.timer.next_overflow() {
+            let time = self.time_at_clock(clock);
+
+            self.scheduler.schedule(time, EventKind::TimerOverflow);
+        }
+    }
+
+    /// The GPU only has events while the display is on
+    fn schedule_gpu(&mut self) {
+        let scheduled = self.scheduler.is_scheduled(EventKind::GpuMode);
+
+        if self.gpu.display_enabled() && !scheduled {
+            let time = self.now() + self.gpu.cycles_to_next_mode() as u64;
+
+            self.scheduler.schedule(time, EventKind::GpuMode);
+        } else if !self.gpu.display_enabled() && scheduled {
+            self.scheduler.cancel(EventKind::GpuMode);
         }
     }
     
@@ -634,5 +1194,11 @@ impl Interconnect {
         if self.sound_enabled {
             self.apu.render(platform);
         }
+
+        let motor = self.cart.motor();
+        if motor != self.rumble {
+            self.rumble = motor;
+            platform.set_rumble(motor);
+        }
     }
 }
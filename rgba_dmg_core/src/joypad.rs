@@ -7,6 +7,7 @@
 //           By: Louise <ludwigette>
 // 
 use rgba_common::{Event, Key};
+use rgba_common::savestate;
 
 #[derive(Debug, Default)]
 pub struct Joypad {
@@ -25,6 +26,8 @@ pub struct Joypad {
     right: bool,
 }
 
+savestate!(Joypad, mode, start, select, a, b, down, up, left, right);
+
 impl Joypad {
     pub fn read(&self) -> u8 {
         if self.mode {
@@ -57,7 +60,10 @@ impl Joypad {
                 Key::Up => self.up = true,
                 Key::Down => self.down = true,
                 Key::Left => self.left = true,
-                Key::Right => self.right = true
+                Key::Right => self.right = true,
+
+                // No shoulder buttons on the Gameboy
+                Key::L | Key::R => { }
             },
             Event::KeyUp(key) => match key {
                 Key::Start => self.start = false,
@@ -68,7 +74,9 @@ impl Joypad {
                 Key::Up => self.up = false,
                 Key::Down => self.down = false,
                 Key::Left => self.left = false,
-                Key::Right => self.right = false
+                Key::Right => self.right = false,
+
+                Key::L | Key::R => { }
             },
             _ => { }
         }
@@ -13,25 +13,51 @@ mod cpu;
 mod gpu;
 mod apu;
 mod timer;
+mod scheduler;
+mod serial;
+mod infrared;
 mod joypad;
 mod io;
 mod cart;
 mod debug;
 
 use rgba_common::{Core, Platform, Event, ConsoleType};
+use rgba_common::{Savestate, StateWriter, StateReader};
 use rgba_common::fnv_hash;
 use crate::cpu::LR35902;
 use crate::io::Interconnect;
 use crate::debug::Debugger;
+use crate::cart::ImageSource;
+
+pub use crate::serial::{SerialDevice, LinkEndpoint, TcpLink, Printer, link_cable};
+pub use crate::infrared::{InfraredDevice, InfraredEndpoint, TcpInfrared, infrared_link};
 
 use std::fs::File;
 use std::io::{Seek, SeekFrom, Read};
 
+const STATE_VERSION: u16 = 10;
+
+/// Hardware model, whose post-boot state is used when running
+/// without a bootrom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    DMG,
+    MGB,
+    CGB,
+    AGB,
+}
+
+impl Model {
+    #[inline]
+    pub fn is_cgb(self) -> bool { matches!(self, Model::CGB | Model::AGB) }
+}
+
 pub struct Gameboy {
     cpu: LR35902,
     io: Interconnect,
     debug: Debugger,
 
+    model: Option<Model>,
     fast_mode: bool,
 }
 
@@ -42,13 +68,60 @@ impl Gameboy {
             io: Interconnect::new(),
             debug: Debugger::new(),
 
+            model: None,
             fast_mode: false,
         }
     }
 
+    /// Forces the model emulated when there is no bootrom. By
+    /// default, it is chosen from the cartridge header.
+    pub fn set_model(&mut self, model: Model) {
+        self.model = Some(model);
+    }
+
+    /// Plugs something in the serial port, like a link cable.
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.io.connect_serial(device);
+    }
+
+    /// Points the infrared port of HuC1 and HuC3 cartridges at
+    /// something, like another Gameboy.
+    pub fn connect_infrared(&mut self, device: Box<dyn InfraredDevice>) {
+        self.io.connect_infrared(device);
+    }
+
+    /// Uses an image file, or every image file of a directory, as
+    /// what the Pocket Camera sees.
+    pub fn set_camera_images(&mut self, path: &str) -> Result<(), &'static str> {
+        self.io.set_camera_images(ImageSource::open(path)?);
+
+        Ok(())
+    }
+
     fn reset(&mut self) {
+        self.io.flush_savefile();
         self.cpu.reset();
         self.io.reset();
+
+        if !self.io.has_bootrom() {
+            self.skip_bootrom();
+        }
+    }
+
+    fn skip_bootrom(&mut self) {
+        let model = self.model.unwrap_or_else(|| self.io.header_model());
+
+        self.io.skip_bootrom(model);
+        self.cpu.skip_bootrom(model, &self.io);
+    }
+
+    fn read_state(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let mut state = StateReader::new(data, ConsoleType::Gameboy, STATE_VERSION)?;
+
+        self.cpu.load(&mut state)?;
+        self.io.load(&mut state)?;
+
+        state.finish()
     }
 }
 
@@ -62,7 +135,7 @@ impl Core for Gameboy {
         }
 
         self.io.ack_frame();
-        self.io.write_savefile();
+        self.io.tick_savefile();
 
         self.io.get_framebuffer()
     }
@@ -109,12 +182,44 @@ impl Core for Gameboy {
     fn load_bios<T: ToString>(&mut self, filename: Option<T>) -> Result<(), &'static str> {
         match filename {
             Some(f) => self.io.load_bios(&f.to_string()),
-            None => panic!("A bootrom is mandatory for GB games.")
+            None => {
+                info!("No bootrom given, the boot sequence will be skipped");
+                Ok(())
+            }
         }
     }
 
-    fn load_rom(&mut self, filename: &str) -> bool {
-        self.io.load_rom(filename)
+    fn load_rom(&mut self, filename: &str) -> Result<(), &'static str> {
+        self.io.load_rom(filename)?;
+
+        if !self.io.has_bootrom() {
+            self.skip_bootrom();
+        }
+
+        Ok(())
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(ConsoleType::Gameboy, STATE_VERSION);
+
+        self.cpu.save(&mut state);
+        self.io.save(&mut state);
+
+        state.into_inner()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        // Keep the current state around so a bad savestate
+        // doesn't leave the emulator half-loaded
+        let backup = self.save_state();
+
+        if let Err(e) = self.read_state(data) {
+            self.read_state(&backup).expect("Couldn't restore state");
+
+            Err(e)
+        } else {
+            Ok(())
+        }
     }
 
     fn get_platform_parameters(&self) -> (u32, u32) {
@@ -129,3 +234,62 @@ impl Core for Gameboy {
 impl Default for Gameboy {
     fn default() -> Gameboy { Gameboy::new() }
 }
+
+impl Drop for Gameboy {
+    fn drop(&mut self) {
+        self.io.flush_savefile();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullPlatform;
+
+    impl Platform for NullPlatform { }
+
+    // CGB-only ROM arming KEY1 and running STOP the given number
+    // of times, then looping
+    fn speed_switch_rom(switches: usize) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+
+        let mut pc = 0x100;
+        for _ in 0..switches {
+            // LD A,1 ; LDH (4D),A ; STOP
+            rom[pc..pc + 6].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00]);
+            pc += 6;
+        }
+
+        // JR -2
+        rom[pc..pc + 2].copy_from_slice(&[0x18, 0xFE]);
+
+        rom
+    }
+
+    fn run_speed_switches(switches: usize) -> u8 {
+        let path = std::env::temp_dir().join(format!("rgba_speed_switch_{}.gbc", switches));
+        std::fs::write(&path, speed_switch_rom(switches)).unwrap();
+
+        let mut gameboy = Gameboy::new();
+        gameboy.set_model(Model::CGB);
+        gameboy.load_rom(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The bootrom leaves the LCD on
+        assert_ne!(gameboy.io.read_u8(0xFF40) & 0x80, 0);
+
+        for _ in 0..4 {
+            gameboy.run_frame(&mut NullPlatform);
+        }
+
+        gameboy.io.read_u8(0xFF4D)
+    }
+
+    #[test]
+    fn speed_switch_with_lcd_on() {
+        assert_eq!(run_speed_switches(1) & 0x81, 0x80);
+        assert_eq!(run_speed_switches(2) & 0x81, 0x00);
+    }
+}
@@ -0,0 +1,50 @@
+// scheduler.rs ---
+//
+// Filename: scheduler.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 17:24:03 2026 (+0200)
+// Last-Updated: Sun Oct 18 23:10:26 2026 (+0200)
+//           By: Louise <louise>
+//
+use rgba_common::{Savestate, StateWriter, StateReader, ScheduledEvent};
+
+/// Something that has to happen at a given T-cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventKind {
+    #[default]
+    GpuMode,
+    TimerOverflow,
+    FrameSequencer,
+    ApuSample,
+    Serial,
+    OamDma,
+    SerialPoll,
+    CameraCapture,
+    InfraredPoll,
+}
+
+impl Savestate for EventKind {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(*self as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        *self = match state.read_u8()? {
+            0 => EventKind::GpuMode,
+            1 => EventKind::TimerOverflow,
+            2 => EventKind::FrameSequencer,
+            3 => EventKind::ApuSample,
+            4 => EventKind::Serial,
+            5 => EventKind::OamDma,
+            6 => EventKind::SerialPoll,
+            7 => EventKind::CameraCapture,
+            8 => EventKind::InfraredPoll,
+            _ => return Err("Invalid scheduler event"),
+        };
+
+        Ok(())
+    }
+}
+
+pub type Event = ScheduledEvent<EventKind>;
+pub type Scheduler = rgba_common::Scheduler<EventKind>;
@@ -0,0 +1,82 @@
+// mod.rs ---
+//
+// Filename: mod.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 18:05:37 2026 (+0200)
+// Last-Updated: Sun Oct 18 23:58:35 2026 (+0200)
+//           By: Louise <louise>
+//
+mod tcp;
+mod printer;
+
+pub use self::tcp::TcpLink;
+pub use self::printer::Printer;
+
+pub(crate) use self::tcp::{connect_local, write_pending};
+
+use std::sync::{Arc, Mutex};
+
+/// Something plugged in the serial port.
+///
+/// When the Gameboy drives the clock, `transfer` is called once the
+/// 8 bits are shifted out. When it waits for the other side's clock,
+/// it announces the byte it will send with `set_ready`, and `poll`
+/// tells it when a transfer happened.
+pub trait SerialDevice {
+    /// Sends a byte, and returns the one shifted in.
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    /// Byte waiting to be sent on the external clock, or None if
+    /// the Gameboy isn't waiting for a transfer anymore.
+    fn set_ready(&mut self, _byte: Option<u8>) { }
+
+    /// Byte received on the external clock, if any.
+    fn poll(&mut self) -> Option<u8> { None }
+}
+
+#[derive(Default)]
+struct LinkSide {
+    ready: Option<u8>,
+    incoming: Option<u8>,
+}
+
+/// One end of a link cable between two Gameboys of the same
+/// process.
+pub struct LinkEndpoint {
+    cable: Arc<Mutex<[LinkSide; 2]>>,
+    side: usize,
+}
+
+/// Returns both ends of a link cable.
+pub fn link_cable() -> (LinkEndpoint, LinkEndpoint) {
+    let cable: Arc<Mutex<[LinkSide; 2]>> = Default::default();
+
+    (
+        LinkEndpoint { cable: cable.clone(), side: 0 },
+        LinkEndpoint { cable, side: 1 },
+    )
+}
+
+impl SerialDevice for LinkEndpoint {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut cable = self.cable.lock().unwrap();
+        let other = &mut cable[self.side ^ 1];
+
+        // Nothing is shifted in if the other side isn't listening
+        match other.ready.take() {
+            Some(received) => {
+                other.incoming = Some(byte);
+                received
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn set_ready(&mut self, byte: Option<u8>) {
+        self.cable.lock().unwrap()[self.side].ready = byte;
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.cable.lock().unwrap()[self.side].incoming.take()
+    }
+}
@@ -0,0 +1,373 @@
+// printer.rs ---
+//
+// Filename: printer.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 19:02:18 2026 (+0200)
+// Last-Updated: Sun Oct 18 19:02:18 2026 (+0200)
+//           By: Louise <louise>
+//
+// Game Boy Printer. The Gameboy sends packets made of a magic
+// number, a command, a compression flag, a length, data and a
+// checksum, and the printer answers with its ID and status. Each
+// printed sheet is saved as a BMP file.
+use crate::serial::SerialDevice;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+// Commands
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0F;
+
+// Status bits
+const STATUS_CHECKSUM: u8 = 0x01;
+const STATUS_BUSY: u8 = 0x02;
+const STATUS_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+
+// The printer memory holds 9 bands of 2 tile rows
+const BAND_SIZE: usize = 0x280;
+const BUFFER_SIZE: usize = BAND_SIZE * 9;
+
+// Status requests answered as busy after a print
+const BUSY_POLLS: u8 = 4;
+
+// Pixel rows fed for each unit of margin
+const MARGIN_ROWS: usize = 8;
+
+const WIDTH: usize = 160;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    directory: PathBuf,
+    next_file: u32,
+
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+
+    status: u8,
+    busy_polls: u8,
+    buffer: Vec<u8>,
+
+    // Shades (0 to 3) of the sheet being printed
+    sheet: Vec<u8>,
+}
+
+impl Printer {
+    /// A printer saving its sheets in the given directory
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Printer {
+        Printer {
+            directory: directory.into(),
+            next_file: 0,
+
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+
+            status: 0,
+            busy_polls: 0,
+            buffer: Vec::new(),
+
+            sheet: Vec::new(),
+        }
+    }
+
+    fn handle_packet(&mut self) {
+        if self.checksum != self.received_checksum {
+            warn!("Printer packet with a wrong checksum ({:04x} instead of {:04x})",
+                  self.received_checksum, self.checksum);
+
+            self.status |= STATUS_CHECKSUM;
+            return;
+        }
+
+        self.status &= !STATUS_CHECKSUM;
+
+        match self.command {
+            CMD_INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            CMD_DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    std::mem::take(&mut self.data)
+                };
+
+                let room = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend_from_slice(&data[..data.len().min(room)]);
+
+                if !self.buffer.is_empty() {
+                    self.status |= STATUS_UNPROCESSED;
+                }
+
+                if self.buffer.len() >= BUFFER_SIZE {
+                    self.status |= STATUS_FULL;
+                }
+            }
+            CMD_PRINT if self.data.len() >= 4 => {
+                let (sheets, margins, palette) = (self.data[0], self.data[1], self.data[2]);
+
+                self.print(sheets, margins >> 4, margins & 0xF, palette);
+
+                self.buffer.clear();
+                self.status = (self.status & !(STATUS_FULL | STATUS_UNPROCESSED)) | STATUS_BUSY;
+                self.busy_polls = BUSY_POLLS;
+            }
+            CMD_PRINT => warn!("Print packet too short ({} bytes)", self.data.len()),
+            CMD_STATUS => {
+                if self.busy_polls > 0 {
+                    self.busy_polls -= 1;
+                }
+            }
+            command => warn!("Unknown printer command {:02x} ({} bytes)", command, self.data.len()),
+        }
+    }
+
+    /// The answer to the status byte, once the packet is handled
+    fn status(&mut self) -> u8 {
+        let status = self.status;
+
+        if self.busy_polls == 0 {
+            self.status &= !STATUS_BUSY;
+        }
+
+        status
+    }
+
+    /// Adds the buffer to the current sheet. A sheet goes on until
+    /// a print with a margin after it.
+    fn print(&mut self, sheets: u8, before: u8, after: u8, palette: u8) {
+        // No sheet means feeding the paper
+        if sheets == 0 {
+            self.save_sheet();
+            return;
+        }
+
+        // A palette of 0 is the usual one
+        let palette = if palette == 0 { 0xE4 } else { palette };
+        let tiles = self.buffer.len() / 16;
+        let height = (tiles / 20) * 8;
+
+        self.feed(before);
+
+        for y in 0..height {
+            for x in 0..WIDTH {
+                let tile = (y / 8) * 20 + x / 8;
+                let offset = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+
+                let color = ((self.buffer[offset] >> bit) & 1)
+                    | (((self.buffer[offset + 1] >> bit) & 1) << 1);
+
+                self.sheet.push((palette >> (color * 2)) & 3);
+            }
+        }
+
+        self.feed(after);
+
+        if after != 0 {
+            self.save_sheet();
+        }
+    }
+
+    fn feed(&mut self, margin: u8) {
+        let rows = margin as usize * MARGIN_ROWS;
+
+        self.sheet.resize(self.sheet.len() + rows * WIDTH, 0);
+    }
+
+    fn save_sheet(&mut self) {
+        if self.sheet.is_empty() {
+            return;
+        }
+
+        let sheet = std::mem::take(&mut self.sheet);
+
+        // Never overwrite a previous print
+        let path = loop {
+            let path = self.directory.join(format!("print_{:04}.bmp", self.next_file));
+            self.next_file += 1;
+
+            if !path.exists() {
+                break path;
+            }
+        };
+
+        match File::create(&path).and_then(|mut file| file.write_all(&bmp(&sheet))) {
+            Ok(()) => info!("Printed to {}", path.display()),
+            Err(e) => warn!("Couldn't save print to {} : {}", path.display(), e),
+        }
+    }
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut answer = 0x00;
+
+        self.state = match self.state {
+            State::Magic1 if byte == 0x88 => State::Magic2,
+            State::Magic1 => State::Magic1,
+            State::Magic2 if byte == 0x33 => State::Command,
+            State::Magic2 if byte == 0x88 => State::Magic2,
+            State::Magic2 => State::Magic1,
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = (byte & 1) != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = byte as usize;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+
+                if self.length == 0 { State::ChecksumLow } else { State::Data }
+            }
+            State::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+
+                if self.data.len() == self.length { State::ChecksumLow } else { State::Data }
+            }
+            State::ChecksumLow => {
+                self.received_checksum = byte as u16;
+
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+
+                State::Alive
+            }
+            State::Alive => {
+                answer = 0x81;
+                self.handle_packet();
+
+                State::Status
+            }
+            State::Status => {
+                answer = self.status();
+
+                State::Magic1
+            }
+        };
+
+        answer
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.save_sheet();
+    }
+}
+
+/// Run-length decoding of DATA packets: a control byte with bit 7
+/// set repeats the next byte (control & 0x7F) + 2 times, otherwise
+/// (control + 1) bytes follow as is.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+
+        if (control & 0x80) != 0 {
+            let length = (control & 0x7F) as usize + 2;
+
+            if let Some(&byte) = data.get(i) {
+                output.resize(output.len() + length, byte);
+            }
+
+            i += 1;
+        } else {
+            let length = control as usize + 1;
+            let end = (i + length).min(data.len());
+
+            output.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+
+    output
+}
+
+/// 24-bit BMP of a sheet
+fn bmp(sheet: &[u8]) -> Vec<u8> {
+    const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+    let height = sheet.len() / WIDTH;
+    let image_size = WIDTH * 3 * height;
+    let mut file = Vec::with_capacity(54 + image_size);
+
+    // File header
+    file.extend_from_slice(b"BM");
+    file.extend_from_slice(&((54 + image_size) as u32).to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&54u32.to_le_bytes());
+
+    // Info header
+    file.extend_from_slice(&40u32.to_le_bytes());
+    file.extend_from_slice(&(WIDTH as i32).to_le_bytes());
+    file.extend_from_slice(&(height as i32).to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&24u16.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&(image_size as u32).to_le_bytes());
+    file.extend_from_slice(&2835u32.to_le_bytes());
+    file.extend_from_slice(&2835u32.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+
+    // Rows are stored bottom-up, 160 * 3 bytes is already aligned
+    for row in sheet.chunks(WIDTH).rev() {
+        for &shade in row {
+            let v = SHADES[shade as usize];
+            file.extend_from_slice(&[v, v, v]);
+        }
+    }
+
+    file
+}
@@ -0,0 +1,179 @@
+// tcp.rs ---
+//
+// Filename: tcp.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 18:21:50 2026 (+0200)
+// Last-Updated: Sun Oct 18 23:58:35 2026 (+0200)
+//           By: Louise <louise>
+//
+// Link cable over a local TCP socket. Both sides send 2-byte
+// messages: the byte they wait with on the external clock, and
+// the bytes they send when they drive the clock.
+use crate::serial::SerialDevice;
+
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const MSG_READY: u8 = 0;
+const MSG_NOT_READY: u8 = 1;
+const MSG_TRANSFER: u8 = 2;
+
+pub struct TcpLink {
+    stream: TcpStream,
+    connected: bool,
+
+    remote_ready: Option<u8>,
+    incoming: VecDeque<u8>,
+    buffer: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl TcpLink {
+    /// Connects to the other Gameboy if it is already listening on
+    /// the given local port, or waits for it to connect otherwise.
+    pub fn open(port: u16) -> Result<TcpLink, &'static str> {
+        let stream = connect_local(port, "Link cable")?;
+
+        Ok(TcpLink {
+            stream,
+            connected: true,
+
+            remote_ready: None,
+            incoming: VecDeque::new(),
+            buffer: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    fn send(&mut self, kind: u8, value: u8) {
+        if self.connected {
+            self.outgoing.extend_from_slice(&[kind, value]);
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.connected {
+            if let Err(e) = write_pending(&mut self.stream, &mut self.outgoing) {
+                warn!("Link cable disconnected : {}", e);
+                self.connected = false;
+                self.remote_ready = None;
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        let mut data = [0; 64];
+
+        self.flush();
+
+        while self.connected {
+            match self.stream.read(&mut data) {
+                Ok(0) => {
+                    warn!("Link cable disconnected");
+                    self.connected = false;
+                    self.remote_ready = None;
+                }
+                Ok(n) => self.buffer.extend_from_slice(&data[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => { }
+                Err(e) => {
+                    warn!("Link cable disconnected : {}", e);
+                    self.connected = false;
+                    self.remote_ready = None;
+                }
+            }
+        }
+
+        let complete = self.buffer.len() & !1;
+
+        for message in self.buffer[..complete].chunks(2) {
+            match message[0] {
+                MSG_READY => self.remote_ready = Some(message[1]),
+                MSG_NOT_READY => self.remote_ready = None,
+                MSG_TRANSFER => self.incoming.push_back(message[1]),
+                kind => warn!("Unknown link cable message {:02x}", kind),
+            }
+        }
+
+        self.buffer.drain(..complete);
+    }
+}
+
+/// Connects to the given local port, or listens on it and waits for
+/// the other side if nobody is there yet. The stream is nonblocking.
+pub(crate) fn connect_local(port: u16, what: &str) -> Result<TcpStream, &'static str> {
+    let stream = match TcpStream::connect(("127.0.0.1", port)) {
+        Ok(stream) => {
+            info!("{} connected to port {}", what, port);
+            stream
+        }
+        Err(_) => {
+            let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| {
+                error!("Couldn't listen on port {} : {}", port, e);
+                "Couldn't listen for the other Gameboy"
+            })?;
+
+            info!("Waiting for the other Gameboy on port {}", port);
+
+            let (stream, addr) = listener.accept().map_err(|e| {
+                error!("Couldn't accept the connection : {}", e);
+                "Couldn't accept the other Gameboy's connection"
+            })?;
+
+            info!("{} connected from {}", what, addr);
+            stream
+        }
+    };
+
+    let _ = stream.set_nodelay(true);
+
+    if stream.set_nonblocking(true).is_err() {
+        return Err("Couldn't set up the socket");
+    }
+
+    Ok(stream)
+}
+
+/// Writes as much of the pending data as the nonblocking socket
+/// takes, and keeps the rest for the next call.
+pub(crate) fn write_pending(stream: &mut TcpStream, pending: &mut Vec<u8>) -> io::Result<()> {
+    while !pending.is_empty() {
+        match stream.write(pending) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => { pending.drain(..n); }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => { }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+impl SerialDevice for TcpLink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.receive();
+
+        match self.remote_ready.take() {
+            Some(received) => {
+                self.send(MSG_TRANSFER, byte);
+                received
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn set_ready(&mut self, byte: Option<u8>) {
+        match byte {
+            Some(byte) => self.send(MSG_READY, byte),
+            None => self.send(MSG_NOT_READY, 0),
+        }
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.receive();
+        self.incoming.pop_front()
+    }
+}
@@ -6,9 +6,14 @@
 // Last-Updated: Wed Jun 13 12:45:57 2018 (+0200)
 //           By: Louise <louise>
 // 
+use rgba_common::savestate;
 
+/// DIV and TIMA are not ticked every cycle, they are computed from
+/// the timestamp of the last update, and the Interconnect schedules
+/// the overflows.
 pub struct Timer {
     div: u16,
+    last_update: u64,
 
     tima: u8,
     tma: u8,
@@ -19,14 +24,17 @@ pub struct Timer {
     it_timer: bool,
 }
 
+savestate!(Timer, div, last_update, tima, tma, tima_running, speed, it_timer);
+
 impl Timer {
     pub fn new() -> Timer {
         Timer {
             div: 0,
+            last_update: 0,
             tima: 0,
             tma: 0,
             tima_running: false,
-            speed: 10,
+            speed: 1023,
 
             it_timer: false
         }
@@ -34,6 +42,7 @@ impl Timer {
 
     pub fn reset(&mut self) {
         self.div = 0;
+        self.last_update = 0;
         self.tima = 0;
         self.tma = 0;
         self.tima_running = false;
@@ -41,28 +50,77 @@ impl Timer {
         self.it_timer = false;
     }
 
-    pub fn handle(&mut self) {
-        self.div = self.div.wrapping_add(1);
-        
-        if self.tima_running {  
-            if (self.div & self.speed) == 0 {
-                self.tima = self.tima.wrapping_add(1);
-
-                if self.tima == 0 {
-                    self.tima = self.tma;
-                    self.it_timer = true;
-                }
-            }
+    #[inline]
+    fn div_at(&self, now: u64) -> u16 {
+        self.div.wrapping_add((now - self.last_update) as u16)
+    }
+
+    /// TIMA at the given time, and whether it overflowed since
+    /// the last update. TIMA increments when DIV crosses a
+    /// multiple of the period, which divides 0x10000.
+    fn tima_at(&self, now: u64) -> (u8, bool) {
+        if !self.tima_running {
+            return (self.tima, false);
+        }
+
+        let period = self.speed as u64 + 1;
+        let div = self.div as u64;
+        let ticks = (div + now - self.last_update) / period - div / period;
+        let total = self.tima as u64 + ticks;
+
+        if total < 0x100 {
+            (total as u8, false)
+        } else {
+            let tma = self.tma as u64;
+
+            ((tma + (total - 0x100) % (0x100 - tma)) as u8, true)
+        }
+    }
+
+    /// Brings DIV and TIMA up to date, this is also how an
+    /// overflow is handled.
+    pub fn update(&mut self, now: u64) {
+        let (tima, overflow) = self.tima_at(now);
+
+        self.div = self.div_at(now);
+        self.tima = tima;
+        self.last_update = now;
+
+        if overflow {
+            self.it_timer = true;
+        }
+    }
+
+    /// When TIMA will overflow, if it is running
+    pub fn next_overflow(&self) -> Option<u64> {
+        if self.tima_running {
+            let period = self.speed as u64 + 1;
+            let div = self.div as u64;
+            let target = (div / period + 0x100 - self.tima as u64) * period;
+
+            Some(self.last_update + target - div)
+        } else {
+            None
         }
     }
 
-    pub fn get_internal(&self) -> u16 { self.div }
+    pub fn get_internal(&self, now: u64) -> u16 { self.div_at(now) }
+    pub fn set_internal(&mut self, now: u64, div: u16) {
+        self.update(now);
+        self.div = div;
+    }
     
-    pub fn div(&self) -> u8 { (self.div >> 8) as u8 }
-    pub fn set_div(&mut self) { self.div = 0; }
+    pub fn div(&self, now: u64) -> u8 { (self.div_at(now) >> 8) as u8 }
+    pub fn set_div(&mut self, now: u64) {
+        self.update(now);
+        self.div = 0;
+    }
 
-    pub fn set_tima(&mut self, tima: u8) { self.tima = tima; }
-    pub fn tima(&self) -> u8 { self.tima }
+    pub fn set_tima(&mut self, now: u64, tima: u8) {
+        self.update(now);
+        self.tima = tima;
+    }
+    pub fn tima(&self, now: u64) -> u8 { self.tima_at(now).0 }
     
     pub fn tma(&self) -> u8 { self.tma }
     pub fn set_tma(&mut self, tma: u8) { self.tma = tma; }
@@ -79,7 +137,9 @@ impl Timer {
         ((self.tima_running as u8) << 2) | speed
     }
 
-    pub fn set_tac(&mut self, value: u8) {
+    pub fn set_tac(&mut self, now: u64, value: u8) {
+        self.update(now);
+
         self.speed = match value & 0x3 {
             0 => 1023,
             1 => 15,
@@ -0,0 +1,51 @@
+// fifo.rs ---
+//
+// Filename: fifo.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 18:03:21 2026 (+0200)
+// Last-Updated: Sun Oct 18 18:03:21 2026 (+0200)
+//           By: Louise <louise>
+//
+use rgba_common::savestate;
+
+/// One of the two Direct Sound channels, a 32-byte queue of
+/// signed 8-bit PCM samples.
+#[derive(Debug, Default, Clone)]
+pub struct Fifo {
+    buffer: [i8; 32],
+    read: usize,
+    len: usize,
+
+    current: i8,
+}
+
+savestate!(Fifo, buffer, read, len, current);
+
+impl Fifo {
+    pub fn push(&mut self, sample: u8) {
+        if self.len < 32 {
+            self.buffer[(self.read + self.len) & 0x1f] = sample as i8;
+            self.len += 1;
+        }
+    }
+
+    /// Moves to the next sample, returns true if the FIFO
+    /// needs to be refilled.
+    pub fn pop(&mut self) -> bool {
+        if self.len > 0 {
+            self.current = self.buffer[self.read];
+            self.read = (self.read + 1) & 0x1f;
+            self.len -= 1;
+        }
+
+        self.len <= 16
+    }
+
+    pub fn reset(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+
+    #[inline]
+    pub fn sample(&self) -> i16 { self.current as i16 }
+}
@@ -3,19 +3,65 @@
 // Filename: mod.rs
 // Author: Louise <louise>
 // Created: Sun Jan 21 22:26:01 2018 (+0100)
-// Last-Updated: Thu Jul 12 16:59:10 2018 (+0200)
-//           By: Louise <ludwigette>
+// Last-Updated: Sun Oct 18 18:03:21 2026 (+0200)
+//           By: Louise <louise>
 // 
+mod fifo;
+mod wave;
 
-#[derive(Default, Debug, Clone)]
+use crate::apu::fifo::Fifo;
+use crate::apu::wave::WaveChannel;
+
+use rgba_common::{Platform, SAMPLE_RATE};
+use rgba_common::apu::{SquareChannel, NoiseChannel};
+use rgba_common::savestate;
+
+const CPU_CLOCK: u64 = 16_777_216;
+
+#[derive(Default, Debug)]
 pub struct APU {
+    // PSG, the Gameboy channels
+    channel1: SquareChannel,
+    channel2: SquareChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+
+    soundcnt_l: u16,
+    psg_cycles: u32,
+    frame_cycles: u32,
+    frame_sequencer: u8,
+
+    soundcnt_h: u16,
+    master_enable: bool,
     soundbias: u16,
+
+    fifo: [Fifo; 2],
+
+    samples: Vec<i16>,
+    samples_index: usize,
+    buffer_complete: bool,
+
+    sample_clock: u64,
 }
 
+savestate!(APU,
+           channel1, channel2, channel3, channel4,
+           soundcnt_l, psg_cycles, frame_cycles, frame_sequencer,
+           soundcnt_h, master_enable, soundbias, fifo,
+           samples, samples_index, buffer_complete, sample_clock);
+
 impl APU {
     pub fn new() -> APU {
         APU {
+            channel1: SquareChannel::new(),
+            channel2: SquareChannel::new(),
+            channel3: WaveChannel::new(),
+            channel4: NoiseChannel::new(),
+
             soundbias: 0x0200,
+            samples: vec![0; 1024],
+
+            ..Default::default()
         }
     }
 
@@ -27,21 +73,273 @@ impl APU {
         self.soundbias = soundbias;
     }
 
+    pub fn set_soundcnt_h(&mut self, value: u16) {
+        // The reset bits are not stored
+        self.soundcnt_h = value & 0x770F;
+
+        if (value & 0x0800) != 0 { self.fifo[0].reset(); }
+        if (value & 0x8000) != 0 { self.fifo[1].reset(); }
+    }
+
     pub fn io_read_u16(&self, address: usize) -> u16 {
         match address {
+            SOUND1CNT_L => self.channel1.nr0() as u16,
+            SOUND1CNT_H => (self.channel1.nr1() as u16) | ((self.channel1.nr2() as u16) << 8),
+            SOUND1CNT_X => ((self.channel1.nr4() & 0x40) as u16) << 8,
+            SOUND2CNT_L => (self.channel2.nr1() as u16) | ((self.channel2.nr2() as u16) << 8),
+            SOUND2CNT_H => ((self.channel2.nr4() & 0x40) as u16) << 8,
+            SOUND3CNT_L => self.channel3.cnt_l(),
+            SOUND3CNT_H => self.channel3.cnt_h(),
+            SOUND3CNT_X => self.channel3.cnt_x(),
+            SOUND4CNT_L => (self.channel4.nr2() as u16) << 8,
+            SOUND4CNT_H => (self.channel4.nr3() as u16) | (((self.channel4.nr4() & 0x40) as u16) << 8),
+
+            SOUNDCNT_L => self.soundcnt_l,
+            SOUNDCNT_H => self.soundcnt_h,
+            SOUNDCNT_X => {
+                ((self.master_enable as u16) << 7) |
+                ((self.channel4.enabled as u16) << 3) |
+                ((self.channel3.enabled() as u16) << 2) |
+                ((self.channel2.enabled() as u16) << 1) |
+                (self.channel1.enabled() as u16)
+            }
             SOUNDBIAS => self.soundbias(),
+
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                (self.channel3.wave(address) as u16) | ((self.channel3.wave(address + 1) as u16) << 8)
+            }
+
+            // Unused halves
+            0x04000066 | 0x0400006A | 0x0400006E | 0x04000076 |
+            0x0400007A | 0x0400007E | 0x04000086 | 0x0400008A => 0,
             _ => { warn!("Unmapped read_u16 from {:08x} (APU)", address); 0 },
         }
     }
     
     pub fn io_write_u16(&mut self, address: usize, value: u16) {
         match address {
+            SOUND1CNT_L => self.channel1.set_nr0(value as u8),
+            SOUND1CNT_H => {
+                self.channel1.set_nr1(value as u8);
+                self.channel1.set_nr2((value >> 8) as u8);
+            }
+            SOUND1CNT_X => {
+                self.channel1.set_nr3(value as u8);
+                self.channel1.set_nr4((value >> 8) as u8);
+            }
+            SOUND2CNT_L => {
+                self.channel2.set_nr1(value as u8);
+                self.channel2.set_nr2((value >> 8) as u8);
+            }
+            SOUND2CNT_H => {
+                self.channel2.set_nr3(value as u8);
+                self.channel2.set_nr4((value >> 8) as u8);
+            }
+            SOUND3CNT_L => self.channel3.set_cnt_l(value),
+            SOUND3CNT_H => self.channel3.set_cnt_h(value),
+            SOUND3CNT_X => self.channel3.set_cnt_x(value),
+            SOUND4CNT_L => {
+                self.channel4.set_nr1(value as u8);
+                self.channel4.set_nr2((value >> 8) as u8);
+            }
+            SOUND4CNT_H => {
+                self.channel4.set_nr3(value as u8);
+                self.channel4.set_nr4((value >> 8) as u8);
+            }
+
+            SOUNDCNT_L => self.soundcnt_l = value & 0xFF77,
+            SOUNDCNT_H => self.set_soundcnt_h(value),
+            SOUNDCNT_X => self.master_enable = (value & 0x80) != 0,
             SOUNDBIAS => self.set_soundbias(value),
+
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                self.channel3.set_wave(address, value as u8);
+                self.channel3.set_wave(address + 1, (value >> 8) as u8);
+            }
+
+            FIFO_A_L | FIFO_A_H => {
+                self.fifo[0].push(value as u8);
+                self.fifo[0].push((value >> 8) as u8);
+            }
+            FIFO_B_L | FIFO_B_H => {
+                self.fifo[1].push(value as u8);
+                self.fifo[1].push((value >> 8) as u8);
+            }
             // We don't log unknown APU writes yet, because we don't really care for now
             // _ => warn!("Unmapped write_u16 to {:08x} (APU, value={:04x})", address, value),
             _ => { }
         }
     }
+
+    /// Called when timer 0 or 1 overflows, returns a mask of the
+    /// FIFOs that need to be refilled by DMA.
+    pub fn timer_overflow(&mut self, timer: usize) -> u8 {
+        let mut refill = 0;
+
+        for i in 0..2 {
+            let timer_select = ((self.soundcnt_h >> (10 + i * 4)) & 1) as usize;
+
+            if timer_select == timer && self.fifo[i].pop() {
+                refill |= 1 << i;
+            }
+        }
+
+        refill
+    }
+
+    // Actual APU
+    pub fn render<T: Platform>(&mut self, platform: &mut T) {
+        if self.buffer_complete {
+            platform.queue_samples(&self.samples);
+
+            self.buffer_complete = false;
+        }
+    }
+
+    /// PSG output for one side, from 0 to 480 at full volume
+    fn psg(&self, side: u16) -> i16 {
+        let enables = self.soundcnt_l >> (8 + side * 4);
+        let volume = ((self.soundcnt_l >> (side * 4)) & 7) + 1;
+
+        let mut output = 0;
+
+        if (enables & 1) != 0 { output += self.channel1.render() as u16; }
+        if (enables & 2) != 0 { output += self.channel2.render() as u16; }
+        if (enables & 4) != 0 { output += self.channel3.render() as u16; }
+        if (enables & 8) != 0 { output += self.channel4.render() as u16; }
+
+        // SOUNDCNT_H selects 25%, 50% or 100%
+        ((output * volume) >> (2 - (self.soundcnt_h & 3).min(2))) as i16
+    }
+
+    /// Mixes the PSG and both FIFOs, biased and clamped to 10 bits
+    /// like the hardware does, then brought back to 16 bits.
+    fn mix(&self) -> i16 {
+        if !self.master_enable {
+            return 0;
+        }
+
+        let mut right = self.psg(0);
+        let mut left = self.psg(1);
+
+        for i in 0..2 {
+            let full_volume = (self.soundcnt_h & (4 << i)) != 0;
+            let sample = self.fifo[i].sample() << if full_volume { 2 } else { 1 };
+
+            if (self.soundcnt_h & (0x100 << (i * 4))) != 0 { right += sample; }
+            if (self.soundcnt_h & (0x200 << (i * 4))) != 0 { left += sample; }
+        }
+
+        let bias = (self.soundbias & 0x3FE) as i16;
+        let left = (left + bias).clamp(0, 0x3FF) - 0x200;
+        let right = (right + bias).clamp(0, 0x3FF) - 0x200;
+
+        (left + right) << 4
+    }
+
+    // The PSG runs at the Gameboy's clock
+    fn spend_psg_cycles(&mut self, cycles: u32) {
+        self.psg_cycles += cycles;
+
+        let cycles = self.psg_cycles >> 2;
+        self.psg_cycles &= 3;
+
+        self.frame_cycles += cycles;
+
+        if self.frame_cycles >= 8192 {
+            self.frame_cycles -= 8192;
+
+            match self.frame_sequencer {
+                0 | 4 => {
+                    self.channel1.length_click();
+                    self.channel2.length_click();
+                    self.channel3.length_click();
+                    self.channel4.length_click();
+                },
+                2 | 6 => {
+                    self.channel1.sweep_click();
+                    self.channel1.length_click();
+                    self.channel2.length_click();
+                    self.channel3.length_click();
+                    self.channel4.length_click();
+                },
+                7 => {
+                    self.channel1.envelope_click();
+                    self.channel2.envelope_click();
+                    self.channel4.envelope_click();
+                },
+                _ => { }
+            }
+
+            self.frame_sequencer = (self.frame_sequencer + 1) & 0x7;
+        }
+
+        let cycles_16 = cycles as u16;
+
+        if self.channel1.enabled {
+            self.channel1.spend_cycles(cycles_16);
+        }
+
+        if self.channel2.enabled {
+            self.channel2.spend_cycles(cycles_16);
+        }
+
+        if self.channel3.enabled {
+            self.channel3.spend_cycles(cycles_16);
+        }
+
+        if self.channel4.enabled {
+            self.channel4.spend_cycles(cycles_16);
+        }
+    }
+
+    /// Cycles until the next sample is mixed
+    pub fn cycles_to_next_sample(&self) -> u32 {
+        let remaining = CPU_CLOCK - self.sample_clock;
+
+        remaining.div_ceil(SAMPLE_RATE as u64) as u32
+    }
+
+    pub fn spend_cycles(&mut self, cycles: u32) {
+        if self.master_enable {
+            self.spend_psg_cycles(cycles);
+        }
+
+        self.sample_clock += (cycles as u64) * (SAMPLE_RATE as u64);
+
+        while self.sample_clock >= CPU_CLOCK {
+            self.sample_clock -= CPU_CLOCK;
+
+            self.samples[self.samples_index] = self.mix();
+            self.samples_index = (self.samples_index + 1) & 0x3ff;
+
+            if self.samples_index == 0 {
+                self.buffer_complete = true;
+            }
+        }
+    }
 }
 
-const SOUNDBIAS: usize = 0x04000088;
+const SOUND1CNT_L: usize = 0x04000060;
+const SOUND1CNT_H: usize = 0x04000062;
+const SOUND1CNT_X: usize = 0x04000064;
+const SOUND2CNT_L: usize = 0x04000068;
+const SOUND2CNT_H: usize = 0x0400006C;
+const SOUND3CNT_L: usize = 0x04000070;
+const SOUND3CNT_H: usize = 0x04000072;
+const SOUND3CNT_X: usize = 0x04000074;
+const SOUND4CNT_L: usize = 0x04000078;
+const SOUND4CNT_H: usize = 0x0400007C;
+
+const SOUNDCNT_L: usize = 0x04000080;
+const SOUNDCNT_H: usize = 0x04000082;
+const SOUNDCNT_X: usize = 0x04000084;
+const SOUNDBIAS:  usize = 0x04000088;
+
+const WAVE_RAM_START: usize = 0x04000090;
+const WAVE_RAM_END:   usize = 0x0400009E;
+
+pub const FIFO_A_L: usize = 0x040000A0;
+const FIFO_A_H:     usize = 0x040000A2;
+pub const FIFO_B_L: usize = 0x040000A4;
+const FIFO_B_H:     usize = 0x040000A6;
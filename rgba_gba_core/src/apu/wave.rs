@@ -0,0 +1,152 @@
+// wave.rs ---
+//
+// Filename: wave.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 18:41:09 2026 (+0200)
+// Last-Updated: Sun Oct 18 18:41:09 2026 (+0200)
+//           By: Louise <louise>
+//
+// Wave channel, as in the DMG core but with two banks of
+// 32 samples which can be played one after the other.
+//
+use rgba_common::savestate;
+
+#[derive(Debug, Default)]
+pub struct WaveChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+
+    timer: u16,
+    timer_load: u16,
+    frequency: u16,
+    out_volume: u8,
+    volume: u8,
+    force_volume: bool,
+
+    // Length counter
+    length_counter: u16,
+    length_load: u8,
+    length_enable: bool,
+
+    // Wave RAM banks
+    two_banks: bool,
+    bank: usize,
+
+    wave_data: [[u8; 0x20]; 2],
+    wave_state: usize,
+}
+
+savestate!(WaveChannel,
+           enabled, dac_enabled, timer, timer_load, frequency, out_volume,
+           volume, force_volume,
+           length_counter, length_load, length_enable,
+           two_banks, bank, wave_data, wave_state);
+
+impl WaveChannel {
+    pub fn new() -> WaveChannel {
+        Default::default()
+    }
+
+    pub fn enabled(&self) -> bool { self.enabled }
+
+    pub fn cnt_l(&self) -> u16 {
+        ((self.two_banks as u16) << 5) |
+        ((self.bank as u16) << 6) |
+        ((self.dac_enabled as u16) << 7)
+    }
+
+    pub fn set_cnt_l(&mut self, value: u16) {
+        self.two_banks = (value & 0x20) != 0;
+        self.bank = ((value >> 6) & 1) as usize;
+        self.dac_enabled = (value & 0x80) != 0;
+
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn cnt_h(&self) -> u16 {
+        ((self.volume as u16) << 13) | ((self.force_volume as u16) << 15)
+    }
+
+    pub fn set_cnt_h(&mut self, value: u16) {
+        self.length_load = value as u8;
+        self.length_counter = 256 - (self.length_load as u16);
+
+        self.volume = ((value >> 13) & 3) as u8;
+        self.force_volume = (value & 0x8000) != 0;
+    }
+
+    pub fn cnt_x(&self) -> u16 { (self.length_enable as u16) << 14 }
+
+    pub fn set_cnt_x(&mut self, value: u16) {
+        self.length_enable = (value & 0x4000) != 0;
+        self.frequency = value & 0x7ff;
+
+        if (value & 0x8000) != 0 {
+            self.enabled = self.dac_enabled;
+
+            if self.length_counter == 0 {
+                self.length_counter = 256;
+            }
+
+            self.timer_load = (2048 - self.frequency) << 1;
+            self.timer = 0;
+            self.wave_state = 0;
+        }
+    }
+
+    // The CPU accesses the bank that isn't being played
+    pub fn wave(&self, address: usize) -> u8 {
+        let bank = &self.wave_data[self.bank ^ 1];
+        let offset = (address & 0xF) << 1;
+
+        (bank[offset] << 4) | bank[offset + 1]
+    }
+
+    pub fn set_wave(&mut self, address: usize, value: u8) {
+        let bank = &mut self.wave_data[self.bank ^ 1];
+        let offset = (address & 0xF) << 1;
+
+        bank[offset] = (value & 0xF0) >> 4;
+        bank[offset + 1] = value & 0xF;
+    }
+
+    pub fn length_click(&mut self) {
+        if (self.length_counter > 0) && self.length_enable {
+            self.length_counter -= 1;
+
+            if self.length_counter == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    #[inline]
+    pub fn render(&self) -> u8 { self.out_volume }
+
+    pub fn spend_cycles(&mut self, cycles: u16) {
+        self.timer = self.timer.wrapping_add(cycles);
+
+        while self.timer >= self.timer_load {
+            self.timer -= self.timer_load;
+
+            let samples = if self.two_banks { 0x40 } else { 0x20 };
+            self.wave_state = (self.wave_state + 1) % samples;
+
+            let position = (self.bank << 5) + self.wave_state;
+            let sample = self.wave_data[(position >> 5) & 1][position & 0x1F];
+
+            self.out_volume = if self.force_volume {
+                (sample * 3) >> 2
+            } else {
+                match self.volume {
+                    1 => sample,
+                    2 => sample >> 1,
+                    3 => sample >> 2,
+                    _ => 0,
+                }
+            };
+        }
+    }
+}
@@ -62,7 +62,7 @@ def write_branch_exchange(g):
     g.write("_cpu.branch(_io);")
 
 def write_swi(g):
-    g.write("_cpu.raise_swi();")
+    g.write("_cpu.raise_swi(_io, (instr >> 16) & 0xFF);")
     
 def write_op2_imm(g, high, low):
     s = (high & 0x01) != 0
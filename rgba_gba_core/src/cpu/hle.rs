@@ -0,0 +1,456 @@
+// hle.rs ---
+//
+// Filename: hle.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 19:20:32 2026 (+0200)
+// Last-Updated: Sun Oct 18 19:20:32 2026 (+0200)
+//           By: Louise <louise>
+//
+// High-level emulation of the BIOS calls, used when no BIOS
+// file is given.
+//
+use crate::cpu::{ARM7TDMI, CpuMode, CpuState};
+use crate::io::Interconnect;
+
+// Flags the IRQ handler sets for IntrWait
+const BIOS_IF: usize = 0x03007FF8;
+
+const IME: usize = 0x04000208;
+const HALTCNT: usize = 0x04000301;
+
+impl ARM7TDMI {
+    /// State of the CPU after the BIOS boot sequence
+    pub fn skip_bios(&mut self, io: &mut Interconnect) {
+        self.registers = [0; 31];
+
+        self.registers[13] = 0x03007F00;
+        self.registers[16] = 0x03007FA0; // IRQ r13
+        self.registers[18] = 0x03007FE0; // SVC r13
+
+        self.mode = CpuMode::System;
+        self.state = CpuState::ARM;
+        self.irq = false;
+        self.fiq = false;
+        self.intr_wait = 0;
+
+        self.registers[15] = 0x08000000;
+        self.branch(io);
+    }
+
+    pub fn hle_swi(&mut self, io: &mut Interconnect, comment: u32) {
+        let r0 = self.get_register(0);
+        let r1 = self.get_register(1);
+        let r2 = self.get_register(2);
+        let r3 = self.get_register(3);
+
+        match comment {
+            0x00 => self.soft_reset(io),
+            0x01 => register_ram_reset(io, r0),
+            0x02 => io.write_u8(HALTCNT, 0),
+            0x03 => io.write_u8(HALTCNT, 0x80),
+            0x04 => self.intr_wait(io, r0 != 0, r1 as u16),
+            0x05 => self.intr_wait(io, true, 1),
+            0x06 => self.div(r0 as i32, r1 as i32),
+            0x07 => self.div(r1 as i32, r0 as i32),
+            0x08 => self.set_register(0, (r0 as f64).sqrt() as u32),
+            0x09 => self.set_register(0, arctan(r0 as i16 as i32) as u32),
+            0x0A => self.set_register(0, arctan2(r0 as i16 as i32, r1 as i16 as i32)),
+            0x0B => cpu_set(io, r0, r1, r2),
+            0x0C => cpu_fast_set(io, r0, r1, r2),
+            0x0D => self.set_register(0, 0xBAAE187F),
+            0x0E => bg_affine_set(io, r0, r1, r2),
+            0x0F => obj_affine_set(io, r0, r1, r2, r3),
+            0x10 => bit_unpack(io, r0, r1, r2),
+            0x11 | 0x12 => { let data = lz77_uncomp(io, r0); write_buffer(io, r1, &data) },
+            0x13 => { let data = huff_uncomp(io, r0); write_buffer(io, r1, &data) },
+            0x14 | 0x15 => { let data = rl_uncomp(io, r0); write_buffer(io, r1, &data) },
+            0x19 => io.write_u16(0x04000088, if r0 != 0 { 0x200 } else { 0 }),
+            0x1F => self.set_register(0, midi_key_to_freq(io, r0, r1, r2)),
+            _ => warn!("Unimplemented BIOS call {:02x}", comment),
+        }
+    }
+
+    fn soft_reset(&mut self, io: &mut Interconnect) {
+        let to_ram = io.read_u8(0x03007FFA) != 0;
+
+        for address in (0x03007E00..0x03008000).step_by(4) {
+            io.write_u32(address, 0);
+        }
+
+        self.skip_bios(io);
+
+        if to_ram {
+            self.registers[15] = 0x02000000;
+            self.branch(io);
+        }
+    }
+
+    // The BIOS halts until one of the requested interrupts is
+    // flagged by the IRQ handler. We halt, and execute the SWI
+    // again when woken up, remembering what we are waiting for.
+    fn intr_wait(&mut self, io: &mut Interconnect, discard: bool, flags: u16) {
+        let (discard, flags) = if self.intr_wait != 0 {
+            (false, self.intr_wait)
+        } else {
+            (discard, flags)
+        };
+
+        io.write_u16(IME, 1);
+
+        let mut bios_if = io.read_u16(BIOS_IF);
+
+        if discard {
+            bios_if &= !flags;
+        }
+
+        if (bios_if & flags) != 0 {
+            io.write_u16(BIOS_IF, bios_if & !flags);
+            self.intr_wait = 0;
+        } else {
+            io.write_u16(BIOS_IF, bios_if);
+            io.write_u8(HALTCNT, 0);
+
+            self.intr_wait = flags;
+            self.pc -= match self.state {
+                CpuState::ARM => 4,
+                CpuState::Thumb => 2,
+            };
+        }
+    }
+
+    fn div(&mut self, number: i32, denom: i32) {
+        if denom == 0 {
+            warn!("Division by zero in BIOS call");
+            return;
+        }
+
+        let quot = number.wrapping_div(denom);
+
+        self.set_register(0, quot as u32);
+        self.set_register(1, number.wrapping_rem(denom) as u32);
+        self.set_register(3, quot.unsigned_abs());
+    }
+}
+
+fn register_ram_reset(io: &mut Interconnect, flags: u32) {
+    let regions: [(usize, usize); 5] = [
+        (0x02000000, 0x40000), // EWRAM
+        (0x03000000, 0x7E00),  // IWRAM, without the stacks
+        (0x05000000, 0x400),   // Palette
+        (0x06000000, 0x18000), // VRAM
+        (0x07000000, 0x400),   // OAM
+    ];
+
+    for (i, &(start, len)) in regions.iter().enumerate() {
+        if (flags & (1 << i)) != 0 {
+            for address in (start..(start + len)).step_by(4) {
+                io.write_u32(address, 0);
+            }
+        }
+    }
+
+    // Sound registers
+    if (flags & 0x40) != 0 {
+        for address in (0x04000060..0x040000A8).step_by(2) {
+            io.write_u16(address, 0);
+        }
+    }
+}
+
+// Polynomial approximation used by the BIOS. Its 32-bit MULs
+// overflow near the ends of the range, so they wrap here too.
+fn arctan(x: i32) -> i32 {
+    let a = -(x.wrapping_mul(x) >> 14);
+    let mut b = (0xA9i32.wrapping_mul(a) >> 14) + 0x390;
+
+    for &c in [0x91C, 0xFB6, 0x16AA, 0x2081, 0x3651, 0xA2F9].iter() {
+        b = (a.wrapping_mul(b) >> 14).wrapping_add(c);
+    }
+
+    x.wrapping_mul(b) >> 16
+}
+
+fn arctan2(x: i32, y: i32) -> u32 {
+    let angle = if y == 0 {
+        if x >= 0 { 0 } else { 0x8000 }
+    } else if x == 0 {
+        if y >= 0 { 0x4000 } else { 0xC000 }
+    } else if y >= 0 {
+        if x >= 0 {
+            if x >= y { arctan((y << 14) / x) } else { 0x4000 - arctan((x << 14) / y) }
+        } else if -x >= y {
+            0x8000 + arctan((y << 14) / x)
+        } else {
+            0x4000 - arctan((x << 14) / y)
+        }
+    } else if x <= 0 {
+        if -x > -y { 0x8000 + arctan((y << 14) / x) } else { 0xC000 - arctan((x << 14) / y) }
+    } else if x >= -y {
+        0x10000 + arctan((y << 14) / x)
+    } else {
+        0xC000 - arctan((x << 14) / y)
+    };
+
+    (angle as u32) & 0xFFFF
+}
+
+fn cpu_set(io: &mut Interconnect, source: u32, dest: u32, cnt: u32) {
+    let count = (cnt & 0x1FFFFF) as usize;
+    let fixed = (cnt & (1 << 24)) != 0;
+
+    if (cnt & (1 << 26)) != 0 {
+        let (source, dest) = ((source & !3) as usize, (dest & !3) as usize);
+
+        for i in 0..count {
+            let v = io.read_u32(if fixed { source } else { source + (i << 2) });
+            io.write_u32(dest + (i << 2), v);
+        }
+    } else {
+        let (source, dest) = ((source & !1) as usize, (dest & !1) as usize);
+
+        for i in 0..count {
+            let v = io.read_u16(if fixed { source } else { source + (i << 1) });
+            io.write_u16(dest + (i << 1), v);
+        }
+    }
+}
+
+fn cpu_fast_set(io: &mut Interconnect, source: u32, dest: u32, cnt: u32) {
+    // Always words, by blocks of 8
+    let count = ((cnt & 0x1FFFFF) + 7) & !7;
+
+    cpu_set(io, source, dest, (cnt & (1 << 24)) | (1 << 26) | count);
+}
+
+// sin and cos of the angle, as the BIOS computes them from
+// its table, in 1.14 fixed point
+fn sin_cos(theta: u16) -> (i32, i32) {
+    let angle = ((theta >> 8) as f64) * std::f64::consts::PI / 128.0;
+
+    ((angle.sin() * 16384.0) as i32, (angle.cos() * 16384.0) as i32)
+}
+
+fn bg_affine_set(io: &mut Interconnect, source: u32, dest: u32, count: u32) {
+    let (mut source, mut dest) = (source as usize, dest as usize);
+
+    for _ in 0..count {
+        let ox = io.read_u32(source) as i32;
+        let oy = io.read_u32(source + 4) as i32;
+        let cx = io.read_u16(source + 8) as i16 as i32;
+        let cy = io.read_u16(source + 10) as i16 as i32;
+        let sx = io.read_u16(source + 12) as i16 as i32;
+        let sy = io.read_u16(source + 14) as i16 as i32;
+        let (sin, cos) = sin_cos(io.read_u16(source + 16));
+
+        let pa = (sx * cos) >> 14;
+        let pb = -((sx * sin) >> 14);
+        let pc = (sy * sin) >> 14;
+        let pd = (sy * cos) >> 14;
+
+        io.write_u16(dest, pa as u16);
+        io.write_u16(dest + 2, pb as u16);
+        io.write_u16(dest + 4, pc as u16);
+        io.write_u16(dest + 6, pd as u16);
+        io.write_u32(dest + 8, (ox - (pa * cx + pb * cy)) as u32);
+        io.write_u32(dest + 12, (oy - (pc * cx + pd * cy)) as u32);
+
+        source += 20;
+        dest += 16;
+    }
+}
+
+fn obj_affine_set(io: &mut Interconnect, source: u32, dest: u32, count: u32, stride: u32) {
+    let (mut source, mut dest, stride) = (source as usize, dest as usize, stride as usize);
+
+    for _ in 0..count {
+        let sx = io.read_u16(source) as i16 as i32;
+        let sy = io.read_u16(source + 2) as i16 as i32;
+        let (sin, cos) = sin_cos(io.read_u16(source + 4));
+
+        io.write_u16(dest, ((sx * cos) >> 14) as u16);
+        io.write_u16(dest + stride, (-((sx * sin) >> 14)) as u16);
+        io.write_u16(dest + stride * 2, ((sy * sin) >> 14) as u16);
+        io.write_u16(dest + stride * 3, ((sy * cos) >> 14) as u16);
+
+        source += 8;
+        dest += stride * 4;
+    }
+}
+
+fn bit_unpack(io: &mut Interconnect, source: u32, dest: u32, info: u32) {
+    let info = info as usize;
+    let len = io.read_u16(info) as usize;
+    let src_width = io.read_u8(info + 2) as u32;
+    let dst_width = io.read_u8(info + 3) as u32;
+    let offset = io.read_u32(info + 4);
+    let zero_data = (offset & 0x80000000) != 0;
+    let offset = offset & 0x7FFFFFFF;
+
+    if ![1, 2, 4, 8].contains(&src_width) || ![1, 2, 4, 8, 16, 32].contains(&dst_width) {
+        warn!("Bad widths for BitUnPack ({} to {})", src_width, dst_width);
+        return;
+    }
+
+    let mut dest = (dest & !3) as usize;
+    let mut word: u32 = 0;
+    let mut bits = 0;
+
+    for i in 0..len {
+        let byte = io.read_u8(source as usize + i) as u32;
+
+        for shift in (0..8).step_by(src_width as usize) {
+            let mut value = (byte >> shift) & ((1 << src_width) - 1);
+
+            if value != 0 || zero_data {
+                value = value.wrapping_add(offset);
+            }
+
+            word |= value << bits;
+            bits += dst_width;
+
+            if bits >= 32 {
+                io.write_u32(dest, word);
+                dest += 4;
+                word = 0;
+                bits = 0;
+            }
+        }
+    }
+}
+
+fn lz77_uncomp(io: &Interconnect, source: u32) -> Vec<u8> {
+    let mut source = source as usize;
+    let size = (io.read_u32(source) >> 8) as usize;
+    let mut output = Vec::with_capacity(size);
+
+    source += 4;
+
+    while output.len() < size {
+        let flags = io.read_u8(source);
+        source += 1;
+
+        for block in (0..8).rev() {
+            if output.len() >= size {
+                break;
+            }
+
+            if (flags & (1 << block)) != 0 {
+                let b0 = io.read_u8(source) as usize;
+                let b1 = io.read_u8(source + 1) as usize;
+                source += 2;
+
+                let disp = (((b0 & 0xF) << 8) | b1) + 1;
+                let len = (b0 >> 4) + 3;
+
+                for _ in 0..len {
+                    let byte = if disp <= output.len() { output[output.len() - disp] } else { 0 };
+                    output.push(byte);
+                }
+            } else {
+                output.push(io.read_u8(source));
+                source += 1;
+            }
+        }
+    }
+
+    output.truncate(size);
+    output
+}
+
+fn huff_uncomp(io: &Interconnect, source: u32) -> Vec<u8> {
+    let source = source as usize;
+    let header = io.read_u32(source);
+    let data_size = header & 0xF;
+    let size = (header >> 8) as usize;
+    let mut output = Vec::with_capacity(size);
+
+    if data_size != 4 && data_size != 8 {
+        warn!("Bad data size for Huffman ({})", data_size);
+        return output;
+    }
+
+    let tree = source + 5;
+    let mut bitstream = source + 4 + ((io.read_u8(source + 4) as usize) + 1) * 2;
+
+    let mut node_addr = tree;
+    let mut word: u32 = 0;
+    let mut bits = 0;
+
+    while output.len() < size {
+        let data = io.read_u32(bitstream);
+        bitstream += 4;
+
+        for bit in (0..32).rev() {
+            let direction = ((data >> bit) & 1) as usize;
+            let node = io.read_u8(node_addr) as usize;
+            let next = (node_addr & !1) + ((node & 0x3F) << 1) + 2 + direction;
+
+            if (node & (0x80 >> direction)) != 0 {
+                word |= (io.read_u8(next) as u32) << bits;
+                bits += data_size;
+                node_addr = tree;
+
+                if bits == 32 {
+                    output.extend_from_slice(&word.to_le_bytes());
+                    word = 0;
+                    bits = 0;
+
+                    if output.len() >= size {
+                        break;
+                    }
+                }
+            } else {
+                node_addr = next;
+            }
+        }
+    }
+
+    output.truncate(size);
+    output
+}
+
+fn rl_uncomp(io: &Interconnect, source: u32) -> Vec<u8> {
+    let mut source = source as usize;
+    let size = (io.read_u32(source) >> 8) as usize;
+    let mut output = Vec::with_capacity(size);
+
+    source += 4;
+
+    while output.len() < size {
+        let flag = io.read_u8(source) as usize;
+        source += 1;
+
+        if (flag & 0x80) != 0 {
+            let byte = io.read_u8(source);
+            source += 1;
+
+            for _ in 0..((flag & 0x7F) + 3) {
+                output.push(byte);
+            }
+        } else {
+            for _ in 0..((flag & 0x7F) + 1) {
+                output.push(io.read_u8(source));
+                source += 1;
+            }
+        }
+    }
+
+    output.truncate(size);
+    output
+}
+
+// Written by halfwords, which works for both WRAM and VRAM
+fn write_buffer(io: &mut Interconnect, dest: u32, data: &[u8]) {
+    for (i, chunk) in data.chunks(2).enumerate() {
+        let value = (chunk[0] as u16) | ((*chunk.get(1).unwrap_or(&0) as u16) << 8);
+
+        io.write_u16(dest as usize + (i << 1), value);
+    }
+}
+
+fn midi_key_to_freq(io: &Interconnect, wave: u32, key: u32, fine: u32) -> u32 {
+    let freq = io.read_u32(wave as usize + 4) as f64;
+    let exponent = (180.0 - (key as f64) - ((fine & 0xFF) as f64) / 256.0) / 12.0;
+
+    (freq / 2f64.powf(exponent)) as u32
+}
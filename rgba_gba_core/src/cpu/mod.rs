@@ -8,9 +8,11 @@
 // 
 use std::fmt;
 use crate::io::Interconnect;
+use rgba_common::{savestate, Savestate, StateWriter, StateReader};
 
 mod arm;
 mod thumb;
+mod hle;
 
 #[derive(Debug, Default)]
 pub struct ARM7TDMI {
@@ -33,23 +35,28 @@ pub struct ARM7TDMI {
 
     // Lines
     irq_line: bool,
+
+    // Interrupts waited on by the HLE IntrWait
+    intr_wait: u16,
 }
 
+savestate!(ARM7TDMI,
+           registers, spsr, pc, sign, zero, carry, overflow,
+           irq, fiq, state, mode, irq_line, intr_wait);
+
 impl ARM7TDMI {
     pub fn new() -> ARM7TDMI {
         Default::default()
     }
 
+    /// Hardware reset, jumping to the BIOS
     pub fn reset(&mut self, io: &mut Interconnect) {
-        self.mode = CpuMode::System;
-        self.state = CpuState::ARM;
-
-        self.registers[15] = 0x08000000;
-        self.registers[18] = 0x03007f00; // SVC r13
+        *self = ARM7TDMI::new();
 
-        self.irq = false;
-        self.fiq = false;
+        self.irq = true;
+        self.fiq = true;
 
+        self.registers[15] = 0x00000000;
         self.branch(io);
     }
 
@@ -71,7 +78,11 @@ impl ARM7TDMI {
         }
     }
 
-    pub fn raise_swi(&mut self) {
+    pub fn raise_swi(&mut self, io: &mut Interconnect, comment: u32) {
+        if !io.has_bios() {
+            return self.hle_swi(io, comment);
+        }
+
         let old_cpsr = self.cpsr();
         let old_pc = self.pc;
 
@@ -299,9 +310,21 @@ impl Default for CpuState {
     fn default() -> CpuState { CpuState::ARM }
 }
 
+impl Savestate for CpuState {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(*self as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        *self = if state.read_u8()? != 0 { CpuState::Thumb } else { CpuState::ARM };
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 #[allow(dead_code)]
-enum CpuMode {
+pub(crate) enum CpuMode {
     User = 0x10,
     System = 0x1F,
     FIQ = 0x11,
@@ -315,7 +338,33 @@ impl Default for CpuMode {
     fn default() -> CpuMode { CpuMode::SVC }
 }
 
+impl Savestate for CpuMode {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(*self as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        *self = match state.read_u8()? {
+            0x10 => CpuMode::User,
+            0x1f => CpuMode::System,
+            0x11 => CpuMode::FIQ,
+            0x12 => CpuMode::IRQ,
+            0x13 => CpuMode::SVC,
+            0x17 => CpuMode::ABT,
+            0x1B => CpuMode::UND,
+            _ => return Err("Invalid CPU mode in savestate"),
+        };
+
+        Ok(())
+    }
+}
+
 impl CpuMode {
+    /// Whether the mode bits of a CPSR value name an existing mode
+    pub fn is_valid(value: u32) -> bool {
+        matches!(value & 0x1f, 0x10 | 0x1f | 0x11 | 0x12 | 0x13 | 0x17 | 0x1B)
+    }
+
     pub fn from_u32(value: u32) -> CpuMode {
         match value {
             0x10 => CpuMode::User,
@@ -336,7 +336,7 @@ def write_f16(g, high):
     g.write("}")
 
 def write_f17(g, high):
-    g.write("_cpu.raise_swi();")
+    g.write("_cpu.raise_swi(_io, (instr & 0xFF) as u32);")
     
 def write_f18(g, high):
     g.write("let offset = (((instr << 5) as i16) >> 4) as i32;")
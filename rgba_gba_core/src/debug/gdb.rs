@@ -0,0 +1,480 @@
+// gdb.rs ---
+//
+// Filename: gdb.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 20:02:47 2026 (+0200)
+// Last-Updated: Sun Oct 18 23:48:02 2026 (+0200)
+//           By: Louise <louise>
+//
+// GDB remote serial protocol stub, listening on a local TCP port.
+//
+use crate::GBA;
+use crate::cpu::CpuMode;
+use crate::io::WatchKind;
+
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const TARGET_XML: &str = concat!(
+    "<?xml version=\"1.0\"?>",
+    "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">",
+    "<target version=\"1.0\">",
+    "<architecture>armv4t</architecture>",
+    "<feature name=\"org.gnu.gdb.arm.core\">",
+    "<reg name=\"r0\" bitsize=\"32\" regnum=\"0\"/>",
+    "<reg name=\"r1\" bitsize=\"32\"/>",
+    "<reg name=\"r2\" bitsize=\"32\"/>",
+    "<reg name=\"r3\" bitsize=\"32\"/>",
+    "<reg name=\"r4\" bitsize=\"32\"/>",
+    "<reg name=\"r5\" bitsize=\"32\"/>",
+    "<reg name=\"r6\" bitsize=\"32\"/>",
+    "<reg name=\"r7\" bitsize=\"32\"/>",
+    "<reg name=\"r8\" bitsize=\"32\"/>",
+    "<reg name=\"r9\" bitsize=\"32\"/>",
+    "<reg name=\"r10\" bitsize=\"32\"/>",
+    "<reg name=\"r11\" bitsize=\"32\"/>",
+    "<reg name=\"r12\" bitsize=\"32\"/>",
+    "<reg name=\"sp\" bitsize=\"32\" type=\"data_ptr\"/>",
+    "<reg name=\"lr\" bitsize=\"32\"/>",
+    "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\"/>",
+    "<reg name=\"cpsr\" bitsize=\"32\"/>",
+    "</feature>",
+    "<feature name=\"org.rgba.arm.banked\">",
+    "<reg name=\"r13_usr\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"r14_usr\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"r13_irq\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"r14_irq\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"r13_svc\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"r14_svc\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"r13_und\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"r14_und\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"r13_abt\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"r14_abt\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"spsr_irq\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"spsr_svc\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"spsr_und\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"spsr_abt\" bitsize=\"32\" group=\"banked\"/>",
+    "<reg name=\"spsr_fiq\" bitsize=\"32\" group=\"banked\"/>",
+    "</feature>",
+    "</target>",
+);
+
+// r0-r15, cpsr, then the banked registers
+const REGISTER_COUNT: usize = 32;
+
+// Largest packet we accept and send, as advertised to GDB
+const PACKET_SIZE: usize = 0x1000;
+
+// Index of the banked registers in `ARM7TDMI::registers`, in the
+// order of the target description
+const BANKED_REGISTERS: [usize; 10] = [13, 14, 16, 17, 18, 19, 20, 21, 22, 23];
+
+/// Why the stub is handing control back to GDB
+enum StopReason {
+    Signal,
+    Breakpoint,
+    Watchpoint(u32, WatchKind),
+}
+
+pub struct GdbStub {
+    stream: TcpStream,
+
+    breakpoints: HashSet<u32>,
+    stepping: bool,
+    interrupted: bool,
+    resuming: bool,
+}
+
+impl GdbStub {
+    /// Waits for GDB to connect on the given local port. The
+    /// emulation is stopped until GDB resumes it.
+    pub fn listen(port: u16) -> Result<GdbStub, &'static str> {
+        let listener = match TcpListener::bind(("127.0.0.1", port)) {
+            Ok(l) => l,
+            Err(e) => {
+                error!("Couldn't listen for GDB : {}", e);
+                return Err("Couldn't listen for GDB");
+            }
+        };
+
+        info!("Waiting for GDB on port {}", port);
+
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                info!("GDB connected from {}", addr);
+
+                let _ = stream.set_nodelay(true);
+
+                Ok(GdbStub {
+                    stream,
+
+                    breakpoints: HashSet::new(),
+                    stepping: false,
+                    interrupted: true,
+                    resuming: false,
+                })
+            }
+
+            Err(e) => {
+                error!("Couldn't accept GDB connection : {}", e);
+                Err("Couldn't accept GDB connection")
+            }
+        }
+    }
+
+    /// Checks, without blocking, if GDB asked to interrupt the
+    /// emulation.
+    pub fn poll_interrupt(&mut self) {
+        let mut byte = [0; 1];
+
+        if self.stream.set_nonblocking(true).is_err() {
+            return;
+        }
+
+        while let Ok(1) = self.stream.read(&mut byte) {
+            if byte[0] == 0x03 {
+                self.interrupted = true;
+            }
+        }
+
+        let _ = self.stream.set_nonblocking(false);
+    }
+
+    /// Called before each instruction. Returns false once GDB
+    /// has detached.
+    pub fn handle(&mut self, gba: &mut GBA) -> bool {
+        let pc = gba.cpu.pc;
+        let skip_breakpoint = std::mem::replace(&mut self.resuming, false);
+
+        let reason = if let Some((address, kind)) = gba.io.watchpoint_hit() {
+            StopReason::Watchpoint(address, kind)
+        } else if !skip_breakpoint && self.breakpoints.contains(&pc) {
+            StopReason::Breakpoint
+        } else if self.stepping || self.interrupted {
+            StopReason::Signal
+        } else {
+            return true;
+        };
+
+        self.stepping = false;
+        self.interrupted = false;
+
+        self.serve(gba, reason)
+    }
+
+    fn serve(&mut self, gba: &mut GBA, reason: StopReason) -> bool {
+        let reply = match reason {
+            StopReason::Signal => "S05".to_string(),
+            StopReason::Breakpoint => "T05swbreak:;".to_string(),
+            StopReason::Watchpoint(address, kind) => format!(
+                "T05{}:{:08x};",
+                match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                },
+                address
+            ),
+        };
+
+        if !self.send(&reply) {
+            return false;
+        }
+
+        while let Some(packet) = self.receive() {
+            let (command, args) = (packet[0], &packet[1..]);
+
+            let reply = match command {
+                b'?' => "S05".to_string(),
+                b'g' => (0..REGISTER_COUNT).map(|n| hex_u32(read_register(gba, n))).collect(),
+                b'G' => {
+                    let mut ok = true;
+
+                    for (n, value) in args.chunks_exact(8).take(REGISTER_COUNT).enumerate() {
+                        if let Some(value) = parse_hex_u32_le(value) {
+                            ok &= write_register(gba, n, value);
+                        }
+                    }
+
+                    if ok { "OK" } else { "E01" }.to_string()
+                }
+                b'p' => match parse_hex(args) {
+                    Some(n) if (n as usize) < REGISTER_COUNT => hex_u32(read_register(gba, n as usize)),
+                    _ => "E01".to_string(),
+                },
+                b'P' => match split_once(args, b'=') {
+                    Some((n, value)) => {
+                        match (parse_hex(n), parse_hex_u32_le(value)) {
+                            (Some(n), Some(value)) if (n as usize) < REGISTER_COUNT => {
+                                if write_register(gba, n as usize, value) { "OK" } else { "E01" }.to_string()
+                            }
+                            _ => "E01".to_string(),
+                        }
+                    }
+                    None => "E01".to_string(),
+                },
+                // Longer reads are cut to what fits in a packet, GDB
+                // asks for the rest afterwards
+                b'm' => match parse_address_length(args) {
+                    Some((address, len)) => (0..len.min((PACKET_SIZE / 2) as u32))
+                        .map(|i| format!("{:02x}", read_byte(gba, address.wrapping_add(i))))
+                        .collect(),
+                    None => "E01".to_string(),
+                },
+                b'M' => match split_once(args, b':') {
+                    Some((range, data)) => match parse_address_length(range) {
+                        Some((address, len)) if data.len() >= (len as usize) * 2 => {
+                            for (i, digits) in data.chunks_exact(2).take(len as usize).enumerate() {
+                                if let Some(v) = parse_hex(digits) {
+                                    write_byte(gba, address.wrapping_add(i as u32), v as u8);
+                                }
+                            }
+
+                            "OK".to_string()
+                        }
+                        _ => "E01".to_string(),
+                    },
+                    None => "E01".to_string(),
+                },
+                b'c' | b's' => {
+                    if let Some(address) = parse_hex(args) {
+                        gba.cpu.registers[15] = address;
+                        gba.cpu.branch(&mut gba.io);
+                    }
+
+                    self.stepping = command == b's';
+                    self.resuming = true;
+                    gba.io.ack_watchpoint();
+
+                    return true;
+                }
+                b'Z' | b'z' => self.toggle_point(gba, command == b'Z', args),
+                b'H' => "OK".to_string(),
+                b'T' => "OK".to_string(),
+                b'D' => {
+                    self.send("OK");
+                    self.detach(gba);
+
+                    return false;
+                }
+                b'k' => {
+                    self.detach(gba);
+
+                    return false;
+                }
+                b'q' => self.query(args),
+                _ => String::new(),
+            };
+
+            if !self.send(&reply) {
+                break;
+            }
+        }
+
+        self.detach(gba);
+        false
+    }
+
+    fn detach(&mut self, gba: &mut GBA) {
+        info!("GDB detached");
+
+        self.breakpoints.clear();
+        gba.io.ack_watchpoint();
+    }
+
+    fn query(&self, args: &[u8]) -> String {
+        if args.starts_with(b"Supported") {
+            format!("PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+", PACKET_SIZE)
+        } else if args == b"Attached" {
+            "1".to_string()
+        } else if args == b"C" {
+            "QC1".to_string()
+        } else if args == b"fThreadInfo" {
+            "m1".to_string()
+        } else if args == b"sThreadInfo" {
+            "l".to_string()
+        } else if let Some(range) = args.strip_prefix(b"Xfer:features:read:target.xml:") {
+            match parse_address_length(range) {
+                Some((offset, len)) => {
+                    let offset = (offset as usize).min(TARGET_XML.len());
+                    let end = offset.saturating_add(len as usize).min(TARGET_XML.len());
+
+                    format!("{}{}",
+                            if end == TARGET_XML.len() { "l" } else { "m" },
+                            &TARGET_XML[offset..end])
+                }
+                None => "E01".to_string(),
+            }
+        } else {
+            String::new()
+        }
+    }
+
+    // Software and hardware breakpoints are the same thing here, we
+    // don't patch the code.
+    fn toggle_point(&mut self, gba: &mut GBA, insert: bool, args: &[u8]) -> String {
+        let mut parts = args.split(|&b| b == b',');
+
+        let (kind, address, len) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(kind), Some(address), Some(len)) => {
+                match (parse_hex(address), parse_hex(len)) {
+                    (Some(address), Some(len)) => (kind, address, len),
+                    _ => return "E01".to_string(),
+                }
+            }
+            _ => return "E01".to_string(),
+        };
+
+        let watch = match kind {
+            b"0" | b"1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+
+                return "OK".to_string();
+            }
+            b"2" => WatchKind::Write,
+            b"3" => WatchKind::Read,
+            b"4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+
+        if insert {
+            gba.io.set_watchpoint(address, len, watch);
+        } else {
+            gba.io.rem_watchpoint(address, len, watch);
+        }
+
+        "OK".to_string()
+    }
+
+    // Packets
+    fn send(&mut self, data: &str) -> bool {
+        let checksum = data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, checksum);
+
+        debug!("GDB <- {}", data);
+
+        self.stream.write_all(packet.as_bytes()).is_ok()
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let mut byte = [0; 1];
+
+        loop {
+            // Wait for the start of a packet, skipping the acks
+            loop {
+                self.stream.read_exact(&mut byte).ok()?;
+
+                match byte[0] {
+                    b'$' => break,
+                    0x03 => self.interrupted = true,
+                    _ => (),
+                }
+            }
+
+            let mut data = Vec::new();
+
+            loop {
+                self.stream.read_exact(&mut byte).ok()?;
+
+                if byte[0] == b'#' {
+                    break;
+                }
+
+                data.push(byte[0]);
+            }
+
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).ok()?;
+
+            let expected = parse_hex(&checksum);
+            let actual = data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+
+            if expected == Some(u32::from(actual)) {
+                self.stream.write_all(b"+").ok()?;
+
+                debug!("GDB -> {}", String::from_utf8_lossy(&data));
+
+                if !data.is_empty() {
+                    return Some(data);
+                }
+            } else {
+                warn!("Bad checksum on GDB packet");
+                self.stream.write_all(b"-").ok()?;
+            }
+        }
+    }
+}
+
+fn read_register(gba: &GBA, n: usize) -> u32 {
+    match n {
+        0..=14 => gba.cpu.get_register(n),
+        15 => gba.cpu.pc,
+        16 => gba.cpu.cpsr(),
+        17..=26 => gba.cpu.registers[BANKED_REGISTERS[n - 17]],
+        27..=31 => gba.cpu.spsr[n - 27],
+        _ => 0,
+    }
+}
+
+// Returns false if the value can't go in the register
+fn write_register(gba: &mut GBA, n: usize, value: u32) -> bool {
+    match n {
+        0..=14 => gba.cpu.set_register(n, value),
+        15 => {
+            gba.cpu.registers[15] = value;
+            gba.cpu.branch(&mut gba.io);
+        }
+        16 if !CpuMode::is_valid(value) => return false,
+        16 => gba.cpu.set_cpsr(value),
+        17..=26 => gba.cpu.registers[BANKED_REGISTERS[n - 17]] = value,
+        27..=31 => gba.cpu.spsr[n - 27] = value,
+        _ => (),
+    }
+
+    true
+}
+
+// Memory is accessed by halfwords, which every region supports
+fn read_byte(gba: &GBA, address: u32) -> u8 {
+    let halfword = gba.io.peek_u16((address & !1) as usize);
+
+    (halfword >> ((address & 1) << 3)) as u8
+}
+
+fn write_byte(gba: &mut GBA, address: u32, value: u8) {
+    let aligned = (address & !1) as usize;
+    let shift = (address & 1) << 3;
+    let halfword = gba.io.peek_u16(aligned);
+
+    gba.io.poke_u16(aligned, (halfword & !(0xFF << shift)) | ((value as u16) << shift));
+}
+
+fn hex_u32(value: u32) -> String {
+    value.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Packets are raw bytes, which may not be valid UTF-8
+fn parse_hex(s: &[u8]) -> Option<u32> {
+    u32::from_str_radix(std::str::from_utf8(s).ok()?, 16).ok()
+}
+
+fn parse_hex_u32_le(s: &[u8]) -> Option<u32> {
+    parse_hex(s).map(u32::swap_bytes).filter(|_| s.len() == 8)
+}
+
+fn split_once(s: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let position = s.iter().position(|&b| b == separator)?;
+
+    Some((&s[..position], &s[position + 1..]))
+}
+
+fn parse_address_length(s: &[u8]) -> Option<(u32, u32)> {
+    let (address, len) = split_once(s, b',')?;
+
+    Some((parse_hex(address)?, parse_hex(len)?))
+}
@@ -7,10 +7,12 @@
 //           By: Louise <ludwigette>
 //
 mod disasm;
+mod gdb;
 
-use crate::GBA;
-use crate::cpu::CpuState;
+use crate::cpu::{ARM7TDMI, CpuState};
+use crate::io::Interconnect;
 use crate::debug::disasm::{disasm_arm, disasm_thumb};
+pub use crate::debug::gdb::GdbStub;
 use rgba_common::Platform;
 
 use std::collections::VecDeque;
@@ -31,16 +33,16 @@ impl Debugger {
 
     pub fn trigger(&mut self) { self.steps = 1; }
     
-    pub fn handle<T: Platform>(&mut self, gba: &mut GBA, platform: &mut T) {
-        let pc = gba.cpu.pc;
+    pub fn handle<T: Platform>(&mut self, cpu: &ARM7TDMI, io: &Interconnect, platform: &mut T) {
+        let pc = cpu.pc;
         
         if self.should_break(pc) || self.enough_steps() {
-            println!("{}", gba.cpu);
+            println!("{}", cpu);
             println!("{:08x}: {}",
                      pc,
-                     match gba.cpu.state {
-                         CpuState::ARM => disasm_arm(&gba.io, pc),
-                         CpuState::Thumb => disasm_thumb(&gba.io, pc),
+                     match cpu.state {
+                         CpuState::ARM => disasm_arm(io, pc),
+                         CpuState::Thumb => disasm_thumb(io, pc),
                      }
             );
             
@@ -83,7 +85,7 @@ impl Debugger {
                                 print!("{:08x}:", address);
 
                                 for i in 0..16 {
-                                    print!(" {:02x}", gba.io.read_u8((address as usize) + i));
+                                    print!(" {:02x}", io.read_u8((address as usize) + i));
                                 }
 
                                 println!("");
@@ -99,7 +101,7 @@ impl Debugger {
                                 print!("{:08x}:", aligned);
 
                                 for i in 0..8 {
-                                    print!(" {:04x}", gba.io.read_u16(aligned + (i << 1)));
+                                    print!(" {:04x}", io.read_u16(aligned + (i << 1)));
                                 }
 
                                 println!("");
@@ -115,7 +117,7 @@ impl Debugger {
                                 print!("{:08x}:", aligned);
 
                                 for i in 0..4 {
-                                    print!(" {:08x}", gba.io.read_u32(aligned + (i << 2)));
+                                    print!(" {:08x}", io.read_u32(aligned + (i << 2)));
                                 }
 
                                 println!("");
@@ -131,12 +133,12 @@ impl Debugger {
                             pc
                         };
 
-                        match gba.cpu.state {
+                        match cpu.state {
                             CpuState::ARM => {
-                                println!("{:08x}: {}", addr, disasm_arm(&gba.io, addr));
+                                println!("{:08x}: {}", addr, disasm_arm(io, addr));
                             },
                             CpuState::Thumb => {
-                                println!("{:08x}: {}", addr, disasm_thumb(&gba.io, addr));
+                                println!("{:08x}: {}", addr, disasm_thumb(io, addr));
                             }
                         }
                     }
@@ -148,7 +150,7 @@ impl Debugger {
                             pc
                         } & 0xFFFFFFFC;
                         
-                        println!("{:08x}: {}", addr, disasm_arm(&gba.io, addr));
+                        println!("{:08x}: {}", addr, disasm_arm(io, addr));
                     }
 
                     Some("d/t") => {
@@ -158,7 +160,7 @@ impl Debugger {
                             pc
                         } & 0xFFFFFFFE;
                         
-                        println!("{:08x}: {}", addr, disasm_thumb(&gba.io, addr));
+                        println!("{:08x}: {}", addr, disasm_thumb(io, addr));
                     }
                     
                     Some("h") | Some("help") => {
@@ -22,6 +22,17 @@ mod mode3;
 mod mode4;
 
 use crate::irq::{IrqManager, IRQ_VBLANK, IRQ_HBLANK, IRQ_VCOUNT};
+use rgba_common::{savestate, Savestate, StateWriter, StateReader};
+
+// Cycles spent drawing a line, and in a whole line
+pub const HDRAW_CYCLES: u64 = 960;
+pub const LINE_CYCLES: u64 = 1232;
+
+// DMA triggers returned by hblank and end_line
+pub const DMA_VBLANK: u8 = 1;
+pub const DMA_HBLANK: u8 = 2;
+pub const DMA_VIDEO: u8 = 4;
+pub const DMA_VIDEO_END: u8 = 8;
 
 pub struct GPU {
     // Result
@@ -36,8 +47,6 @@ pub struct GPU {
     render_line: Option<u16>,
     is_frame: bool,
     vcount: u16,
-    clock: u32,
-    dots: u32,
     mode: GpuMode,
 
     dispcnt: u16,
@@ -62,6 +71,12 @@ pub struct GPU {
     bldy: u16,
 }
 
+savestate!(GPU,
+           framebuffer, pram, vram, oam,
+           render_line, is_frame, vcount, mode, dispcnt,
+           irq_vblank_en, irq_hblank_en, irq_vcount_en, vcount_match,
+           bg, win, winin, winout, bldcnt, bldalpha, bldy);
+
 impl GPU {
     pub fn new() -> GPU {
         GPU {
@@ -74,8 +89,6 @@ impl GPU {
             render_line: None,
             is_frame: false,
             vcount: 0,
-            clock: 0,
-            dots: 0,
             mode: GpuMode::Visible,
 
             // DISPSTAT
@@ -110,54 +123,53 @@ impl GPU {
     pub fn is_frame(&self) -> bool { self.is_frame }
     pub fn ack_frame(&mut self) { self.is_frame = false; }
 
-    pub fn spend_cycles(&mut self, nb_cycles: u32, irq: &mut IrqManager) {
-        let total_cycles = self.clock + nb_cycles;
-
-        let dots = total_cycles >> 2;
-        let new_clock = total_cycles & 3;
-        
-        self.dots += dots;
-        self.clock = new_clock;
-
+    /// Start of the HBlank period, returns the DMA triggers
+    pub fn hblank(&mut self, irq: &mut IrqManager) -> u8 {
         match self.mode {
             GpuMode::Visible => {
-                if self.dots >= 240 {
-                    if self.irq_hblank_en {
-                        irq.raise_irq(IRQ_HBLANK);
-                    }
-                    
-                    self.mode = GpuMode::HBlank;
+                if self.irq_hblank_en {
+                    irq.raise_irq(IRQ_HBLANK);
                 }
+
+                self.mode = GpuMode::HBlank;
+
+                // Video capture runs from line 2 to line 161
+                if self.vcount >= 2 { DMA_HBLANK | DMA_VIDEO } else { DMA_HBLANK }
             }
-            GpuMode::HBlank => {
-                while self.dots >= 308 {
-                    self.dots -= 308;
-                    self.render_line = Some(self.vcount);
-                    self.increment_lines(irq);
-
-                    if self.vcount == 160 {
-                        if self.irq_vblank_en {
-                            irq.raise_irq(IRQ_VBLANK);
-                        }
-                        
-                        self.mode = GpuMode::VBlank;
-                    } else {
-                        self.mode = GpuMode::Visible;
-                    }
+            GpuMode::VBlank if self.vcount < 162 => DMA_VIDEO,
+            _ => 0,
+        }
+    }
+
+    /// End of a line, returns the DMA triggers
+    pub fn end_line(&mut self, irq: &mut IrqManager) -> u8 {
+        let mut triggers = 0;
+
+        if self.mode == GpuMode::HBlank {
+            self.render_line = Some(self.vcount);
+        }
+
+        self.increment_lines(irq);
+
+        match self.vcount {
+            160 => {
+                if self.irq_vblank_en {
+                    irq.raise_irq(IRQ_VBLANK);
                 }
+
+                triggers |= DMA_VBLANK;
+                self.mode = GpuMode::VBlank;
             }
-            GpuMode::VBlank => {
-                while self.dots >= 308 {
-                    self.dots -= 308;
-                    self.increment_lines(irq);
-
-                    if self.vcount == 0 {
-                        self.is_frame = true;
-                        self.mode = GpuMode::Visible;
-                    }
-                }
+            162 => triggers |= DMA_VIDEO_END,
+            0 => {
+                self.is_frame = true;
+                self.mode = GpuMode::Visible;
             }
+            1..=159 => self.mode = GpuMode::Visible,
+            _ => { }
         }
+
+        triggers
     }
 }
 
@@ -168,6 +180,27 @@ pub enum GpuMode {
     VBlank,
 }
 
+impl Savestate for GpuMode {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(match *self {
+            GpuMode::Visible => 0,
+            GpuMode::HBlank => 1,
+            GpuMode::VBlank => 2,
+        });
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        *self = match state.read_u8()? {
+            0 => GpuMode::Visible,
+            1 => GpuMode::HBlank,
+            2 => GpuMode::VBlank,
+            _ => return Err("Invalid GPU mode in savestate"),
+        };
+
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Default)]
 pub struct Background {
     pub cnt: u16,
@@ -182,12 +215,16 @@ pub struct Background {
     pub par_d: u16,
 }
 
+savestate!(Background, cnt, h_off, v_off, x_ref, y_ref, par_a, par_b, par_c, par_d);
+
 #[derive(Default)]
 struct Window {
     pub h_off: u16,
     pub v_off: u16,
 }
 
+savestate!(Window, h_off, v_off);
+
 pub struct DisplayLine {
     pub bg: [[u16; 240]; 4],
     pub bg_enabled: [bool; 4],
@@ -195,3 +232,8 @@ pub struct DisplayLine {
     pub obj: [u16; 240],
     pub obj_data: [u8; 240],
 }
+
+// Flags in DisplayLine::obj_data, the low two bits
+// being the priority of the sprite
+pub const OBJ_SEMI_TRANSPARENT: u8 = 0x04;
+pub const OBJ_WINDOW: u8 = 0x08;
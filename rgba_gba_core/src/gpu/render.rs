@@ -9,7 +9,18 @@
 use byteorder::{ByteOrder, LittleEndian};
 
 use crate::gpu::GPU;
-use crate::gpu::DisplayLine;
+use crate::gpu::{DisplayLine, OBJ_SEMI_TRANSPARENT, OBJ_WINDOW};
+
+// Sprite sizes, by shape then size
+const OBJ_SIZE: [[(i32, i32); 4]; 3] = [
+	[(8, 8),  (16, 16), (32, 32), (64, 64)], // Square
+	[(16, 8), (32, 8),  (32, 16), (64, 32)], // Horizontal
+	[(8, 16), (8, 32),  (16, 32), (32, 64)], // Vertical
+];
+
+// Layers, as found in BLDCNT
+const LAYER_OBJ: usize = 4;
+const LAYER_BD: usize = 5;
 
 impl GPU {
 	#[inline]
@@ -33,6 +44,10 @@ impl GPU {
 				mode => debug!("Rendering unimplemented mode {}", mode),
 			}
 
+			if (self.dispcnt & 0x1000) != 0 {
+				self.render_obj(line, &mut display_line);
+			}
+
 			let result = self.blend_line(line, &mut display_line);
 			
 			for x in 0..240 {
@@ -41,18 +56,168 @@ impl GPU {
 		}
 	}
 
+	pub fn render_obj(&self, line: u16, display_line: &mut DisplayLine) {
+		let mapping_1d = (self.dispcnt & 0x40) != 0;
+		let obj_window = (self.dispcnt & 0x8000) != 0;
+
+		// In bitmap modes, the first half of OBJ VRAM is used by the BG
+		let tile_min = if (self.dispcnt & 7) >= 3 { 512 } else { 0 };
+
+		// Cycles available for sprite rendering on this line,
+		// less if OAM access is allowed during H-Blank
+		let mut cycles: i32 = if (self.dispcnt & 0x20) != 0 { 954 } else { 1210 };
+
+		for obj in 0..128 {
+			let attr0 = LittleEndian::read_u16(&self.oam[(obj << 3)..]);
+			let attr1 = LittleEndian::read_u16(&self.oam[(obj << 3) + 2..]);
+			let attr2 = LittleEndian::read_u16(&self.oam[(obj << 3) + 4..]);
+
+			let affine = (attr0 & 0x100) != 0;
+			let double_size = affine && (attr0 & 0x200) != 0;
+			let mode = (attr0 >> 10) & 3;
+			let shape = (attr0 >> 14) as usize;
+
+			// Disabled sprite, or prohibited values
+			if (!affine && (attr0 & 0x200) != 0) || mode == 3 || shape == 3 {
+				continue;
+			}
+
+			let (width, height) = OBJ_SIZE[shape][(attr1 >> 14) as usize];
+			let (box_width, box_height) = if double_size {
+				(width * 2, height * 2)
+			} else {
+				(width, height)
+			};
+
+			// Line within the sprite, Y wraps around at 256
+			let sprite_y = ((line as i32) - ((attr0 & 0xff) as i32)) & 0xff;
+
+			if sprite_y >= box_height {
+				continue;
+			}
+
+			cycles -= if affine { 10 + box_width * 2 } else { box_width };
+
+			if cycles < 0 {
+				break;
+			}
+
+			if mode == 2 && !obj_window {
+				continue;
+			}
+
+			// X is a 9-bit signed value
+			let x = (((attr1 & 0x1ff) << 7) as i16 >> 7) as i32;
+
+			let bpp8 = (attr0 & 0x2000) != 0;
+			let tile_nb = (attr2 & 0x3ff) as i32;
+			let priority = ((attr2 >> 10) & 3) as u8;
+			let palette = 0x200 + if bpp8 { 0 } else { ((attr2 >> 12) as usize) << 5 };
+
+			let data = priority | match mode {
+				1 => OBJ_SEMI_TRANSPARENT,
+				2 => OBJ_WINDOW,
+				_ => 0,
+			};
+
+			// Tiles between two rows of the sprite
+			let row_stride = if mapping_1d {
+				(width >> 3) << (bpp8 as i32)
+			} else {
+				32
+			};
+
+			// Rotation/scaling parameters, in 8.8 fixed point
+			let (pa, pb, pc, pd) = if affine {
+				let params = (((attr1 >> 9) & 0x1f) as usize) << 5;
+
+				(
+					LittleEndian::read_i16(&self.oam[params + 0x06..]) as i32,
+					LittleEndian::read_i16(&self.oam[params + 0x0e..]) as i32,
+					LittleEndian::read_i16(&self.oam[params + 0x16..]) as i32,
+					LittleEndian::read_i16(&self.oam[params + 0x1e..]) as i32,
+				)
+			} else {
+				let h_flip = (attr1 & 0x1000) != 0;
+				let v_flip = (attr1 & 0x2000) != 0;
+
+				(if h_flip { -0x100 } else { 0x100 }, 0, 0, if v_flip { -0x100 } else { 0x100 })
+			};
+
+			let rel_y = sprite_y - box_height / 2;
+
+			for sprite_x in 0..box_width {
+				let px = x + sprite_x;
+
+				if !(0..240).contains(&px) {
+					continue;
+				}
+
+				let rel_x = sprite_x - box_width / 2;
+
+				// Texture coordinates, relative to the center of the sprite
+				let tx = ((pa * rel_x + pb * rel_y) >> 8) + width / 2;
+				let ty = ((pc * rel_x + pd * rel_y) >> 8) + height / 2;
+
+				// Flipped coordinates need an adjustment, the center
+				// being between two pixels
+				let (tx, ty) = if affine {
+					(tx, ty)
+				} else {
+					(if pa < 0 { tx - 1 } else { tx }, if pd < 0 { ty - 1 } else { ty })
+				};
+
+				if tx < 0 || tx >= width || ty < 0 || ty >= height {
+					continue;
+				}
+
+				let tile = tile_nb + (ty >> 3) * row_stride + ((tx >> 3) << (bpp8 as i32));
+
+				if (tile & 0x3ff) < tile_min {
+					continue;
+				}
+
+				// 8bpp tiles near the end wrap around the 32KB OBJ area
+				let tile_addr = ((tile & 0x3ff) as usize) << 5;
+				let index = if bpp8 {
+					let offset = tile_addr + (((ty & 7) << 3) | (tx & 7)) as usize;
+
+					self.vram[0x10000 + (offset & 0x7fff)]
+				} else {
+					let byte = self.vram[0x10000 + tile_addr + (((ty & 7) << 2) | ((tx & 7) >> 1)) as usize];
+
+					(byte >> ((tx & 1) << 2)) & 0xf
+				};
+
+				if index == 0 {
+					continue;
+				}
+
+				let px = px as usize;
+
+				if mode == 2 {
+					display_line.obj_data[px] |= OBJ_WINDOW;
+				} else if (display_line.obj[px] & 0x8000) == 0
+					|| priority < (display_line.obj_data[px] & 3) {
+						display_line.obj[px] = LittleEndian::read_u16(
+							&self.pram[palette + ((index as usize) << 1)..]
+						) | 0x8000;
+						display_line.obj_data[px] = data | (display_line.obj_data[px] & OBJ_WINDOW);
+					}
+			}
+		}
+	}
+
 	pub fn blend_line(&self, line: u16, display_line: &mut DisplayLine) -> [u16; 240] {
 		let mut output: [u16; 240] = [0; 240];
 		
-		let bg0_enabled = display_line.bg_enabled[0] && ((self.dispcnt & 0x100) != 0);
-		let bg1_enabled = display_line.bg_enabled[1] && ((self.dispcnt & 0x200) != 0);
-		let bg2_enabled = display_line.bg_enabled[2] && ((self.dispcnt & 0x400) != 0);
-		let bg3_enabled = display_line.bg_enabled[3] && ((self.dispcnt & 0x800) != 0);
-
-		let bg0_priority = self.bg[0].cnt & 0x3;
-		let bg1_priority = self.bg[1].cnt & 0x3;
-		let bg2_priority = self.bg[2].cnt & 0x3;
-		let bg3_priority = self.bg[3].cnt & 0x3;
+		let bg_enabled = [
+			display_line.bg_enabled[0] && ((self.dispcnt & 0x100) != 0),
+			display_line.bg_enabled[1] && ((self.dispcnt & 0x200) != 0),
+			display_line.bg_enabled[2] && ((self.dispcnt & 0x400) != 0),
+			display_line.bg_enabled[3] && ((self.dispcnt & 0x800) != 0),
+		];
+		let obj_enabled = (self.dispcnt & 0x1000) != 0;
 
 		let win0_x0 = ((self.win[0].h_off) >> 8) & 0xff;
 		let win0_x1 = if (self.win[0].h_off & 0xff) > 240 { 240 } else { self.win[0].h_off & 0xff };
@@ -66,51 +231,123 @@ impl GPU {
 
 		let win0_en = (self.dispcnt & 0x2000) == 0x2000;
 		let win1_en = (self.dispcnt & 0x4000) == 0x4000;
+		let objwin_en = (self.dispcnt & 0x8000) == 0x8000;
 
 		let win0_in = self.winin & 0x3f;
 		let win1_in = (self.winin >> 8) & 0x3f;
 		let winout = self.winout & 0x3f;
+		let objwin_in = (self.winout >> 8) & 0x3f;
+
+		// Color special effects
+		let first_target = self.bldcnt & 0x3f;
+		let effect = (self.bldcnt >> 6) & 3;
+		let second_target = (self.bldcnt >> 8) & 0x3f;
+
+		let eva = (self.bldalpha & 0x1f).min(16);
+		let evb = ((self.bldalpha >> 8) & 0x1f).min(16);
+		let evy = (self.bldy & 0x1f).min(16);
 
 		let backdrop = LittleEndian::read_u16(&self.pram);
 		
 		for px in 0..240 {
-			let win_bits = if win0_en || win1_en {
+			let obj_data = display_line.obj_data[px as usize];
+
+			let win_bits = if win0_en || win1_en || objwin_en {
 				if win0_en && (px >= win0_x0) && (px < win0_x1) && (line >= win0_y0) && (line < win0_y1) {
 					win0_in
 				} else if win1_en && (px >= win1_x0) && (px < win1_x1) && (line >= win1_y0) && (line < win1_y1) {
 					win1_in
+				} else if objwin_en && (obj_data & OBJ_WINDOW) != 0 {
+					objwin_in
 				} else {
 					winout
 				}
 			} else {
 				0x3f
 			};
-			
-			output[px as usize] = backdrop;
-			
-			for i in (0..4).rev() {
-				if bg3_enabled && (bg3_priority == i) && ((display_line.bg[3][px as usize] & 0x8000) != 0) && (win_bits & 8) == 8 {
-					output[px as usize] = display_line.bg[3][px as usize];
-				}
-				
-				if bg2_enabled && (bg2_priority == i) && ((display_line.bg[2][px as usize] & 0x8000) != 0) && (win_bits & 4) == 4 {
-					output[px as usize] = display_line.bg[2][px as usize];
+
+			// Find the two topmost layers, the sprites being
+			// drawn above backgrounds of the same priority
+			let obj_visible = obj_enabled
+				&& ((display_line.obj[px as usize] & 0x8000) != 0)
+				&& (win_bits & 0x10) != 0;
+			let mut layers = [(LAYER_BD, backdrop); 2];
+			let mut found = 0;
+
+			for i in 0..4 {
+				if obj_visible && (obj_data & 3) as u16 == i {
+					layers[found] = (LAYER_OBJ, display_line.obj[px as usize]);
+					found += 1;
 				}
-				
-				if bg1_enabled && (bg1_priority == i) && ((display_line.bg[1][px as usize] & 0x8000) != 0) && (win_bits & 2) == 2 {
-					output[px as usize] = display_line.bg[1][px as usize];
+
+				for (bg, &enabled) in bg_enabled.iter().enumerate() {
+					let color = display_line.bg[bg][px as usize];
+
+					if found < 2 && enabled && ((self.bg[bg].cnt & 0x3) == i)
+						&& ((color & 0x8000) != 0) && (win_bits & (1 << bg)) != 0 {
+							layers[found] = (bg, color);
+							found += 1;
+						}
 				}
-				
-				if bg0_enabled && (bg0_priority == i) && ((display_line.bg[0][px as usize] & 0x8000) != 0) && (win_bits & 1) == 1 {
-					output[px as usize] = display_line.bg[0][px as usize];
+
+				if found == 2 {
+					break;
 				}
 			}
+
+			let (top, below) = (layers[0], layers[1]);
+
+			let effects = (win_bits & 0x20) != 0;
+			let is_first = (first_target & (1 << top.0)) != 0;
+			let is_second = (second_target & (1 << below.0)) != 0;
+			let semi_transparent = top.0 == LAYER_OBJ && (obj_data & OBJ_SEMI_TRANSPARENT) != 0;
+
+			output[px as usize] = if effects && semi_transparent && is_second {
+				blend_alpha(top.1, below.1, eva, evb)
+			} else if effects && is_first {
+				match effect {
+					1 if is_second => blend_alpha(top.1, below.1, eva, evb),
+					2 => blend_brightness(top.1, 0x7fff, evy),
+					3 => blend_brightness(top.1, 0, evy),
+					_ => top.1,
+				}
+			} else {
+				top.1
+			};
 		};
 
 		output
 	}
 }
 
+// Alpha blending, with coefficients out of 16
+fn blend_alpha(a: u16, b: u16, eva: u16, evb: u16) -> u16 {
+	let mut result = 0;
+
+	for shift in [0, 5, 10].iter() {
+		let ca = (a >> shift) & 0x1f;
+		let cb = (b >> shift) & 0x1f;
+
+		result |= ((ca * eva + cb * evb) >> 4).min(0x1f) << shift;
+	}
+
+	result
+}
+
+// Fading to white or black
+fn blend_brightness(a: u16, target: u16, evy: u16) -> u16 {
+	let mut result = 0;
+
+	for shift in [0, 5, 10].iter() {
+		let ca = ((a >> shift) & 0x1f) as i32;
+		let ct = ((target >> shift) & 0x1f) as i32;
+
+		result |= ((ca + (((ct - ca) * evy as i32) >> 4)) as u16) << shift;
+	}
+
+	result
+}
+
 fn expand_color(source: u16) -> u32 {
 	u32::from_be_bytes (
 		[
@@ -0,0 +1,457 @@
+// backup.rs ---
+//
+// Filename: backup.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 17:12:45 2026 (+0200)
+// Last-Updated: Sun Oct 18 17:12:45 2026 (+0200)
+//           By: Louise <louise>
+//
+use rgba_common::{savestate, Savestate, StateWriter, StateReader};
+
+use std::cell::Cell;
+use std::fs;
+use std::io::Write;
+
+// Frames to wait after the last write to the backup before
+// writing the savefile, and maximum frames between two writes
+// if the game keeps writing
+const SAVE_IDLE_FRAMES: u32 = 60;
+const SAVE_MAX_FRAMES: u32 = 600;
+
+// Manufacturer and device IDs reported by the flash chips
+const FLASH64_ID: (u8, u8) = (0x32, 0x1B);  // Panasonic MN63F805MNP
+const FLASH128_ID: (u8, u8) = (0x62, 0x13); // Sanyo LE26FV10N1TS
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackupKind {
+    None,
+    Sram,
+    Flash64,
+    Flash128,
+    Eeprom,
+}
+
+impl BackupKind {
+    /// Looks for the ID string the SDK libraries leave in the ROM
+    pub fn detect(rom: &[u8]) -> BackupKind {
+        const IDS: [(&[u8], BackupKind); 6] = [
+            (b"EEPROM_V", BackupKind::Eeprom),
+            (b"SRAM_V", BackupKind::Sram),
+            (b"SRAM_F_V", BackupKind::Sram),
+            (b"FLASH_V", BackupKind::Flash64),
+            (b"FLASH512_V", BackupKind::Flash64),
+            (b"FLASH1M_V", BackupKind::Flash128),
+        ];
+
+        // The strings are always word-aligned
+        for offset in (0..rom.len()).step_by(4) {
+            for &(id, kind) in IDS.iter() {
+                if rom[offset..].starts_with(id) {
+                    return kind;
+                }
+            }
+        }
+
+        BackupKind::None
+    }
+
+    fn size(self) -> usize {
+        match self {
+            BackupKind::None => 0,
+            BackupKind::Sram => 0x8000,
+            BackupKind::Flash64 => 0x10000,
+            BackupKind::Flash128 => 0x20000,
+            BackupKind::Eeprom => 0x2000,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Flash {
+    // Position in the AA/55 unlock sequence
+    step: u8,
+
+    id_mode: bool,
+    erase: bool,
+    write_next: bool,
+    bank_next: bool,
+    bank: u32,
+}
+
+savestate!(Flash, step, id_mode, erase, write_next, bank_next, bank);
+
+#[derive(Default)]
+struct Eeprom {
+    // Width of the address, 6 bits for 512B and 14 bits for 8KB,
+    // 0 if we don't know yet
+    addr_bits: u32,
+
+    input: u128,
+    input_len: u32,
+
+    output: u64,
+    output_pos: Cell<u32>,
+}
+
+impl Savestate for Eeprom {
+    fn save(&self, state: &mut StateWriter) {
+        self.addr_bits.save(state);
+        state.write_u64(self.input as u64);
+        state.write_u64((self.input >> 64) as u64);
+        self.input_len.save(state);
+        self.output.save(state);
+        self.output_pos.get().save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        self.addr_bits.load(state)?;
+        self.input = (state.read_u64()? as u128) | ((state.read_u64()? as u128) << 64);
+        self.input_len.load(state)?;
+        self.output.load(state)?;
+        self.output_pos.set(state.read_u32()?);
+
+        Ok(())
+    }
+}
+
+/// GamePak backup media: SRAM, Flash or EEPROM, backed by a savefile.
+pub struct Backup {
+    kind: BackupKind,
+    data: Vec<u8>,
+    save_filename: Option<String>,
+
+    flash: Flash,
+    eeprom: Eeprom,
+
+    dirty: bool,
+    idle_frames: u32,
+    dirty_frames: u32,
+}
+
+impl Backup {
+    pub fn new(filename: &str, kind: BackupKind) -> Backup {
+        let mut data = vec![0xFF; kind.size()];
+        let mut eeprom = Eeprom {
+            output_pos: Cell::new(68),
+            ..Default::default()
+        };
+        let save_filename = format!("{}.sav", filename);
+
+        if kind != BackupKind::None {
+            info!("Backup type : {:?}", kind);
+
+            match fs::read(&save_filename) {
+                Ok(save) => {
+                    // EEPROM size is only known from the savefile
+                    // or from the first access
+                    if kind == BackupKind::Eeprom && save.len() == 0x200 {
+                        data.truncate(0x200);
+                        eeprom.addr_bits = 6;
+                    } else if kind == BackupKind::Eeprom && save.len() == 0x2000 {
+                        eeprom.addr_bits = 14;
+                    }
+
+                    let len = save.len().min(data.len());
+                    data[..len].copy_from_slice(&save[..len]);
+
+                    info!("Savefile loaded!");
+                }
+                Err(e) => info!("No savefile loaded : {}", e),
+            }
+        }
+
+        Backup {
+            kind,
+            data,
+            save_filename: if kind != BackupKind::None { Some(save_filename) } else { None },
+
+            flash: Flash::default(),
+            eeprom,
+
+            dirty: false,
+            idle_frames: 0,
+            dirty_frames: 0,
+        }
+    }
+
+    #[inline]
+    pub fn is_eeprom(&self) -> bool { self.kind == BackupKind::Eeprom }
+
+    #[inline]
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+        self.idle_frames = 0;
+    }
+
+    // SRAM and Flash, on the 8-bit bus at 0x0E000000
+    pub fn read_u8(&self, address: usize) -> u8 {
+        let address = address & 0xFFFF;
+
+        match self.kind {
+            BackupKind::Sram => self.data[address & 0x7FFF],
+            BackupKind::Flash64 | BackupKind::Flash128 => {
+                let id = if self.kind == BackupKind::Flash64 { FLASH64_ID } else { FLASH128_ID };
+
+                match address {
+                    0 if self.flash.id_mode => id.0,
+                    1 if self.flash.id_mode => id.1,
+                    _ => self.data[((self.flash.bank as usize) << 16) | address],
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_u8(&mut self, address: usize, value: u8) {
+        let address = address & 0xFFFF;
+
+        match self.kind {
+            BackupKind::Sram => {
+                self.data[address & 0x7FFF] = value;
+                self.mark_dirty();
+            }
+            BackupKind::Flash64 | BackupKind::Flash128 => self.flash_write(address, value),
+            _ => warn!("Write to backup at {:04x} but there is no SRAM/Flash", address),
+        }
+    }
+
+    /// Debugger write to SRAM or Flash, straight into the current bank
+    pub fn poke_u8(&mut self, address: usize, value: u8) {
+        let address = address & 0xFFFF;
+
+        match self.kind {
+            BackupKind::Sram => self.data[address & 0x7FFF] = value,
+            BackupKind::Flash64 | BackupKind::Flash128 =>
+                self.data[((self.flash.bank as usize) << 16) | address] = value,
+            _ => return,
+        }
+
+        self.mark_dirty();
+    }
+
+    fn flash_write(&mut self, address: usize, value: u8) {
+        if self.flash.write_next {
+            self.data[((self.flash.bank as usize) << 16) | address] = value;
+            self.flash.write_next = false;
+            self.mark_dirty();
+
+            return;
+        }
+
+        if self.flash.bank_next && address == 0 {
+            self.flash.bank = (value & 1) as u32;
+            self.flash.bank_next = false;
+
+            return;
+        }
+
+        match (self.flash.step, address, value) {
+            (0, 0x5555, 0xAA) => self.flash.step = 1,
+            (1, 0x2AAA, 0x55) => self.flash.step = 2,
+            (2, 0x5555, command) => {
+                self.flash.step = 0;
+
+                match command {
+                    0x90 => self.flash.id_mode = true,
+                    0xF0 => self.flash.id_mode = false,
+                    0x80 => self.flash.erase = true,
+                    0x10 if self.flash.erase => {
+                        for byte in self.data.iter_mut() { *byte = 0xFF; }
+                        self.mark_dirty();
+                    }
+                    0xA0 => self.flash.write_next = true,
+                    0xB0 if self.kind == BackupKind::Flash128 => self.flash.bank_next = true,
+                    _ => warn!("Unknown flash command {:02x}", command),
+                }
+
+                if command != 0x80 {
+                    self.flash.erase = false;
+                }
+            }
+            (2, sector, 0x30) if self.flash.erase && (sector & 0xFFF) == 0 => {
+                let start = ((self.flash.bank as usize) << 16) | sector;
+
+                for byte in self.data[start..(start + 0x1000)].iter_mut() { *byte = 0xFF; }
+
+                self.flash.step = 0;
+                self.flash.erase = false;
+                self.mark_dirty();
+            }
+            _ => self.flash.step = 0,
+        }
+    }
+
+    /// Called when DMA3 starts a transfer, the EEPROM size can be
+    /// deduced from the length of the first request.
+    pub fn eeprom_dma(&mut self, to_eeprom: bool, count: u16) {
+        if self.eeprom.addr_bits != 0 || !to_eeprom {
+            return;
+        }
+
+        self.eeprom.addr_bits = match count {
+            9 | 73 => 6,
+            17 | 81 => 14,
+            _ => return,
+        };
+
+        if self.eeprom.addr_bits == 6 {
+            self.data.truncate(0x200);
+        }
+
+        info!("EEPROM size : {} bytes", self.data.len());
+    }
+
+    // EEPROM, one bit per halfword
+    pub fn eeprom_read(&self) -> u16 {
+        let pos = self.eeprom.output_pos.get();
+
+        match pos {
+            // Four dummy bits, then 64 bits of data
+            0..=3 => { self.eeprom.output_pos.set(pos + 1); 0 }
+            4..=67 => {
+                self.eeprom.output_pos.set(pos + 1);
+                ((self.eeprom.output >> (67 - pos)) & 1) as u16
+            }
+            // Ready
+            _ => 1,
+        }
+    }
+
+    pub fn eeprom_write(&mut self, value: u16) {
+        let addr_bits = if self.eeprom.addr_bits == 0 { 6 } else { self.eeprom.addr_bits };
+
+        self.eeprom.input = (self.eeprom.input << 1) | ((value & 1) as u128);
+        self.eeprom.input_len += 1;
+
+        let len = self.eeprom.input_len;
+
+        if len < 2 {
+            return;
+        }
+
+        let command = (self.eeprom.input >> (len - 2)) & 3;
+
+        match command {
+            // Read request : 11, address, 0
+            0b11 if len == addr_bits + 3 => {
+                let block = self.eeprom_block((self.eeprom.input >> 1) as usize);
+
+                self.eeprom.output = u64::from_be_bytes(
+                    self.data[block..(block + 8)].try_into().unwrap()
+                );
+                self.eeprom.output_pos.set(0);
+                self.eeprom.input_len = 0;
+            }
+
+            // Write request : 10, address, 64 bits, 0
+            0b10 if len == addr_bits + 67 => {
+                let block = self.eeprom_block((self.eeprom.input >> 65) as usize);
+                let value = (self.eeprom.input >> 1) as u64;
+
+                self.data[block..(block + 8)].copy_from_slice(&value.to_be_bytes());
+                self.eeprom.output_pos.set(68);
+                self.eeprom.input_len = 0;
+                self.mark_dirty();
+            }
+
+            0b00 | 0b01 => {
+                warn!("Unknown EEPROM request");
+                self.eeprom.input_len = 0;
+            }
+
+            _ => { }
+        }
+    }
+
+    #[inline]
+    fn eeprom_block(&self, address: usize) -> usize {
+        (address << 3) & (self.data.len() - 1)
+    }
+
+    /// Called once per frame, writes the savefile once the game
+    /// has stopped writing to the backup for a while.
+    pub fn tick(&mut self) {
+        if self.dirty {
+            self.idle_frames += 1;
+            self.dirty_frames += 1;
+
+            if self.idle_frames >= SAVE_IDLE_FRAMES || self.dirty_frames >= SAVE_MAX_FRAMES {
+                self.flush();
+            }
+        }
+    }
+
+    /// Writes the savefile if the backup changed since the last write.
+    /// The data is written to a temporary file which is then renamed,
+    /// so the savefile is never left half-written.
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+
+        self.dirty = false;
+        self.idle_frames = 0;
+        self.dirty_frames = 0;
+
+        if let Some(ref save_filename) = self.save_filename {
+            info!("Writing savefile!");
+
+            let tmp_filename = format!("{}.tmp", save_filename);
+            let result = fs::File::create(&tmp_filename)
+                .and_then(|mut file| {
+                    file.write_all(&self.data)?;
+                    file.sync_all()
+                })
+                .and_then(|_| fs::rename(&tmp_filename, save_filename));
+
+            if let Err(e) = result {
+                warn!("Couldn't save to savefile : {}", e);
+            }
+        }
+    }
+}
+
+impl Default for Backup {
+    fn default() -> Backup {
+        Backup::new("", BackupKind::None)
+    }
+}
+
+impl Savestate for Backup {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.kind as u8);
+
+        self.data.save(state);
+        self.flash.save(state);
+        self.eeprom.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        if state.read_u8()? != self.kind as u8 {
+            return Err("Savestate is for another backup type");
+        }
+
+        let mut data = Vec::new();
+        data.load(state)?;
+
+        // The EEPROM shrinks to 512B once its size is known, and its
+        // accesses are masked with the size, so both are fine
+        let size_ok = if self.kind == BackupKind::Eeprom {
+            data.len() == 0x200 || data.len() == 0x2000
+        } else {
+            data.len() == self.data.len()
+        };
+
+        if !size_ok {
+            return Err("Savestate backup size doesn't match");
+        }
+
+        if data != self.data {
+            self.data = data;
+            self.mark_dirty();
+        }
+
+        self.flash.load(state)?;
+        self.eeprom.load(state)
+    }
+}
@@ -6,6 +6,13 @@
 // Last-Updated: Thu Nov  5 21:11:57 2020 (+0100)
 //           By: Louise <louise>
 // 
+use rgba_common::savestate;
+
+// Start timings
+pub const TIMING_IMMEDIATE: u16 = 0;
+pub const TIMING_VBLANK: u16 = 1;
+pub const TIMING_HBLANK: u16 = 2;
+pub const TIMING_SPECIAL: u16 = 3;
 
 #[derive(Default)]
 pub struct DmaChannel {
@@ -22,9 +29,21 @@ pub struct DmaChannel {
     pub drq: bool,
     pub start_timing: u16,
     pub irq_en: bool,
-    pub enable: bool
+    pub enable: bool,
+
+    // Internal registers, latched when the channel is enabled
+    pub internal_source: u32,
+    pub internal_dest: u32,
+    pub internal_count: u32,
+    pub pending: bool,
 }
 
+savestate!(DmaChannel,
+           channel, source_addr, dest_addr, word_count,
+           source_mode, dest_mode, repeat, word_size, drq,
+           start_timing, irq_en, enable,
+           internal_source, internal_dest, internal_count, pending);
+
 impl DmaChannel {
     pub fn new(channel: u32) -> DmaChannel {
         let mut dma = DmaChannel::default();
@@ -53,6 +72,8 @@ impl DmaChannel {
 
     #[inline]
     pub fn write_cnt_h(&mut self, cnt: u16) {
+        let was_enabled = self.enable;
+        
         self.dest_mode = (cnt >> 5) & 3;
         self.source_mode = (cnt >> 7) & 3;
         self.repeat = ((cnt >> 9) & 1) != 0;
@@ -62,10 +83,79 @@ impl DmaChannel {
         self.irq_en = ((cnt >> 14) & 1) != 0;
         self.enable = ((cnt >> 15) & 1) != 0;
 
-        if self.enable {
-            warn!("Writing to DMA{}CNT_H with value {:04x} (from {:08x} to {:08x})", self.channel, cnt, self.source_addr, self.dest_addr);
+        if self.enable && !was_enabled {
+            debug!("Enabling DMA{} with value {:04x} (from {:08x} to {:08x})", self.channel, cnt, self.source_addr, self.dest_addr);
+
+            self.internal_source = self.source_addr & self.source_mask();
+            self.internal_dest = self.dest_addr & self.dest_mask();
+            self.reload_count();
+
+            if self.start_timing == TIMING_SPECIAL && self.channel == 0 {
+                warn!("DMA0 doesn't have a special start timing");
+            }
+
+            self.pending = self.start_timing == TIMING_IMMEDIATE;
+        } else if !self.enable {
+            self.pending = false;
         }
     }
+
+    #[inline]
+    fn source_mask(&self) -> u32 {
+        if self.channel == 0 { 0x07FFFFFF } else { 0x0FFFFFFF }
+    }
+
+    #[inline]
+    fn dest_mask(&self) -> u32 {
+        if self.channel == 3 { 0x0FFFFFFF } else { 0x07FFFFFF }
+    }
+
+    /// A word count of 0 means the maximum length for the channel.
+    #[inline]
+    pub fn reload_count(&mut self) {
+        self.internal_count = match (self.channel, self.word_count) {
+            (3, 0) => 0x10000,
+            (_, 0) => 0x4000,
+            (3, count) => count as u32,
+            (_, count) => (count & 0x3FFF) as u32,
+        };
+    }
+
+    /// Marks the channel as ready to run if it is waiting for
+    /// the given start timing.
+    #[inline]
+    pub fn trigger(&mut self, timing: u16) {
+        if self.enable && self.start_timing == timing {
+            self.pending = true;
+        }
+    }
+
+    /// Called once a transfer is over. Repeating channels wait
+    /// for their next trigger, the others are disabled.
+    pub fn finish(&mut self) {
+        self.pending = false;
+
+        if self.repeat && self.start_timing != TIMING_IMMEDIATE {
+            self.reload_count();
+
+            // Increment/Reload
+            if self.dest_mode == 3 {
+                self.internal_dest = self.dest_addr & self.dest_mask();
+            }
+        } else {
+            self.enable = false;
+        }
+    }
+}
+
+/// Next address for the given address control mode.
+#[inline]
+pub fn step_address(address: u32, mode: u16, size: u32) -> u32 {
+    match mode {
+        1 => address.wrapping_sub(size),
+        2 => address,
+        _ => address.wrapping_add(size),
+    }
 }
 
 // Channel 0 I/O ports
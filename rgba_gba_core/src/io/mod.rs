@@ -6,16 +6,20 @@
 // Last-Updated: Thu Nov  5 21:07:37 2020 (+0100)
 //           By: Louise <louise>
 //
+mod backup;
 mod dma;
 mod timer;
 
 use crate::cpu::ARM7TDMI;
-use crate::gpu::GPU;
-use crate::apu::APU;
+use crate::gpu::{GPU, DMA_VBLANK, DMA_HBLANK, DMA_VIDEO, DMA_VIDEO_END, HDRAW_CYCLES, LINE_CYCLES};
+use crate::apu::{APU, FIFO_A_L, FIFO_B_L};
 use crate::keypad::Keypad;
-use crate::irq::IrqManager;
-use crate::io::dma::DmaChannel;
+use crate::irq::{IrqManager, IRQ_DMA, IRQ_KEYPAD};
+use crate::io::dma::{DmaChannel, step_address};
+use crate::io::dma::{TIMING_VBLANK, TIMING_HBLANK, TIMING_SPECIAL};
 use crate::io::timer::Timer;
+use crate::io::backup::{Backup, BackupKind};
+use crate::scheduler::{Scheduler, Event, EventKind};
 
 // Import DMA I/O ports
 use crate::io::dma::{DMA0SAD_L, DMA0SAD_H, DMA0DAD_L, DMA0DAD_H, DMA0CNT_L, DMA0CNT_H};
@@ -27,16 +31,18 @@ use crate::io::dma::{DMA3SAD_L, DMA3SAD_H, DMA3DAD_L, DMA3DAD_H, DMA3CNT_L, DMA3
 use crate::io::timer::{TM0CNT_L, TM1CNT_L, TM2CNT_L, TM3CNT_L,
                        TM0CNT_H, TM1CNT_H, TM2CNT_H, TM3CNT_H};
 
+use rgba_common::{Platform, Savestate, StateWriter, StateReader};
+
 use byteorder::{ByteOrder, LittleEndian};
 use std::fs::File;
 use std::io::Read;
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::Cell;
 
 #[macro_use] mod macros;
 
 pub struct Interconnect {
     bios: Vec<u8>,
+    hle_bios: bool,
     rom:  Vec<u8>,
     iram: [u8; 0x8000],
     eram: [u8; 0x40000],
@@ -45,35 +51,86 @@ pub struct Interconnect {
     gpu: GPU,
     apu: APU,
     pub keypad: Keypad,
+    sound_enabled: bool,
 
     cycles_to_spend: u32,
+    scheduler: Scheduler,
+    apu_timestamp: u64,
     rom_len: usize,
     waitstates: [[[u32; 2]; 3]; 16],
 
     postflg: u8,
     irq: IrqManager,
     dma: [DmaChannel; 4],
-    timer: [Rc<RefCell<Timer>>; 4],
+    timer: [Timer; 4],
+    backup: Backup,
+
+    // Watchpoints
+    watchpoints_enabled: bool,
+    watchpoints: Vec<Watchpoint>,
+    watchpoint_hit: Cell<Option<(u32, WatchKind)>>,
+}
+
+/// Kind of access a watchpoint stops on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Watchpoint {
+    address: u32,
+    len: u32,
+    kind: WatchKind,
+}
+
+impl Savestate for Interconnect {
+    fn save(&self, state: &mut StateWriter) {
+        self.iram.save(state);
+        self.eram.save(state);
+        self.io.save(state);
+        self.gpu.save(state);
+        self.apu.save(state);
+        self.keypad.save(state);
+        self.cycles_to_spend.save(state);
+        self.scheduler.save(state);
+        self.apu_timestamp.save(state);
+        self.postflg.save(state);
+        self.irq.save(state);
+        self.dma.save(state);
+
+        self.timer.save(state);
+
+        self.backup.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        self.iram.load(state)?;
+        self.eram.load(state)?;
+        self.io.load(state)?;
+        self.gpu.load(state)?;
+        self.apu.load(state)?;
+        self.keypad.load(state)?;
+        self.cycles_to_spend.load(state)?;
+        self.scheduler.load(state)?;
+        self.apu_timestamp.load(state)?;
+        self.postflg.load(state)?;
+        self.irq.load(state)?;
+        self.dma.load(state)?;
+
+        self.timer.load(state)?;
+
+        self.backup.load(state)
+    }
 }
 
 impl Interconnect {
     pub fn new() -> Interconnect {
-        let timer1: Rc<RefCell<Timer>> = Default::default();
-        let timer2: Rc<RefCell<Timer>> = Default::default();
-        let timer3: Rc<RefCell<Timer>> = Default::default();
-        let timer4: Rc<RefCell<Timer>> = Default::default();
-
-        timer1.borrow_mut().set_id(1 << 3);
-        timer2.borrow_mut().set_id(1 << 4);
-        timer3.borrow_mut().set_id(1 << 5);
-        timer4.borrow_mut().set_id(1 << 6);
-        
-        timer1.borrow_mut().set_next(timer2.clone());
-        timer2.borrow_mut().set_next(timer3.clone());
-        timer3.borrow_mut().set_next(timer4.clone());
-            
-        Interconnect {
+        let mut io = Interconnect {
             bios: vec![],
+            hle_bios: false,
             rom:  vec![],
             iram: [0; 0x8000],
             eram: [0; 0x40000],
@@ -83,6 +140,8 @@ impl Interconnect {
             keypad: Keypad::default(),
 
             cycles_to_spend: 0,
+            scheduler: Scheduler::new(),
+            apu_timestamp: 0,
             rom_len: 0,
             waitstates: [
                 [[1, 1], [1, 1], [1, 1]], // BIOS
@@ -106,8 +165,56 @@ impl Interconnect {
             postflg: 0,
             irq: IrqManager::new(),
             dma: [DmaChannel::new(0), DmaChannel::new(1), DmaChannel::new(2), DmaChannel::new(3)],
-            timer: [timer1, timer2, timer3, timer4],
+            timer: [Timer::new(1 << 3), Timer::new(1 << 4), Timer::new(1 << 5), Timer::new(1 << 6)],
+            backup: Backup::default(),
+            sound_enabled: true,
+
+            watchpoints_enabled: false,
+            watchpoints: Vec::new(),
+            watchpoint_hit: Cell::new(None),
+        };
+
+        io.start_scheduler();
+        io
+    }
+
+    /// Schedules the events that are always there
+    fn start_scheduler(&mut self) {
+        self.scheduler = Scheduler::new();
+        self.apu_timestamp = 0;
+
+        self.scheduler.schedule(HDRAW_CYCLES, EventKind::HBlank);
+        self.scheduler.schedule(self.apu.cycles_to_next_sample() as u64, EventKind::ApuSample);
+    }
+
+    /// Puts everything back in its power-on state, except for the
+    /// BIOS, the cartridge and its backup.
+    pub fn reset(&mut self) {
+        self.backup.flush();
+
+        self.iram.fill(0);
+        self.eram.fill(0);
+        self.io.fill(0);
+
+        self.gpu = GPU::new();
+        self.apu = APU::new();
+        self.keypad = Keypad::default();
+
+        self.cycles_to_spend = 0;
+        self.start_scheduler();
+        self.postflg = self.hle_bios as u8;
+        self.irq = IrqManager::new();
+        self.dma = [DmaChannel::new(0), DmaChannel::new(1), DmaChannel::new(2), DmaChannel::new(3)];
+
+        for timer in self.timer.iter_mut() {
+            timer.reset();
         }
+
+        self.watchpoint_hit.set(None);
+    }
+
+    pub fn set_sound_enabled(&mut self, sound_enabled: bool) {
+        self.sound_enabled = sound_enabled;
     }
 
     pub fn declare_access(&mut self, address: usize, width: usize) {
@@ -118,30 +225,231 @@ impl Interconnect {
         self.cycles_to_spend += cycles;
     }
 
-    pub fn spend(&mut self, cpu: &mut ARM7TDMI) {
-        self.gpu.spend_cycles(self.cycles_to_spend, &mut self.irq);
+    /// Current timestamp, counting the cycles not spent yet
+    #[inline]
+    fn now(&self) -> u64 {
+        self.scheduler.now() + self.cycles_to_spend as u64
+    }
+
+    /// Used while halted, nothing can happen before the next event.
+    pub fn skip_to_next_event(&mut self) {
+        let now = self.now();
 
-        // Handle DMA (immediately)
-        if self.dma[0].start_timing == 0 && self.dma[0].enable {
-            handle_dma!(self, self.dma[0]);
-        } else if self.dma[1].start_timing == 0 && self.dma[1].enable {
-            handle_dma!(self, self.dma[1]);
-        } else if self.dma[2].start_timing == 0 && self.dma[2].enable {
-            handle_dma!(self, self.dma[2]);
-        } else if self.dma[3].start_timing == 0 && self.dma[3].enable {
-            handle_dma!(self, self.dma[3]);
+        if let Some(time) = self.scheduler.next_event() {
+            self.cycles_to_spend += time.saturating_sub(now).max(1) as u32;
         }
+    }
+
+    pub fn spend(&mut self, cpu: &mut ARM7TDMI) {
+        self.scheduler.advance(self.cycles_to_spend);
+        self.cycles_to_spend = 0;
 
-        for timer_ref in &self.timer {
-            timer_ref.borrow_mut().spend_cycles(self.cycles_to_spend, &mut self.irq);
+        while let Some(event) = self.scheduler.pop() {
+            self.handle_event(event);
         }
-        
+
         self.irq.handle(cpu);
-        self.cycles_to_spend = 0;
     }
 
-    pub fn render(&mut self) {
+    fn handle_event(&mut self, event: Event) {
+        match event.kind {
+            EventKind::HBlank => {
+                let triggers = self.gpu.hblank(&mut self.irq);
+
+                self.scheduler.schedule(event.time + LINE_CYCLES - HDRAW_CYCLES, EventKind::EndLine);
+                self.trigger_dma(triggers);
+            }
+            EventKind::EndLine => {
+                let triggers = self.gpu.end_line(&mut self.irq);
+
+                self.scheduler.schedule(event.time + HDRAW_CYCLES, EventKind::HBlank);
+                self.trigger_dma(triggers);
+            }
+            EventKind::TimerOverflow(timer) => self.timer_overflow(timer, event.time),
+            EventKind::Dma => self.run_pending_dma(),
+            EventKind::ApuSample => {
+                self.sync_apu(event.time);
+
+                let next = event.time + self.apu.cycles_to_next_sample() as u64;
+                self.scheduler.schedule(next, EventKind::ApuSample);
+            }
+        }
+    }
+
+    /// Catches the APU up to the given timestamp
+    fn sync_apu(&mut self, now: u64) {
+        if self.sound_enabled {
+            self.apu.spend_cycles((now - self.apu_timestamp) as u32);
+        }
+
+        self.apu_timestamp = now;
+    }
+
+    fn timer_overflow(&mut self, timer: usize, time: u64) {
+        self.timer[timer].overflow(time);
+        self.schedule_timer(timer);
+
+        // Overflows cascade to the next timers in count-up mode
+        let mut timer = timer;
+
+        loop {
+            if let Some(irq) = self.timer[timer].irq_n() {
+                self.irq.raise_irq(irq);
+            }
+
+            // Timers 0 and 1 drive the Direct Sound FIFOs
+            if timer < 2 {
+                let refill = self.apu.timer_overflow(timer);
+
+                if (refill & 1) != 0 { self.fifo_dma(FIFO_A_L as u32); }
+                if (refill & 2) != 0 { self.fifo_dma(FIFO_B_L as u32); }
+            }
+
+            timer += 1;
+
+            if timer == 4 || !self.timer[timer].countup_sig() {
+                break;
+            }
+        }
+    }
+
+    fn schedule_timer(&mut self, timer: usize) {
+        self.scheduler.cancel(EventKind::TimerOverflow(timer));
+
+        if let Some(time) = self.timer[timer].next_overflow() {
+            self.scheduler.schedule(time, EventKind::TimerOverflow(timer));
+        }
+    }
+
+    fn write_timer_cnt(&mut self, timer: usize, value: u16) {
+        let now = self.now();
+
+        self.timer[timer].write_cnt_l(value, now);
+        self.schedule_timer(timer);
+    }
+
+    fn trigger_dma(&mut self, triggers: u8) {
+        if (triggers & DMA_VBLANK) != 0 {
+            for dma in self.dma.iter_mut() { dma.trigger(TIMING_VBLANK); }
+        }
+
+        if (triggers & DMA_HBLANK) != 0 {
+            for dma in self.dma.iter_mut() { dma.trigger(TIMING_HBLANK); }
+        }
+
+        if (triggers & DMA_VIDEO) != 0 {
+            self.dma[3].trigger(TIMING_SPECIAL);
+        }
+
+        if (triggers & DMA_VIDEO_END) != 0 && self.dma[3].start_timing == TIMING_SPECIAL {
+            self.dma[3].enable = false;
+            self.dma[3].pending = false;
+        }
+
+        self.run_pending_dma();
+    }
+
+    /// Lower channels have priority, and a transfer runs
+    /// to completion before the next one starts
+    fn run_pending_dma(&mut self) {
+        for ch in 0..4 {
+            if self.dma[ch].pending {
+                self.run_dma(ch);
+            }
+        }
+    }
+
+    /// Immediate transfers start as soon as the channel is enabled
+    fn write_dma_cnt(&mut self, ch: usize, value: u32) {
+        self.dma[ch].write_cnt(value);
+
+        if self.dma[ch].pending && !self.scheduler.is_scheduled(EventKind::Dma) {
+            let now = self.now();
+            self.scheduler.schedule(now, EventKind::Dma);
+        }
+    }
+
+    /// Runs a whole transfer on a channel.
+    fn run_dma(&mut self, ch: usize) {
+        let mut source_addr = self.dma[ch].internal_source;
+        let mut dest_addr = self.dma[ch].internal_dest;
+        let count = self.dma[ch].internal_count;
+        let word_size = self.dma[ch].word_size;
+        let size = if word_size { 4 } else { 2 };
+
+        // Source mode 3 is prohibited, and the destination
+        // increments in Increment/Reload mode
+        let source_mode = self.dma[ch].source_mode;
+        let dest_mode = self.dma[ch].dest_mode;
+
+        // EEPROM is accessed through DMA3, the length of the
+        // transfer gives away its size
+        if ch == 3 {
+            self.backup.eeprom_dma(dest_addr & 0x0F000000 == 0x0D000000, count as u16);
+        }
+
+        for _ in 0..count {
+            if word_size {
+                let v = self.read_u32((source_addr & !3) as usize);
+                self.write_u32((dest_addr & !3) as usize, v);
+            } else {
+                let v = self.read_u16((source_addr & !1) as usize);
+                self.write_u16((dest_addr & !1) as usize, v);
+            }
+
+            source_addr = step_address(source_addr, source_mode, size);
+            dest_addr = step_address(dest_addr, dest_mode, size);
+        }
+
+        self.dma[ch].internal_source = source_addr;
+        self.dma[ch].internal_dest = dest_addr;
+        self.dma[ch].finish();
+
+        if self.dma[ch].irq_en {
+            self.irq.raise_irq(IRQ_DMA[ch]);
+        }
+    }
+
+    /// Sound DMA, DMA1 and DMA2 in special timing mode send
+    /// four words to the FIFO they point to.
+    fn fifo_dma(&mut self, fifo: u32) {
+        let channel = (1..3).find(|&ch| {
+            self.dma[ch].enable
+                && self.dma[ch].start_timing == TIMING_SPECIAL
+                && self.dma[ch].internal_dest == fifo
+        });
+
+        if let Some(ch) = channel {
+            let mut source_addr = self.dma[ch].internal_source;
+            let source_mode = self.dma[ch].source_mode;
+
+            // The word count and size are ignored, and the
+            // destination doesn't move
+            for _ in 0..4 {
+                let v = self.read_u32((source_addr & !3) as usize);
+                self.write_u32(fifo as usize, v);
+
+                source_addr = step_address(source_addr, source_mode, 4);
+            }
+
+            self.dma[ch].internal_source = source_addr;
+
+            if !self.dma[ch].repeat {
+                self.dma[ch].enable = false;
+            }
+
+            if self.dma[ch].irq_en {
+                self.irq.raise_irq(IRQ_DMA[ch]);
+            }
+        }
+    }
+
+    pub fn render<T: Platform>(&mut self, platform: &mut T) {
         self.gpu.render();
+
+        if self.sound_enabled {
+            self.apu.render(platform);
+        }
     }
 
     pub fn get_framebuffer(&self) -> &[u32] {
@@ -149,6 +457,10 @@ impl Interconnect {
     }
     
     pub fn read_u32(&self, address: usize) -> u32 {
+        if self.watchpoints_enabled {
+            self.check_watchpoints(address, 4, false);
+        }
+
         match address & 0x0F000000 {
             0x00000000 if address < 0x4000 =>
                 LittleEndian::read_u32(&self.bios[address..]),
@@ -170,11 +482,25 @@ impl Interconnect {
             } else {
                 unused_pattern!(address, 32) as u32
             },
+            0x0E000000 => (self.backup.read_u8(address) as u32) * 0x01010101,
             _ => { warn!("Unmapped read_u32 from {:08x}", address); 0 },
         }
     }
 
     pub fn read_u16(&self, address: usize) -> u16 {
+        if self.watchpoints_enabled {
+            self.check_watchpoints(address, 2, false);
+        }
+
+        match address & 0x0F000000 {
+            0x0D000000 if self.is_eeprom(address) => self.backup.eeprom_read(),
+            _ => self.peek_u16(address),
+        }
+    }
+
+    /// Reads a halfword for the debugger, without checking the
+    /// watchpoints nor shifting the EEPROM output.
+    pub fn peek_u16(&self, address: usize) -> u16 {
         match address & 0x0F000000 {
             0x00000000 if address < 0x4000 =>
                 LittleEndian::read_u16(&self.bios[address..]),
@@ -196,11 +522,16 @@ impl Interconnect {
             } else {
                 unused_pattern!(address, 16) as u16
             },
+            0x0E000000 => (self.backup.read_u8(address) as u16) * 0x0101,
             _ => { warn!("Unmapped read_u16 from {:08x}", address); 0 },
         }
     }
 
     pub fn read_u8(&self, address: usize) -> u8 {
+        if self.watchpoints_enabled {
+            self.check_watchpoints(address, 1, false);
+        }
+
         match address & 0x0F000000 {
             0x00000000 if address < 0x4000 => self.bios[address],
             0x02000000 => self.eram[address & 0x3ffff],
@@ -216,6 +547,7 @@ impl Interconnect {
             } else {
                 unused_pattern!(address, 8) as u8
             }
+            0x0E000000 => self.backup.read_u8(address),
             _ => { warn!("Unmapped read_u8 from {:08x}", address); 0 },
         }
     }
@@ -232,20 +564,21 @@ impl Interconnect {
     fn io_read_u16(&self, address: usize) -> u16 {
         match address {
             KEYINPUT => self.keypad.as_register(),
+            KEYCNT => self.keypad.keycnt,
             IE => self.irq.i_e,
             IF => self.irq.i_f,
             IME => self.irq.ime as u16,
             0x04000000..=0x04000056 => self.gpu.io_read_u16(address),
             0x04000060..=0x040000A8 => self.apu.io_read_u16(address),
 
-            TM0CNT_L => self.timer[0].borrow().read_cnt_l(),
-            TM0CNT_H => self.timer[0].borrow().read_cnt_h(),
-            TM1CNT_L => self.timer[1].borrow().read_cnt_l(),
-            TM1CNT_H => self.timer[1].borrow().read_cnt_h(),
-            TM2CNT_L => self.timer[2].borrow().read_cnt_l(),
-            TM2CNT_H => self.timer[2].borrow().read_cnt_h(),
-            TM3CNT_L => self.timer[3].borrow().read_cnt_l(),
-            TM3CNT_H => self.timer[3].borrow().read_cnt_h(),
+            TM0CNT_L => self.timer[0].read_cnt_h(),
+            TM0CNT_H => self.timer[0].read_cnt_l(self.now()),
+            TM1CNT_L => self.timer[1].read_cnt_h(),
+            TM1CNT_H => self.timer[1].read_cnt_l(self.now()),
+            TM2CNT_L => self.timer[2].read_cnt_h(),
+            TM2CNT_H => self.timer[2].read_cnt_l(self.now()),
+            TM3CNT_L => self.timer[3].read_cnt_h(),
+            TM3CNT_H => self.timer[3].read_cnt_l(self.now()),
 
             DMA0SAD_L => (self.dma[0].source_addr & 0xffff) as u16,
             DMA0SAD_H => (self.dma[0].source_addr >> 16) as u16,
@@ -268,7 +601,7 @@ impl Interconnect {
             DMA2CNT_L => self.dma[2].word_count,
             DMA2CNT_H => self.dma[2].read_cnt_h(),
             
-            DMA3SAD_L => (self.dma[0].source_addr & 0xffff) as u16,
+            DMA3SAD_L => (self.dma[3].source_addr & 0xffff) as u16,
             DMA3SAD_H => (self.dma[3].source_addr >> 16) as u16,
             DMA3DAD_L => (self.dma[3].dest_addr & 0xffff) as u16,
             DMA3DAD_H => (self.dma[3].dest_addr >> 16) as u16,
@@ -287,6 +620,10 @@ impl Interconnect {
     }
 
     pub fn write_u32(&mut self, address: usize, value: u32) {
+        if self.watchpoints_enabled {
+            self.check_watchpoints(address, 4, true);
+        }
+
         match address & 0x0F000000 {
             0x00000000 if address < 0x4000 => warn!("Ignored write to BIOS ({:08x})", address),
             0x02000000 => LittleEndian::write_u32(
@@ -306,13 +643,33 @@ impl Interconnect {
             0x0A000000 |
             0x0B000000 |
             0x0C000000 |
-            0x0D000000 |
-            0x0E000000 => { }
+            0x0D000000 => { }
+
+            // The backup has an 8-bit bus
+            0x0E000000 => self.backup.write_u8(
+                address, (value >> ((address & 3) << 3)) as u8
+            ),
             _ => warn!("Unmapped write_u32 to {:08x} (value={:08x})", address, value)
         }
     }
 
     pub fn write_u16(&mut self, address: usize, value: u16) {
+        if self.watchpoints_enabled {
+            self.check_watchpoints(address, 2, true);
+        }
+
+        match address & 0x0F000000 {
+            0x0D000000 if self.is_eeprom(address) => self.backup.eeprom_write(value),
+            0x0E000000 => self.backup.write_u8(
+                address, (value >> ((address & 1) << 3)) as u8
+            ),
+            _ => self.poke_u16(address, value),
+        }
+    }
+
+    /// Writes a halfword for the debugger, without checking the
+    /// watchpoints nor going through the EEPROM and Flash protocols.
+    pub fn poke_u16(&mut self, address: usize, value: u16) {
         match address & 0x0F000000 {
             0x00000000 if address < 0x4000 => warn!("Ignored write to BIOS ({:08x})", address),
             0x02000000 => LittleEndian::write_u16(
@@ -325,16 +682,24 @@ impl Interconnect {
             0x05000000 => self.gpu.pram_write_u16(address, value),
             0x06000000 => self.gpu.vram_write_u16(address, value),
             0x07000000 => self.gpu.oam_write_u16(address, value),
+            0x0E000000 => self.backup.poke_u8(
+                address, (value >> ((address & 1) << 3)) as u8
+            ),
             _ => warn!("Unmapped write_u16 to {:08x} (value={:04x})", address, value),
         }
     }
     
     pub fn write_u8(&mut self, address: usize, value: u8) {
+        if self.watchpoints_enabled {
+            self.check_watchpoints(address, 1, true);
+        }
+
         match address & 0x0F000000 {
             0x00000000 if address < 0x4000 => warn!("Ignored write to BIOS ({:08x})", address),
             0x02000000 => self.eram[address & 0x3ffff] = value,
             0x03000000 => self.iram[address & 0x7fff] = value,
             0x04000000 => self.io_write_u8(address, value),
+            0x0E000000 => self.backup.write_u8(address, value),
             _ => warn!("Unmapped write_u8 to {:08x} (value={:02x})", address, value),
         }
     }
@@ -344,19 +709,19 @@ impl Interconnect {
             // DMA writes are often 32bit so we handle them directly
             DMA0SAD_L => self.dma[0].source_addr = value,
             DMA0DAD_L => self.dma[0].dest_addr = value,
-            DMA0CNT_L => self.dma[0].write_cnt(value),
+            DMA0CNT_L => self.write_dma_cnt(0, value),
             
             DMA1SAD_L => self.dma[1].source_addr = value,
             DMA1DAD_L => self.dma[1].dest_addr = value,
-            DMA1CNT_L => self.dma[1].write_cnt(value),
+            DMA1CNT_L => self.write_dma_cnt(1, value),
             
             DMA2SAD_L => self.dma[2].source_addr = value,
             DMA2DAD_L => self.dma[2].dest_addr = value,
-            DMA2CNT_L => self.dma[2].write_cnt(value),
+            DMA2CNT_L => self.write_dma_cnt(2, value),
             
             DMA3SAD_L => self.dma[3].source_addr = value,
             DMA3DAD_L => self.dma[3].dest_addr = value,
-            DMA3CNT_L => self.dma[3].write_cnt(value),
+            DMA3CNT_L => self.write_dma_cnt(3, value),
             
             _ => {
                 self.io_write_u16(address, value as u16);
@@ -370,16 +735,21 @@ impl Interconnect {
         
         match address {
             0x04000000..=0x04000056 => self.gpu.io_write_u16(address, value),
-            0x04000060..=0x040000A8 => self.apu.io_write_u16(address, value),
-
-            TM0CNT_L => self.timer[0].borrow_mut().write_cnt_l(value),
-            TM0CNT_H => self.timer[0].borrow_mut().write_cnt_h(value),
-            TM1CNT_L => self.timer[1].borrow_mut().write_cnt_l(value),
-            TM1CNT_H => self.timer[1].borrow_mut().write_cnt_h(value),
-            TM2CNT_L => self.timer[2].borrow_mut().write_cnt_l(value),
-            TM2CNT_H => self.timer[2].borrow_mut().write_cnt_h(value),
-            TM3CNT_L => self.timer[3].borrow_mut().write_cnt_l(value),
-            TM3CNT_H => self.timer[3].borrow_mut().write_cnt_h(value),
+            0x04000060..=0x040000A8 => {
+                let now = self.now();
+
+                self.sync_apu(now);
+                self.apu.io_write_u16(address, value);
+            }
+
+            TM0CNT_L => self.write_timer_cnt(0, value),
+            TM0CNT_H => self.timer[0].write_cnt_h(value),
+            TM1CNT_L => self.write_timer_cnt(1, value),
+            TM1CNT_H => self.timer[1].write_cnt_h(value),
+            TM2CNT_L => self.write_timer_cnt(2, value),
+            TM2CNT_H => self.timer[2].write_cnt_h(value),
+            TM3CNT_L => self.write_timer_cnt(3, value),
+            TM3CNT_H => self.timer[3].write_cnt_h(value),
             
             DMA0SAD_L =>
                 self.dma[0].source_addr = (self.dma[0].source_addr & 0xffff0000) | (value as u32),
@@ -390,7 +760,10 @@ impl Interconnect {
             DMA0DAD_H =>
                 self.dma[0].dest_addr = (self.dma[0].dest_addr & 0xffff) | ((value as u32) << 16),
             DMA0CNT_L => self.dma[0].word_count = value,
-            DMA0CNT_H => self.dma[0].write_cnt_h(value),
+            DMA0CNT_H => {
+                let count = self.dma[0].word_count as u32;
+                self.write_dma_cnt(0, count | ((value as u32) << 16));
+            }
 
             DMA1SAD_L =>
                 self.dma[1].source_addr = (self.dma[1].source_addr & 0xffff0000) | (value as u32),
@@ -401,7 +774,10 @@ impl Interconnect {
             DMA1DAD_H =>
                 self.dma[1].dest_addr = (self.dma[1].dest_addr & 0xffff) | ((value as u32) << 16),
             DMA1CNT_L => self.dma[1].word_count = value,
-            DMA1CNT_H => self.dma[1].write_cnt_h(value),
+            DMA1CNT_H => {
+                let count = self.dma[1].word_count as u32;
+                self.write_dma_cnt(1, count | ((value as u32) << 16));
+            }
 
             DMA2SAD_L =>
                 self.dma[2].source_addr = (self.dma[2].source_addr & 0xffff0000) | (value as u32),
@@ -412,7 +788,10 @@ impl Interconnect {
             DMA2DAD_H =>
                 self.dma[2].dest_addr = (self.dma[2].dest_addr & 0xffff) | ((value as u32) << 16),
             DMA2CNT_L => self.dma[2].word_count = value,
-            DMA2CNT_H => self.dma[2].write_cnt_h(value),
+            DMA2CNT_H => {
+                let count = self.dma[2].word_count as u32;
+                self.write_dma_cnt(2, count | ((value as u32) << 16));
+            }
 
             DMA3SAD_L =>
                 self.dma[3].source_addr = (self.dma[3].source_addr & 0xffff0000) | (value as u32),
@@ -423,8 +802,15 @@ impl Interconnect {
             DMA3DAD_H =>
                 self.dma[3].dest_addr = (self.dma[3].dest_addr & 0xffff) | ((value as u32) << 16),
             DMA3CNT_L => self.dma[3].word_count = value,
-            DMA3CNT_H => self.dma[3].write_cnt_h(value),
+            DMA3CNT_H => {
+                let count = self.dma[3].word_count as u32;
+                self.write_dma_cnt(3, count | ((value as u32) << 16));
+            }
             
+            KEYCNT => {
+                self.keypad.set_keycnt(value);
+                self.check_keypad_irq();
+            }
             IE => self.irq.i_e = value,
             IF => self.irq.write_if(value),
             IME => self.irq.write_ime(value),
@@ -434,6 +820,7 @@ impl Interconnect {
     
     fn io_write_u8(&mut self, address: usize, value: u8) {
         match address {
+            HALTCNT if (value & 0x80) != 0 => self.irq.stop = true,
             HALTCNT => self.irq.halt = true,
             POSTFLG => self.postflg = value,
             _ => {
@@ -445,9 +832,70 @@ impl Interconnect {
         }
     }
 
+    // Backup
+    /// EEPROM sits at the top of the 0x0D000000 region, which
+    /// is the whole region for ROMs up to 16MB.
+    #[inline]
+    fn is_eeprom(&self, address: usize) -> bool {
+        self.backup.is_eeprom() && (self.rom_len <= 0x1000000 || address >= 0x0DFFFF00)
+    }
+
+    #[inline]
+    pub fn tick_savefile(&mut self) { self.backup.tick(); }
+    #[inline]
+    pub fn flush_savefile(&mut self) { self.backup.flush(); }
+
     // IRQ
     #[inline]
-    pub fn halt(&self) -> bool { self.irq.halt }
+    pub fn halt(&self) -> bool { self.irq.halt || self.irq.stop }
+    /// In STOP mode, nothing runs until a key interrupt
+    #[inline]
+    pub fn stopped(&self) -> bool { self.irq.stop }
+
+    /// Raises the keypad interrupt if KEYCNT's condition is met,
+    /// to be called whenever the keys or KEYCNT change.
+    pub fn check_keypad_irq(&mut self) {
+        if self.keypad.irq_condition() {
+            self.irq.raise_irq(IRQ_KEYPAD);
+        }
+    }
+
+    // Watchpoints
+    fn check_watchpoints(&self, address: usize, width: u32, write: bool) {
+        let address = address as u32;
+
+        for w in self.watchpoints.iter() {
+            let kind_matches = match w.kind {
+                WatchKind::Write => write,
+                WatchKind::Read => !write,
+                WatchKind::Access => true,
+            };
+
+            if kind_matches && (address < w.address.wrapping_add(w.len)) &&
+                (w.address < address.wrapping_add(width)) {
+                self.watchpoint_hit.set(Some((address, w.kind)));
+            }
+        }
+    }
+
+    #[inline]
+    pub fn watchpoint_hit(&self) -> Option<(u32, WatchKind)> { self.watchpoint_hit.get() }
+    #[inline]
+    pub fn ack_watchpoint(&self) { self.watchpoint_hit.set(None) }
+
+    pub fn set_watchpoint(&mut self, address: u32, len: u32, kind: WatchKind) {
+        self.watchpoints_enabled = true;
+        self.watchpoints.push(Watchpoint { address, len, kind });
+    }
+
+    pub fn rem_watchpoint(&mut self, address: u32, len: u32, kind: WatchKind) -> bool {
+        let count = self.watchpoints.len();
+
+        self.watchpoints.retain(|w| *w != Watchpoint { address, len, kind });
+        self.watchpoints_enabled = !self.watchpoints.is_empty();
+
+        self.watchpoints.len() != count
+    }
 
     // Frame
     #[inline]
@@ -474,30 +922,61 @@ impl Interconnect {
         }
     }
 
-    pub fn load_rom(&mut self, filename: &str) -> bool {
+    /// Without a BIOS file, the BIOS calls are emulated by the CPU,
+    /// and the BIOS region only holds the IRQ handler.
+    pub fn load_hle_bios(&mut self) {
+        info!("No BIOS given, using HLE BIOS");
+
+        self.bios = vec![0; 0x4000];
+        self.hle_bios = true;
+        self.postflg = 1;
+
+        for (i, instr) in HLE_IRQ_HANDLER.iter().enumerate() {
+            LittleEndian::write_u32(&mut self.bios[0x18 + (i << 2)..], *instr);
+        }
+    }
+
+    #[inline]
+    pub fn has_bios(&self) -> bool { !self.hle_bios }
+
+    pub fn load_rom(&mut self, filename: &str) -> Result<(), &'static str> {
         match File::open(filename) {
             Ok(mut file) => {
                 info!("ROM file opened");
 
                 if let Err(e) = file.read_to_end(&mut self.rom) {
                     error!("Error reading ROM file : {}", e);
-                    false
+                    Err("Error reading ROM")
                 } else {
                     self.rom_len = self.rom.len();
-                    true
+
+                    self.backup.flush();
+                    self.backup = Backup::new(filename, BackupKind::detect(&self.rom));
+                    Ok(())
                 }
             }
             Err(e) => {
                 error!("Couldn't open ROM file : {}", e);
-                false
+                Err("Error opening ROM file")
             }
         }
     }
 }
 
 const KEYINPUT: usize = 0x04000130;
+const KEYCNT:   usize = 0x04000132;
 const IE:       usize = 0x04000200;
 const IF:       usize = 0x04000202;
 const IME:      usize = 0x04000208;
 const POSTFLG:  usize = 0x04000300;
 const HALTCNT:  usize = 0x04000301;
+
+// IRQ handler of the HLE BIOS, calling the user handler at 0x03007FFC
+const HLE_IRQ_HANDLER: [u32; 6] = [
+    0xE92D500F, // stmfd sp!, {r0-r3, r12, lr}
+    0xE3A00301, // mov r0, #0x04000000
+    0xE28FE000, // add lr, pc, #0
+    0xE510F004, // ldr pc, [r0, #-4]
+    0xE8BD500F, // ldmfd sp!, {r0-r3, r12, lr}
+    0xE25EF004, // subs pc, lr, #4
+];
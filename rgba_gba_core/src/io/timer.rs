@@ -6,52 +6,119 @@
 // Last-Updated: Tue Aug 20 13:26:33 2019 (+0200)
 //           By: Louise <ludwigette>
 // 
-use crate::irq::IrqManager;
-use std::{
-    rc::Rc,
-    cell::RefCell
-};
+use rgba_common::savestate;
 
+/// Timers don't tick on their own, their counter is computed from
+/// the time they were last updated, and the Interconnect schedules
+/// their overflows.
 #[derive(Clone, Default)]
 pub struct Timer {
-    internal: u32,
     counter: u16,
     reload: u16,
+    last_update: u64,
 
-    scale: u32,
+    shift: u32,
     countup: bool,
     irq: bool,
     irq_n: u16,
     start: bool,
-
-    next: Option<Rc<RefCell<Timer>>>
 }
 
+savestate!(Timer, counter, reload, last_update, shift, countup, irq, irq_n, start);
+
 impl Timer {
-    pub fn set_id(&mut self, id: u16) { self.irq_n = id; }
-    pub fn set_next(&mut self, next: Rc<RefCell<Timer>>) { self.next = Some(next); }
+    pub fn new(id: u16) -> Timer {
+        Timer { irq_n: id, ..Default::default() }
+    }
+
+    /// Power-on state, keeping the IRQ bit
+    pub fn reset(&mut self) {
+        *self = Timer::new(self.irq_n);
+    }
+
+    #[inline]
+    pub fn irq_n(&self) -> Option<u16> {
+        if self.irq { Some(self.irq_n) } else { None }
+    }
+
+    #[inline]
+    fn running(&self) -> bool { self.start && !self.countup }
+
+    /// The prescaler ticks on the system clock, so only whole
+    /// prescaler periods since the last update count.
+    #[inline]
+    pub fn counter(&self, now: u64) -> u16 {
+        if self.running() {
+            let ticks = (now >> self.shift) - (self.last_update >> self.shift);
+
+            self.counter.wrapping_add(ticks as u16)
+        } else {
+            self.counter
+        }
+    }
+
+    #[inline]
+    fn update(&mut self, now: u64) {
+        self.counter = self.counter(now);
+        self.last_update = now;
+    }
+
+    /// When the timer will overflow, if it runs on its own
+    pub fn next_overflow(&self) -> Option<u64> {
+        if self.running() {
+            let ticks = 0x10000 - self.counter as u64;
+
+            Some(((self.last_update >> self.shift) + ticks) << self.shift)
+        } else {
+            None
+        }
+    }
+
+    pub fn overflow(&mut self, now: u64) {
+        self.counter = self.reload;
+        self.last_update = now;
+    }
+
+    /// Returns true if the timer overflowed
+    pub fn countup_sig(&mut self) -> bool {
+        if !(self.start && self.countup) {
+            return false;
+        }
+
+        self.counter = self.counter.wrapping_add(1);
+
+        if self.counter == 0 {
+            self.counter = self.reload;
+
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn write_cnt_h(&mut self, value: u16) {
         self.reload = value;
     }
 
-    pub fn read_cnt_l(&self) -> u16 { return self.counter; }
+    pub fn read_cnt_l(&self, now: u64) -> u16 { self.counter(now) }
     pub fn read_cnt_h(&self) -> u16 {
-        return
-            match self.scale {
-                0 => 0, 63 => 1,
-                255 => 2, 1023 => 3,
-                _ => unreachable!(),
-            }
-        | (self.countup as u16) << 3 | (self.irq as u16) << 6 | (self.start as u16) << 7;
+        (match self.shift {
+            0 => 0, 6 => 1,
+            8 => 2, 10 => 3,
+            _ => unreachable!(),
+        })
+        | (self.countup as u16) << 3 | (self.irq as u16) << 6 | (self.start as u16) << 7
     }
     
-    pub fn write_cnt_l(&mut self, value: u16) {
+    pub fn write_cnt_l(&mut self, value: u16, now: u64) {
+        self.update(now);
+
         // Scale
-        self.scale = match value & 3 {
+        self.shift = match value & 3 {
             0 => 0,
-            1 => 63,
-            2 => 255,
-            3 => 1023,
+            1 => 6,
+            2 => 8,
+            3 => 10,
             _ => unreachable!(),
         };
         
@@ -67,35 +134,6 @@ impl Timer {
         }
         self.start = (value & 0x80) != 0;
     }
-
-    pub fn countup_sig(&mut self) {
-        if self.countup {
-            self.counter = self.counter.wrapping_add(1);
-        }
-    }
-    
-    pub fn spend_cycles(&mut self, mut cycles: u32, irq: &mut IrqManager) {
-        if !self.start || self.countup { return; }
-        
-        while cycles != 0 {
-            cycles -= 1;
-            self.internal = self.internal.wrapping_add(1);
-
-            if (self.internal & self.scale) == 0 {
-                self.counter = self.counter.wrapping_add(1);
-
-                if self.counter == 0 {
-                    if let Some(next) = &self.next {
-                        next.borrow_mut().countup_sig();
-                    }
-                    
-                    if self.irq {
-                        irq.raise_irq(self.irq_n);
-                    }
-                }
-            }
-        }
-    }
 }
 
 pub const TM0CNT_H: usize = 0x04000100;
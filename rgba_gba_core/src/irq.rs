@@ -7,6 +7,7 @@
 //           By: Louise <louise>
 // 
 use crate::cpu::ARM7TDMI;
+use rgba_common::savestate;
 
 #[derive(Debug, Default)]
 pub struct IrqManager {
@@ -14,10 +15,13 @@ pub struct IrqManager {
     pub i_f: u16,
 
     pub halt: bool,
+    pub stop: bool,
     pub ime: bool,
     pub pending: bool,
 }
 
+savestate!(IrqManager, i_e, i_f, halt, stop, ime, pending);
+
 impl IrqManager {
     pub fn new() -> IrqManager {
         Default::default()
@@ -28,6 +32,11 @@ impl IrqManager {
 
         if self.i_f & self.i_e != 0 {
             self.halt = false;
+
+            // Only these can happen while the clocks are stopped
+            if (irq & (IRQ_SERIAL | IRQ_KEYPAD | IRQ_GAMEPAK)) & self.i_e != 0 {
+                self.stop = false;
+            }
             
             if self.ime {
                 self.pending = true;
@@ -61,3 +70,7 @@ impl IrqManager {
 pub const IRQ_VBLANK: u16 = 0x0001;
 pub const IRQ_HBLANK: u16 = 0x0002;
 pub const IRQ_VCOUNT: u16 = 0x0004;
+pub const IRQ_SERIAL: u16 = 0x0080;
+pub const IRQ_DMA:    [u16; 4] = [0x0100, 0x0200, 0x0400, 0x0800];
+pub const IRQ_KEYPAD: u16 = 0x1000;
+pub const IRQ_GAMEPAK: u16 = 0x2000;
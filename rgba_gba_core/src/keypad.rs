@@ -6,6 +6,7 @@
 // Last-Updated: Wed Jan 31 10:46:47 2018 (+0100)
 //           By: Louise <louise>
 // 
+use rgba_common::savestate;
 
 #[derive(Default)]
 pub struct Keypad {
@@ -19,8 +20,15 @@ pub struct Keypad {
     pub down: bool,
     pub r_button: bool,
     pub l_button: bool,
+
+    pub keycnt: u16,
 }
 
+savestate!(Keypad,
+           a_button, b_button, start, select,
+           right, left, up, down, r_button, l_button,
+           keycnt);
+
 impl Keypad {
     #[inline]
     pub fn as_register(&self) -> u16 {
@@ -35,4 +43,23 @@ impl Keypad {
         ((!self.r_button as u16) << 8) |
         ((!self.l_button as u16) << 9)
     }
+
+    #[inline]
+    pub fn set_keycnt(&mut self, value: u16) { self.keycnt = value & 0xC3FF; }
+
+    /// Whether KEYCNT asks for an interrupt with the current keys,
+    /// either when any (OR) or all (AND) of the selected keys are
+    /// pressed.
+    pub fn irq_condition(&self) -> bool {
+        let selected = self.keycnt & 0x3FF;
+        let pressed = !self.as_register() & selected;
+
+        if (self.keycnt & 0x4000) == 0 {
+            false
+        } else if (self.keycnt & 0x8000) != 0 {
+            (selected != 0) && (pressed == selected)
+        } else {
+            pressed != 0
+        }
+    }
 }
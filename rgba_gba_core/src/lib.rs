@@ -10,6 +10,7 @@
 extern crate byteorder;
 extern crate rgba_common;
 use rgba_common::{ConsoleType, Core, Platform, Event, Key};
+use rgba_common::{Savestate, StateWriter, StateReader};
 use rgba_common::fnv_hash;
 
 mod debug;
@@ -19,18 +20,25 @@ mod irq;
 mod keypad;
 mod gpu;
 mod apu;
+mod scheduler;
 
 use crate::cpu::ARM7TDMI;
 use crate::io::Interconnect;
 
-use crate::debug::Debugger;
+use crate::debug::{Debugger, GdbStub};
 
 use std::fs::File;
 use std::io::{Seek, SeekFrom, Read};
 
+const STATE_VERSION: u16 = 8;
+
 pub struct GBA {
     cpu: ARM7TDMI,
     io: Interconnect,
+    debug: Debugger,
+
+    gdb: Option<GdbStub>,
+    fast_mode: bool,
 }
 
 impl GBA {
@@ -38,33 +46,90 @@ impl GBA {
         GBA {
             cpu: ARM7TDMI::new(),
             io: Interconnect::new(),
+            debug: Debugger::new(false),
+
+            gdb: None,
+            fast_mode: false,
         }
     }
+
+    fn reset(&mut self) {
+        self.io.reset();
+
+        if self.io.has_bios() {
+            self.cpu.reset(&mut self.io);
+        } else {
+            self.cpu.skip_bios(&mut self.io);
+        }
+    }
+
+    /// Waits for a GDB connection on the given local port, and
+    /// lets it control the emulation.
+    pub fn listen_gdb(&mut self, port: u16) -> Result<(), &'static str> {
+        self.gdb = Some(GdbStub::listen(port)?);
+
+        Ok(())
+    }
+
+    fn read_state(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let mut state = StateReader::new(data, ConsoleType::GBA, STATE_VERSION)?;
+
+        self.cpu.load(&mut state)?;
+        self.io.load(&mut state)?;
+
+        state.finish()
+    }
 }
 
 impl Core for GBA {
     fn run_frame<T: Platform>(&mut self, platform: &mut T) -> &[u32] {
-        let mut debugger = Debugger::new(false);
+        let mut gdb = self.gdb.take();
+
+        if let Some(ref mut stub) = gdb {
+            stub.poll_interrupt();
+        }
         
         while !self.io.is_frame() {
+            // Nothing runs until a key wakes the console up
+            if self.io.stopped() {
+                break;
+            }
+
             if !self.io.halt() {
-                debugger.handle(self, platform);
+                self.debug.handle(&self.cpu, &self.io, platform);
+
+                if let Some(ref mut stub) = gdb {
+                    if !stub.handle(self) {
+                        gdb = None;
+                    }
+                }
 
                 self.cpu.next_instruction(&mut self.io);
             } else {
-                self.io.delay(25);
+                self.io.skip_to_next_event();
             }
             
             self.io.spend(&mut self.cpu);
-            self.io.render();
+            self.io.render(platform);
         }
 
+        self.gdb = gdb;
+
         self.io.ack_frame();
+        self.io.tick_savefile();
+
         self.io.get_framebuffer()
     }
 
     fn process_event(&mut self, event: Event) {
         match event {
+            Event::Debug => self.debug.trigger(),
+            Event::FastMode => {
+                self.fast_mode = !self.fast_mode;
+                self.io.set_sound_enabled(!self.fast_mode);
+            },
+            Event::Reset => self.reset(),
+
             // Key down
             Event::KeyDown(Key::A) => self.io.keypad.a_button = true,
             Event::KeyDown(Key::B) => self.io.keypad.b_button = true,
@@ -76,6 +141,9 @@ impl Core for GBA {
             Event::KeyDown(Key::Up) => self.io.keypad.up = true,
             Event::KeyDown(Key::Down) => self.io.keypad.down = true,
 
+            Event::KeyDown(Key::L) => self.io.keypad.l_button = true,
+            Event::KeyDown(Key::R) => self.io.keypad.r_button = true,
+
             // Key up
             Event::KeyUp(Key::A) => self.io.keypad.a_button = false,
             Event::KeyUp(Key::B) => self.io.keypad.b_button = false,
@@ -86,8 +154,15 @@ impl Core for GBA {
             Event::KeyUp(Key::Right) => self.io.keypad.right = false,
             Event::KeyUp(Key::Up) => self.io.keypad.up = false,
             Event::KeyUp(Key::Down) => self.io.keypad.down = false,
+
+            Event::KeyUp(Key::L) => self.io.keypad.l_button = false,
+            Event::KeyUp(Key::R) => self.io.keypad.r_button = false,
             _ => (),
         }
+
+        if let Event::KeyDown(_) | Event::KeyUp(_) = event {
+            self.io.check_keypad_irq();
+        }
     }
     
     fn is_file(filename: &str) -> bool {
@@ -120,17 +195,51 @@ impl Core for GBA {
     fn load_bios<T: ToString>(&mut self, filename: Option<T>) -> Result<(), &'static str> {
         match filename {
             Some(f) => self.io.load_bios(&f.to_string()),
-            None => panic!("A BIOS is mandatory to run GBA games."),
+            None => {
+                self.io.load_hle_bios();
+                self.cpu.skip_bios(&mut self.io);
+
+                Ok(())
+            }
         }
     }
     
-    fn load_rom(&mut self, filename: &str) -> bool {
+    fn load_rom(&mut self, filename: &str) -> Result<(), &'static str> {
         self.io.load_rom(filename)
     }
 
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(ConsoleType::GBA, STATE_VERSION);
+
+        self.cpu.save(&mut state);
+        self.io.save(&mut state);
+
+        state.into_inner()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        // Keep the current state around so a bad savestate
+        // doesn't leave the emulator half-loaded
+        let backup = self.save_state();
+
+        if let Err(e) = self.read_state(data) {
+            self.read_state(&backup).expect("Couldn't restore state");
+
+            Err(e)
+        } else {
+            Ok(())
+        }
+    }
+
     fn get_platform_parameters(&self) -> (u32, u32) {
         (240, 160)
     }
     
     fn get_console_type() -> ConsoleType { ConsoleType::GBA }
 }
+
+impl Drop for GBA {
+    fn drop(&mut self) {
+        self.io.flush_savefile();
+    }
+}
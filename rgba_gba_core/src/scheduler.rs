@@ -0,0 +1,48 @@
+// scheduler.rs ---
+//
+// Filename: scheduler.rs
+// Author: Louise <louise>
+// Created: Sun Oct 18 15:02:41 2026 (+0200)
+// Last-Updated: Sun Oct 18 17:20:12 2026 (+0200)
+//           By: Louise <louise>
+//
+use rgba_common::{Savestate, StateWriter, StateReader, ScheduledEvent};
+
+/// Something that has to happen at a given cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventKind {
+    #[default]
+    HBlank,
+    EndLine,
+    TimerOverflow(usize),
+    Dma,
+    ApuSample,
+}
+
+impl Savestate for EventKind {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(match *self {
+            EventKind::HBlank => 0,
+            EventKind::EndLine => 1,
+            EventKind::TimerOverflow(n) => 2 + n as u8,
+            EventKind::Dma => 6,
+            EventKind::ApuSample => 7,
+        });
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), &'static str> {
+        *self = match state.read_u8()? {
+            0 => EventKind::HBlank,
+            1 => EventKind::EndLine,
+            n @ 2..=5 => EventKind::TimerOverflow((n - 2) as usize),
+            6 => EventKind::Dma,
+            7 => EventKind::ApuSample,
+            _ => return Err("Invalid scheduler event"),
+        };
+
+        Ok(())
+    }
+}
+
+pub type Event = ScheduledEvent<EventKind>;
+pub type Scheduler = rgba_common::Scheduler<EventKind>;